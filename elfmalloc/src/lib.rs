@@ -23,6 +23,8 @@ extern crate bsalloc;
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
+extern crate num_cpus;
+extern crate object_alloc;
 
 mod utils;
 #[macro_use]
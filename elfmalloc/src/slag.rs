@@ -63,9 +63,14 @@ use super::bagpipe::bag::{Revocable, WeakBag};
 use super::bagpipe::BagPipe;
 use super::bagpipe::queue::{FAAQueueLowLevel, RevocableFAAQueue};
 use super::utils::{mmap, LazyInitializable, OwnedArray};
+use super::utils::mmap::Reservation;
+use super::alloc::allocator::Layout;
+use super::object_alloc::{Error, ObjectAlloc, SharedObjectAlloc, SharedUntypedObjectAlloc,
+                          UntypedObjectAlloc};
 use std::marker::PhantomData;
 use std::ptr;
 use std::cmp;
+use std::sync::{Mutex, MutexGuard};
 
 #[cfg(feature = "nightly")]
 use std::intrinsics::{likely, unlikely};
@@ -1095,23 +1100,7 @@ impl<CA: CoarseAllocator> LocalCache<CA> {
     }
 }
 
-/// Base address and size of a memory map.
-///
-/// This could also just be a `*mut [u8]`, but having two fields is more explicit. We need a new
-/// type because the `Drop` implementation calls `unmap`.
-#[derive(Debug)]
-struct MapAddr(*mut u8, usize);
-
-impl Drop for MapAddr {
-    fn drop(&mut self) {
-        use self::mmap::unmap;
-        unsafe {
-            unmap(self.0, self.1);
-        }
-    }
-}
-
-/// A large, contiguous, memory-mapped region of memory.
+/// A large, contiguous, reserved region of memory.
 ///
 /// A `Creek` can be seen as a very basic memory allocator that can hand back multiples of its
 /// `page_size`. While it does not implement `free`, the programmer can still call `uncommit` or
@@ -1122,22 +1111,74 @@ impl Drop for MapAddr {
 /// `Creek`s are initialized with a maximum size and never grow beyond that size. They are made
 /// with a particular idiom in mind in which a larger-than-physical-memory mapping is requested for
 /// the `Creek`, only a fraction of which is ever used by the running program (hence, ever backed
-/// by physical page frames).
+/// by physical page frames). The region is reserved without being backed by memory, and pages are
+/// committed in large chunks as they are carved.
 #[derive(Debug)]
 pub struct Creek {
     page_size: usize,
     /// We use a `clone`-based interface in order to facilitate passing across threads and
-    /// leveraging `Arc` to call `unmap`.
-    map_info: Arc<MapAddr>,
+    /// leveraging `Arc` to release the reservation.
+    map_info: Arc<CreekMap>,
     base: *mut u8,
     bump: AtomicPtr<AtomicUsize>,
 }
 
-unsafe impl Send for MapAddr {}
-unsafe impl Sync for MapAddr {}
 unsafe impl Send for Creek {}
 unsafe impl Sync for Creek {}
 
+/// The number of bytes of a `Creek`'s reservation committed at a time.
+///
+/// Committing memory changes its protection, which takes the lock on the process's address space,
+/// so doing it on every `carve` would serialize threads allocating fresh pages. Committed pages
+/// which are never touched don't consume physical memory, so large chunks cost very little.
+const CREEK_COMMIT_CHUNK: usize = 64 << 20;
+
+/// The memory reserved for a `Creek`, along with a high-water mark of how much of it is committed.
+#[derive(Debug)]
+struct CreekMap {
+    reservation: Reservation,
+    /// The number of bytes from the start of the reservation which are committed. Lets `carve`
+    /// skip taking `commit_lock` when its pages are already committed.
+    committed: AtomicUsize,
+    /// Serializes commits. Holds the same value as `committed`.
+    commit_lock: Mutex<usize>,
+}
+
+impl CreekMap {
+    fn new(reservation: Reservation) -> CreekMap {
+        CreekMap {
+            reservation: reservation,
+            committed: AtomicUsize::new(0),
+            commit_lock: Mutex::new(0),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.reservation.size()
+    }
+
+    /// Ensures that everything before `end` is committed.
+    fn commit_to(&self, end: *mut u8) {
+        let end = end as usize - self.reservation.ptr() as usize;
+        if end <= self.committed.load(Ordering::Acquire) {
+            return;
+        }
+        let mut committed = self.commit_lock.lock().unwrap();
+        // another thread may have committed past end while we were waiting for the lock
+        if end <= *committed {
+            return;
+        }
+        let new = cmp::min(cmp::max(end, *committed + CREEK_COMMIT_CHUNK), self.size());
+        unsafe {
+            self.reservation
+                .commit(self.reservation.ptr().offset(*committed as isize),
+                        new - *committed)
+        };
+        *committed = new;
+        self.committed.store(new, Ordering::Release);
+    }
+}
+
 macro_rules! check_bump {
     ($slf:expr) => {
         #[cfg(debug_assertions)]
@@ -1163,9 +1204,12 @@ impl MemoryBlock for Creek {
                 .as_ref()
                 .unwrap()
                 .fetch_add(npages, Ordering::Relaxed);
-            assert!((new_bump + npages) * self.page_size < self.map_info.1,
+            assert!((new_bump + npages) * self.page_size < self.map_info.size(),
                     "address space allocation exceeded");
-            self.base.offset((new_bump * self.page_size) as isize)
+            let pages = self.base.offset((new_bump * self.page_size) as isize);
+            self.map_info
+                .commit_to(pages.offset((npages * self.page_size) as isize));
+            pages
         }
     }
 
@@ -1173,7 +1217,7 @@ impl MemoryBlock for Creek {
         check_bump!(self);
         let it_num = it as usize;
         let base_num = self.base as usize;
-        it_num >= base_num && it_num < base_num + self.map_info.1
+        it_num >= base_num && it_num < base_num + self.map_info.size()
     }
 
     /// Create a new `Creek` with pages of size `page_size` total heap size of `heap_size`,
@@ -1183,12 +1227,12 @@ impl MemoryBlock for Creek {
     /// pages for itself (or for alignment reasons), as a result it is a good idea to have
     /// heap_size be much larger than page_size.
     fn new(page_size: usize) -> Self {
-        use self::mmap::fallible_map;
+        use self::mmap::reserve;
         let get_heap = || {
             let mut heap_size: usize = 2 << 40;
             while heap_size > (1 << 30) {
-                if let Some(heap) = fallible_map(heap_size) {
-                    return (heap, heap_size);
+                if let Some(heap) = reserve(heap_size) {
+                    return heap;
                 }
                 heap_size /= 2;
             }
@@ -1198,9 +1242,9 @@ impl MemoryBlock for Creek {
         assert!(page_size.is_power_of_two());
         assert!(page_size > mem::size_of::<usize>());
         // first, let's grab some memory;
-        let (orig_base, heap_size) = get_heap();
-        info!("created heap of size {}", heap_size);
-        let orig_addr = orig_base as usize;
+        let heap = get_heap();
+        info!("created heap of size {}", heap.size());
+        let orig_addr = heap.ptr() as usize;
         let (slush_addr, real_addr) = {
             // allocate some `slush` space at the beginning of the creek. This gives us space to
             // store the `bump` pointer. In the future, we may store more things in this slush
@@ -1209,7 +1253,7 @@ impl MemoryBlock for Creek {
             // In addition, we must ensure that pages are aligned to their size.
             let base = if orig_addr == 0 {
                 // this is a real possibility if we are calling `mmap` directly.
-                // However, `MmapAlloc` currently handles `mmap` returning null, so this is
+                // However, `Reservation` currently handles `mmap` returning null, so this is
                 // technically a redundant check.
                 orig_addr + page_size
             } else if orig_addr % page_size != 0 {
@@ -1220,9 +1264,11 @@ impl MemoryBlock for Creek {
             };
             (base as *mut u8, (base + page_size) as *mut u8)
         };
+        let map_info = CreekMap::new(heap);
+        map_info.commit_to(unsafe { slush_addr.offset(mem::size_of::<AtomicUsize>() as isize) });
         Creek {
             page_size: page_size,
-            map_info: Arc::new(MapAddr(orig_base, heap_size)),
+            map_info: Arc::new(map_info),
             base: real_addr,
             bump: AtomicPtr::new(slush_addr as *mut AtomicUsize),
         }
@@ -1323,7 +1369,13 @@ impl<C: MemoryBlock, D: DirtyFn> CoarseAllocator for PageAlloc<C, D> {
 
     unsafe fn free(&mut self, ptr: *mut u8, decommit: bool) {
         const MINOR_PAGE_SIZE: isize = 4096;
-        use self::mmap::uncommit;
+        use self::mmap::{uncommit, DecommitStrategy};
+        // With lazy_uncommit, dirty pages are only reclaimed by the kernel under memory pressure,
+        // which makes freeing them (and reusing them before they are reclaimed) cheaper.
+        #[cfg(feature = "lazy_uncommit")]
+        const UNCOMMIT_STRATEGY: DecommitStrategy = DecommitStrategy::Free;
+        #[cfg(not(feature = "lazy_uncommit"))]
+        const UNCOMMIT_STRATEGY: DecommitStrategy = DecommitStrategy::DontNeed;
         use std::cmp;
         if decommit || self.dirty.size_guess() >= self.target_overhead as isize {
             let uncommit_len = cmp::max(0,
@@ -1332,7 +1384,7 @@ impl<C: MemoryBlock, D: DirtyFn> CoarseAllocator for PageAlloc<C, D> {
             if uncommit_len == 0 {
                 self.dirty.push_mut(ptr);
             } else {
-                uncommit(ptr.offset(MINOR_PAGE_SIZE), uncommit_len);
+                uncommit(ptr.offset(MINOR_PAGE_SIZE), uncommit_len, UNCOMMIT_STRATEGY);
                 self.dirty.push_mut(ptr);
             }
         } else {
@@ -1397,31 +1449,75 @@ impl PtrStack {
 /// let la: LocalAllocator<usize> = AllocBuilder::default().page_size(32 << 10).build_local();
 /// ```
 ///
+/// By default, allocators built by an `AllocBuilder` return uninitialized objects from their
+/// `alloc` methods. Allocators built from an `AllocBuilder` constructed with `init_default` or
+/// `func` initialize their objects, and implement `object-alloc`'s `ObjectAlloc` trait.
+///
 /// Modifying other builder parameters past the default is not recommended. The overall API is
 /// unstable.
-pub struct AllocBuilder<T> {
+pub struct AllocBuilder<T, I = Uninit> {
     cutoff_factor: f64,
     page_size: usize,
     target_overhead: usize,
     eager_decommit_threshold: usize,
     max_objects: usize,
+    init: I,
     _marker: PhantomData<T>,
 }
 
-impl<T> Default for AllocBuilder<T> {
-    fn default() -> Self {
+impl<T, I> AllocBuilder<T, I> {
+    fn with_init(init: I) -> Self {
         AllocBuilder {
             cutoff_factor: 0.6,
             page_size: cmp::max(32 << 10, mem::size_of::<T>() * 4),
             target_overhead: 1 << 20,
             eager_decommit_threshold: 128 << 10,
             max_objects: 1 << 30,
+            init: init,
             _marker: PhantomData,
         }
     }
 }
 
-impl<T> AllocBuilder<T> {
+impl<T> Default for AllocBuilder<T> {
+    fn default() -> Self {
+        AllocBuilder::with_init(Uninit)
+    }
+}
+
+impl<T: Default> AllocBuilder<T, DefaultInitializer> {
+    /// Constructs a new builder for allocators which use `T::default` to initialize objects.
+    pub fn init_default() -> Self {
+        AllocBuilder::with_init(DefaultInitializer)
+    }
+}
+
+impl<T, F: Fn() -> T + Clone> AllocBuilder<T, FnInitializer<F>> {
+    /// Constructs a new builder for allocators which use `f` to initialize objects.
+    ///
+    /// The constructed allocators will call `f` whenever a new object is allocated.
+    pub fn func(f: F) -> Self {
+        AllocBuilder::with_init(FnInitializer(f))
+    }
+}
+
+impl<T> AllocBuilder<T, NoInitializer> {
+    /// Constructs a new builder for allocators which do not initialize objects.
+    ///
+    /// Unlike allocators built from `AllocBuilder::default`, the constructed allocators implement
+    /// `ObjectAlloc`, but objects returned by their `alloc` methods are not guaranteed to be valid
+    /// instances of `T`, and objects are not dropped when they are deallocated.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the constructed allocators violate the initialization
+    /// guarantees of the `ObjectAlloc` trait.
+    pub unsafe fn no_initialize() -> Self {
+        AllocBuilder::with_init(NoInitializer(()))
+    }
+}
+
+impl<T, I: Clone> AllocBuilder<T, I> {
     pub fn cutoff_factor(&mut self, cutoff_factor: f64) -> &mut Self {
         self.cutoff_factor = cutoff_factor;
         self
@@ -1443,31 +1539,127 @@ impl<T> AllocBuilder<T> {
         self
     }
 
+    fn slag_allocator(&self, layout: &Layout) -> SlagAllocator<PageAlloc<Creek>> {
+        assert_supported_layout(layout, self.page_size);
+        let pa = PageAlloc::new(self.page_size, self.target_overhead);
+        SlagAllocator::new(self.max_objects,
+                           layout.size(),
+                           0,
+                           self.cutoff_factor,
+                           self.eager_decommit_threshold,
+                           pa)
+    }
+
     /// Build a `LocalAllocator<T>` from the current configuration.
-    pub fn build_local(&self) -> LocalAllocator<T> {
-        LocalAllocator::new_standalone(self.cutoff_factor,
-                                       self.page_size,
-                                       self.target_overhead,
-                                       self.eager_decommit_threshold,
-                                       self.max_objects)
+    pub fn build_local(&self) -> LocalAllocator<T, I> {
+        let slag = self.slag_allocator(&Layout::new::<T>());
+        LocalAllocator(LocalCache::new(slag), self.init.clone(), PhantomData)
     }
 
     /// Build a `MagazineAllocator<T>` from the current configuration.
-    pub fn build_magazine(&self) -> MagazineAllocator<T> {
-        MagazineAllocator::new_standalone(self.cutoff_factor,
-                                          self.page_size,
-                                          self.target_overhead,
-                                          self.eager_decommit_threshold,
-                                          self.max_objects)
+    pub fn build_magazine(&self) -> MagazineAllocator<T, I> {
+        let slag = self.slag_allocator(&Layout::new::<T>());
+        MagazineAllocator(MagazineCache::new(slag), self.init.clone(), PhantomData)
+    }
+
+    /// Build an `UntypedLocalAllocator` from the current configuration.
+    ///
+    /// The allocator's type parameter and initializer are ignored, and the constructed allocator
+    /// allocates uninitialized objects described by `layout`. Since the default page size is
+    /// computed from the size of `T`, the page size should be configured explicitly for large
+    /// layouts.
+    pub fn build_untyped_local(&self, layout: Layout) -> UntypedLocalAllocator {
+        UntypedLocalAllocator(LocalCache::new(self.slag_allocator(&layout)), layout)
+    }
+
+    /// Build an `UntypedMagazineAllocator` from the current configuration.
+    ///
+    /// See `build_untyped_local` for details.
+    pub fn build_untyped_magazine(&self, layout: Layout) -> UntypedMagazineAllocator {
+        UntypedMagazineAllocator(MagazineCache::new(self.slag_allocator(&layout)), layout)
+    }
+
+    /// Build a `SharedMagazineAllocator` from the current configuration.
+    ///
+    /// The resulting allocator has one `MagazineAllocator` per CPU.
+    pub fn build_shared(&self) -> SharedMagazineAllocator<T, I> {
+        SharedMagazineAllocator::new(self.build_magazine(), num_cpus::get())
+    }
+}
+
+/// Check that `layout`'s alignment is guaranteed for objects allocated from a `Slag`.
+///
+/// Objects are laid out contiguously following the `Slag` header and bit-set, both of which are
+/// word-aligned. Thus, objects are guaranteed to be aligned to the smaller of the word size and
+/// their own alignment. Power-of-two sizes are additionally padded so that objects are aligned to
+/// their size. Layouts with larger alignments than this are not supported.
+fn assert_supported_layout(layout: &Layout, page_size: usize) {
+    let (size, align) = (layout.size(), layout.align());
+    assert!(align <= mem::size_of::<usize>() || (size.is_power_of_two() && size <= page_size),
+            "unsupported alignment {} for object size {}",
+            align,
+            size);
+}
+
+/// An initialization strategy for objects allocated by `LocalAllocator`s and
+/// `MagazineAllocator`s.
+///
+/// Objects are initialized when they are allocated and dropped when they are freed, so freed
+/// objects are never cached in an initialized state.
+pub unsafe trait Initializer<T>: Clone {
+    /// Initialize a newly-allocated object.
+    unsafe fn init(&self, obj: *mut T);
+
+    /// Drop an object which is about to be freed.
+    unsafe fn drop(&self, obj: *mut T) {
+        ptr::drop_in_place(obj)
+    }
+}
+
+/// The initializer used by allocators built from `AllocBuilder::default`.
+///
+/// `Uninit` does not implement `Initializer`, so allocators using it do not implement
+/// `ObjectAlloc`.
+#[derive(Copy, Clone, Debug)]
+pub struct Uninit;
+
+/// An `Initializer` which initializes objects using `T::default`.
+#[derive(Copy, Clone, Debug)]
+pub struct DefaultInitializer;
+
+unsafe impl<T: Default> Initializer<T> for DefaultInitializer {
+    unsafe fn init(&self, obj: *mut T) {
+        ptr::write(obj, T::default())
+    }
+}
+
+/// An `Initializer` which initializes objects using a function.
+#[derive(Copy, Clone)]
+pub struct FnInitializer<F>(F);
+
+unsafe impl<T, F: Fn() -> T + Clone> Initializer<T> for FnInitializer<F> {
+    unsafe fn init(&self, obj: *mut T) {
+        ptr::write(obj, (self.0)())
     }
 }
 
+/// An `Initializer` which neither initializes nor drops objects.
+///
+/// A `NoInitializer` can only be obtained using the `unsafe` `AllocBuilder::no_initialize`.
+#[derive(Copy, Clone, Debug)]
+pub struct NoInitializer(());
+
+unsafe impl<T> Initializer<T> for NoInitializer {
+    unsafe fn init(&self, _obj: *mut T) {}
+    unsafe fn drop(&self, _obj: *mut T) {}
+}
+
 macro_rules! typed_wrapper {
-    ($name:ident, $wrapped:tt) => {
-        pub struct $name<T>($wrapped<PageAlloc<Creek>>, PhantomData<T>);
-        impl<T> Clone for $name<T> {
+    ($name:ident, $wrapped:tt, $build:ident) => {
+        pub struct $name<T, I = Uninit>($wrapped<PageAlloc<Creek>>, I, PhantomData<T>);
+        impl<T, I: Clone> Clone for $name<T, I> {
             fn clone(&self) -> Self {
-                $name(self.0.clone(), PhantomData)
+                $name(self.0.clone(), self.1.clone(), PhantomData)
             }
         }
 
@@ -1478,26 +1670,151 @@ macro_rules! typed_wrapper {
                                   eager_decommit: usize,
                                   max_objects: usize)
                 -> Self {
-                    let pa = PageAlloc::new(page_size, target_overhead);
-                    let slag = SlagAllocator::new(max_objects, mem::size_of::<T>(), 0,
-                                                  cutoff_factor, eager_decommit, pa);
-                    $name($wrapped::new(slag), PhantomData)
+                    AllocBuilder::<T>::default()
+                        .cutoff_factor(cutoff_factor)
+                        .page_size(page_size)
+                        .target_overhead(target_overhead)
+                        .eager_decommit_threshold(eager_decommit)
+                        .max_objects(max_objects)
+                        .$build()
                 }
+        }
 
+        impl<T, I> $name<T, I> {
+            /// Allocate an object.
+            ///
+            /// The returned object is uninitialized regardless of this allocator's initializer.
             pub unsafe fn alloc(&mut self) -> *mut T {
                 self.0.alloc() as *mut T
             }
 
+            /// Free an object.
+            ///
+            /// The object is not dropped regardless of this allocator's initializer.
             pub unsafe fn free(&mut self, item: *mut T) {
                 self.0.free(item as *mut u8)
             }
         }
-        unsafe impl<T> Send for $name<T> {}
+        unsafe impl<T, I: Send> Send for $name<T, I> {}
+
+        unsafe impl<T, I: Initializer<T>> ObjectAlloc<T> for $name<T, I> {
+            unsafe fn alloc(&mut self) -> Result<*mut T, Error> {
+                let obj = self.0.alloc() as *mut T;
+                self.1.init(obj);
+                Ok(obj)
+            }
+
+            unsafe fn dealloc(&mut self, x: *mut T) {
+                self.1.drop(x);
+                self.0.free(x as *mut u8)
+            }
+        }
     };
 }
 
-typed_wrapper!(LocalAllocator, LocalCache);
-typed_wrapper!(MagazineAllocator, MagazineCache);
+typed_wrapper!(LocalAllocator, LocalCache, build_local);
+typed_wrapper!(MagazineAllocator, MagazineCache, build_magazine);
+
+macro_rules! untyped_wrapper {
+    ($name:ident, $wrapped:tt) => {
+        pub struct $name($wrapped<PageAlloc<Creek>>, Layout);
+        impl Clone for $name {
+            fn clone(&self) -> Self {
+                $name(self.0.clone(), self.1.clone())
+            }
+        }
+        unsafe impl Send for $name {}
+
+        unsafe impl UntypedObjectAlloc for $name {
+            fn layout(&self) -> Layout {
+                self.1.clone()
+            }
+
+            unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
+                Ok(self.0.alloc())
+            }
+
+            unsafe fn dealloc(&mut self, x: *mut u8) {
+                self.0.free(x)
+            }
+        }
+    };
+}
+
+untyped_wrapper!(UntypedLocalAllocator, LocalCache);
+untyped_wrapper!(UntypedMagazineAllocator, MagazineCache);
+
+/// A `MagazineAllocator` that can be shared between threads.
+///
+/// `MagazineAllocator`s are designed to be cloned once per thread, which is inconvenient when a
+/// single allocator needs to be shared by reference (e.g., as a `SharedUntypedObjectAlloc`). A
+/// `SharedMagazineAllocator` is a sharded-mutex allocator: it holds a fixed number of clones of a
+/// `MagazineAllocator`, each behind its own lock, and assigns each thread to one of them in a
+/// round-robin fashion. Threads are numbered by a process-wide counter, and numbers are not reused
+/// when threads exit, so two live threads may be assigned the same clone even if there are fewer
+/// threads than clones. In that case, they contend for its lock.
+///
+/// Since all of the clones share the same size class, objects may be freed from any thread,
+/// regardless of which thread allocated them.
+///
+/// As with `MagazineAllocator`, the typed `SharedObjectAlloc` interface is only implemented if the
+/// allocator has an `Initializer`.
+pub struct SharedMagazineAllocator<T, I = Uninit> {
+    allocs: Vec<Mutex<MagazineAllocator<T, I>>>,
+}
+
+impl<T, I: Clone> SharedMagazineAllocator<T, I> {
+    /// Create a new `SharedMagazineAllocator` from `n` clones of `alloc`.
+    pub fn new(alloc: MagazineAllocator<T, I>, n: usize) -> Self {
+        assert!(n > 0);
+        let mut allocs = Vec::with_capacity(n);
+        for _ in 1..n {
+            allocs.push(Mutex::new(alloc.clone()));
+        }
+        allocs.push(Mutex::new(alloc));
+        SharedMagazineAllocator { allocs: allocs }
+    }
+}
+
+impl<T, I> SharedMagazineAllocator<T, I> {
+    /// Get the `MagazineAllocator` assigned to the current thread.
+    fn local(&self) -> MutexGuard<MagazineAllocator<T, I>> {
+        use std::sync::atomic::ATOMIC_USIZE_INIT;
+        static NEXT_INDEX: AtomicUsize = ATOMIC_USIZE_INIT;
+        thread_local! {
+            static INDEX: usize = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        }
+        let idx = INDEX.with(|idx| *idx) % self.allocs.len();
+        // A poisoned lock can only result from a panic in MagazineAllocator itself, in which case
+        // its state is unknown and continuing would be unsound.
+        self.allocs[idx].lock().unwrap()
+    }
+}
+
+unsafe impl<T, I: Send> SharedUntypedObjectAlloc for SharedMagazineAllocator<T, I> {
+    fn layout(&self) -> Layout {
+        // NOTE: This is valid because AllocBuilder checks that T's alignment is supported.
+        Layout::new::<T>()
+    }
+
+    unsafe fn alloc(&self) -> Result<*mut u8, Error> {
+        Ok(self.local().alloc() as *mut u8)
+    }
+
+    unsafe fn dealloc(&self, x: *mut u8) {
+        self.local().free(x as *mut T)
+    }
+}
+
+unsafe impl<T, I: Initializer<T> + Send> SharedObjectAlloc<T> for SharedMagazineAllocator<T, I> {
+    unsafe fn alloc(&self) -> Result<*mut T, Error> {
+        ObjectAlloc::alloc(&mut *self.local())
+    }
+
+    unsafe fn dealloc(&self, x: *mut T) {
+        ObjectAlloc::dealloc(&mut *self.local(), x)
+    }
+}
 
 /// Allocator state wrapping a `Slag`.
 ///
@@ -1738,6 +2055,7 @@ impl<CA: CoarseAllocator> Clone for SlagAllocator<CA> {
 #[cfg(test)]
 mod tests {
     extern crate env_logger;
+    extern crate object_alloc_test;
     use super::*;
     use std::thread;
     use std::ptr::write_volatile;
@@ -1882,4 +2200,139 @@ mod tests {
         }
     }
 
+    fn test_memory_corruption<T: Copy + Send + 'static>() {
+        use self::object_alloc_test::corruption::{CorruptionTesterDefault, TestBuilder};
+        let new_local = || {
+            AllocBuilder::<CorruptionTesterDefault<T>, _>::init_default()
+                .page_size(4096)
+                .build_local()
+        };
+        TestBuilder::new(new_local).test();
+        let new_magazine = || {
+            AllocBuilder::<CorruptionTesterDefault<T>, _>::init_default()
+                .page_size(4096)
+                .build_magazine()
+        };
+        TestBuilder::new(new_magazine).test();
+    }
+
+    #[test]
+    fn memory_corruption_16_byte() {
+        test_memory_corruption::<[u8; 16]>();
+    }
+
+    #[test]
+    fn memory_corruption_24_byte() {
+        test_memory_corruption::<[u8; 24]>();
+    }
+
+    #[test]
+    fn memory_corruption_100_byte() {
+        test_memory_corruption::<[u8; 100]>();
+    }
+
+    #[test]
+    fn memory_corruption_256_byte() {
+        test_memory_corruption::<[u8; 256]>();
+    }
+
+    #[test]
+    fn untyped_alloc() {
+        let _ = env_logger::init();
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let mut oa = AllocBuilder::<u8>::default()
+            .page_size(4096)
+            .build_untyped_local(layout.clone());
+        assert_eq!(oa.layout(), layout);
+        let mut h = HashSet::new();
+        for _ in 0..4096 {
+            unsafe {
+                let item = UntypedObjectAlloc::alloc(&mut oa).unwrap();
+                assert_eq!(item as usize % 8, 0);
+                write_volatile(item as *mut usize, 10);
+                assert!(h.insert(item as usize));
+            }
+        }
+        for item in h {
+            unsafe {
+                UntypedObjectAlloc::dealloc(&mut oa, item as *mut u8);
+            }
+        }
+    }
+
+    #[test]
+    fn shared_alloc_many_threads() {
+        let _ = env_logger::init();
+        use std::sync::Arc;
+        use std::sync::mpsc::channel;
+        const N_ITEMS: usize = 4096 * 4;
+        const N_THREADS: usize = 8;
+        let oa = Arc::new(AllocBuilder::<usize>::default()
+                              .page_size(4096)
+                              .build_shared());
+        // Allocate from many threads and free everything from the main thread to exercise
+        // frees to a different MagazineAllocator than the one that performed the allocation.
+        let (send, recv) = channel();
+        let mut threads = Vec::new();
+        for _ in 0..N_THREADS {
+            let my_alloc = oa.clone();
+            let send = send.clone();
+            threads.push(thread::spawn(move || for i in 0..N_ITEMS {
+                                           unsafe {
+                                               let item =
+                                                   SharedUntypedObjectAlloc::alloc(&*my_alloc)
+                                                       .unwrap();
+                                               write_volatile(item as *mut usize, i);
+                                               send.send(item as usize).unwrap();
+                                           }
+                                       }));
+        }
+        drop(send);
+        for t in threads {
+            t.join().expect("threads should exit successfully");
+        }
+
+        let mut h = HashSet::new();
+        for item in recv.iter() {
+            assert!(h.insert(item));
+        }
+        assert_eq!(h.len(), N_ITEMS * N_THREADS);
+        for item in h {
+            unsafe {
+                SharedUntypedObjectAlloc::dealloc(&*oa, item as *mut u8);
+            }
+        }
+    }
+
+    #[test]
+    fn shared_typed_alloc() {
+        let _ = env_logger::init();
+        // Check that SharedMagazineAllocator can be used through the typed SharedObjectAlloc
+        // interface.
+        fn alloc_many<A: SharedObjectAlloc<usize>>(alloc: &A) {
+            let mut items = Vec::new();
+            for i in 0..1024 {
+                unsafe {
+                    let item = alloc.alloc().unwrap();
+                    write_volatile(item, i);
+                    items.push(item);
+                }
+            }
+            for (i, &item) in items.iter().enumerate() {
+                unsafe {
+                    assert_eq!(*item, i);
+                    alloc.dealloc(item);
+                }
+            }
+            // objects are initialized with usize::default
+            unsafe {
+                let item = alloc.alloc().unwrap();
+                assert_eq!(*item, 0);
+                alloc.dealloc(item);
+            }
+        }
+        alloc_many(&AllocBuilder::<usize>::init_default()
+                        .page_size(4096)
+                        .build_shared());
+    }
 }
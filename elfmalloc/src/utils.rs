@@ -13,6 +13,7 @@ pub mod mmap {
     extern crate mmap_alloc;
     use self::mmap_alloc::MapAllocBuilder;
     use super::super::alloc::allocator::{Alloc, Layout};
+    pub use self::mmap_alloc::{DecommitStrategy, Reservation};
 
     pub fn map(size: usize) -> *mut u8 {
         fallible_map(size).expect("mmap should not fail")
@@ -31,15 +32,20 @@ pub mod mmap {
         }
     }
 
+    pub fn reserve(size: usize) -> Option<Reservation> {
+        MapAllocBuilder::default().exec().reserve(size)
+    }
+
     pub unsafe fn unmap(p: *mut u8, len: usize) {
         MapAllocBuilder::default()
             .exec()
             .build()
             .dealloc(p, Layout::from_size_align(len, 1).unwrap())
     }
-    pub unsafe fn uncommit(p: *mut u8, len: usize) {
+    pub unsafe fn uncommit(p: *mut u8, len: usize, strategy: DecommitStrategy) {
         MapAllocBuilder::default()
             .exec()
+            .decommit_strategy(strategy)
             .build()
             .uncommit(p, Layout::from_size_align(len, 1).unwrap())
     }
@@ -34,8 +34,12 @@ extern crate kernel32;
 extern crate winapi;
 
 use self::alloc::allocator::{Alloc, Layout, Excess, AllocErr};
-use self::object_alloc::{Exhausted, UntypedObjectAlloc};
+use self::object_alloc::{AllocatorStats, Error, SharedUntypedObjectAlloc, Stats,
+                         UntypedObjectAlloc};
 use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use errno::errno;
@@ -76,7 +80,47 @@ use errno::errno;
 ///
 /// In order to avoid this scenario, it is often necessary to explicitly flush the instruction
 /// cache before executing newly-written memory. The mechanism to accomplish this differs by
-/// system.
+/// system. On Linux and Mac, `flush_icache` does so for the current CPU architecture.
+///
+/// Rather than mapping memory as both writable and executable, code can be written to writable
+/// memory, which is then made executable (and no longer writable) using `MapAlloc::protect`.
+///
+/// # Guard Pages
+///
+/// On Linux and Mac, each allocation can be surrounded by inaccessible guard pages (see
+/// `guard_pages`), so that accesses which run off either end of an object crash the program
+/// rather than silently corrupting other memory. This is useful for thread stacks, and, together
+/// with `align_end`, for debugging buffer overruns in the style of Electric Fence. Guard pages are
+/// mapped along with each object, and are unmapped when it is deallocated. They count towards the
+/// memory reported as mapped by `stats`, but are never committed.
+///
+/// # Shared and File-Backed Memory
+///
+/// By default, memory is private to the process which allocated it (`MAP_PRIVATE`). On Linux,
+/// memory can instead be shared with other processes in one of three ways:
+///
+/// - `shared` maps anonymous memory with `MAP_SHARED`, so that it is shared with children created
+///   by `fork` after the allocation is made.
+/// - `memfd` creates an anonymous in-memory file (see `memfd_create(2)`) of a fixed capacity, out
+///   of which objects are allocated. Other processes can map the file using the descriptor
+///   returned by `MapAlloc::fd`, and find an object in it using `MapAlloc::file_offset`.
+/// - `file` allocates objects out of an existing file, which is mapped in its entirety.
+///
+/// In the latter two modes, the file is mapped once when the `MapAlloc` is built and unmapped
+/// when it is dropped, and objects are allocated by handing out successive ranges of the file.
+/// The space occupied by a deallocated object is never reused; once the end of the file is
+/// reached, allocation fails. Deallocated objects are made inaccessible, and their memory is
+/// released to the operating system if the file was created by `memfd`. Objects allocated from an
+/// existing file contain the file's previous contents rather than zeroes (except when allocated
+/// using `alloc_zeroed`), and writes to them are written back to the file.
+///
+/// Huge pages and the "commit" option are not supported in the latter two modes.
+///
+/// # Decommitting Memory
+///
+/// On Linux and Mac, `MapAlloc::uncommit` releases the physical memory backing an object using
+/// the configured `DecommitStrategy` (see `decommit_strategy`). The default is
+/// `DecommitStrategy::DontNeed` on Linux and `DecommitStrategy::Free` on Mac.
 pub struct MapAllocBuilder {
     read: bool,
     write: bool,
@@ -86,10 +130,101 @@ pub struct MapAllocBuilder {
     pagesize: usize,
     huge_pagesize: Option<usize>,
     obj_size: Option<usize>,
+    // Only supported on Linux and Mac
+    guard_before: usize,
+    guard_after: usize,
+    align_end: bool,
+    #[cfg(target_os = "linux")]
+    backing: Backing,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    decommit: DecommitStrategy,
+}
+
+/// A mechanism for releasing the physical memory backing a range of pages.
+///
+/// Strategies differ in how quickly memory is released and in what the pages contain when they
+/// are next accessed. If the kernel rejects a strategy's advice value, either because it is too
+/// old to support it or because the mapping doesn't support it, `DontNeed` is used instead.
+///
+/// # Platform-specific behavior
+///
+/// `DecommitStrategy` is only supported on Linux and Mac.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecommitStrategy {
+    /// Use `MADV_DONTNEED`.
+    ///
+    /// On Linux, the memory of private mappings is released immediately, and the pages are
+    /// zero-filled when they are next accessed. On Mac, the memory is released lazily.
+    DontNeed,
+    /// Use `MADV_FREE`.
+    ///
+    /// The memory is only released once the kernel needs it, which makes decommitting cheaper,
+    /// and makes accessing the pages again cheaper if it hasn't been released by then. The pages
+    /// may retain their contents. `MADV_FREE` requires Linux 4.5 or later, and is only supported
+    /// for private mappings.
+    Free,
+    /// Use `MADV_REMOVE`.
+    ///
+    /// The memory and any backing store of shared mappings (including those created with
+    /// `MapAllocBuilder::shared` or `MapAllocBuilder::memfd`) are released immediately, and the
+    /// pages are zero-filled when they are next accessed. Private mappings don't support
+    /// `MADV_REMOVE`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `Remove` is only supported on Linux.
+    #[cfg(target_os = "linux")]
+    Remove,
+    /// Replace the pages with a new mapping.
+    ///
+    /// The memory is released immediately, and the pages are zero-filled when they are next
+    /// accessed. This is more expensive than the other strategies, but works on every kernel. It
+    /// is only supported for private mappings without huge pages.
+    ///
+    /// The new mapping has the permissions configured on the `MapAllocBuilder`, so permissions
+    /// set using `MapAlloc::protect` are lost. As with the other strategies, the pages are left
+    /// uncommitted even if the allocator was configured with `MapAllocBuilder::commit`.
+    Remap,
 }
 
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl Default for DecommitStrategy {
+    #[cfg(target_os = "linux")]
+    fn default() -> DecommitStrategy {
+        DecommitStrategy::DontNeed
+    }
+
+    #[cfg(target_os = "macos")]
+    fn default() -> DecommitStrategy {
+        DecommitStrategy::Free
+    }
+}
+
+/// The memory backing the mappings of a `MapAllocBuilder`.
+#[cfg(target_os = "linux")]
+#[derive(Copy, Clone)]
+enum Backing {
+    Private,
+    Shared,
+    // a new memfd of the given capacity
+    Memfd(usize),
+    File(RawFd),
+}
+
+/// A raw file descriptor.
+#[cfg(target_os = "linux")]
+pub type RawFd = libc::c_int;
+
 impl MapAllocBuilder {
     pub fn build(&self) -> MapAlloc {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            if self.decommit == DecommitStrategy::Remap {
+                assert!(self.huge_pagesize.is_none(),
+                        "the Remap decommit strategy is not supported with huge pages");
+            }
+        }
         #[cfg(target_os = "linux")]
         {
             if let Some(huge) = self.huge_pagesize {
@@ -98,6 +233,28 @@ impl MapAllocBuilder {
                         huge);
             }
         }
+        #[cfg(target_os = "linux")]
+        {
+            let private = match self.backing {
+                Backing::Private => true,
+                _ => false,
+            };
+            if self.decommit == DecommitStrategy::Remap {
+                assert!(private,
+                        "the Remap decommit strategy is only supported for private mappings");
+            }
+            if self.decommit == DecommitStrategy::Free {
+                assert!(private,
+                        "the Free decommit strategy is only supported for private mappings");
+            }
+        }
+        #[cfg(target_os = "linux")]
+        let backing = match self.backing {
+            Backing::Private => MapBacking::Private,
+            Backing::Shared => MapBacking::Shared,
+            Backing::Memfd(size) => unsafe { self.map_file(memfd_create(size), true, size) },
+            Backing::File(fd) => unsafe { self.map_file(fd, false, file_size(fd)) },
+        };
 
         let obj_size = if let Some(obj_size) = self.obj_size {
             assert_eq!(obj_size % self.pagesize,
@@ -115,6 +272,59 @@ impl MapAllocBuilder {
             perms: perms::get_perm(self.read, self.write, self.exec),
             commit: self.commit,
             obj_size: obj_size,
+            guard_before: self.guard_before,
+            guard_after: self.guard_after,
+            align_end: self.align_end,
+            #[cfg(target_os = "linux")]
+            backing: backing,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            decommit: self.decommit,
+            bytes_mapped: AtomicUsize::new(0),
+            live_objects: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserves a range of address space.
+    ///
+    /// `reserve` reserves `size` bytes of address space (rounded up to a multiple of the page
+    /// size) which is initially inaccessible, and which is not backed by any memory until parts of
+    /// it are committed. Only the permissions configured on the builder are used; they are the
+    /// permissions of committed memory. `reserve` returns `None` if there is not enough free
+    /// address space.
+    ///
+    /// See the `Reservation` documentation for more details.
+    pub fn reserve(&self, size: usize) -> Option<Reservation> {
+        let perms = perms::get_perm(self.read, self.write, self.exec);
+        unsafe { Reservation::map(next_multiple(size, self.pagesize), self.pagesize, perms) }
+    }
+
+    // map_file maps the first len bytes of the file fd (rounded down to a multiple of the page
+    // size) for use by a MapAlloc. If owned is true, the MapAlloc closes fd when it is dropped.
+    #[cfg(target_os = "linux")]
+    unsafe fn map_file(&self, fd: RawFd, owned: bool, len: usize) -> MapBacking {
+        use libc::MAP_SHARED;
+
+        assert!(self.huge_pagesize.is_none(),
+                "huge pages are not supported for file-backed mappings");
+        assert!(!self.commit,
+                "the commit option is not supported for file-backed mappings");
+        let len = len & !(self.pagesize - 1);
+        let base = if len == 0 {
+            // mmap doesn't allow empty mappings; every allocation will fail
+            0
+        } else {
+            let perms = perms::get_perm(self.read, self.write, self.exec);
+            match map_with(len, perms, false, None, MAP_SHARED, fd, 0) {
+                Some(ptr) => ptr as usize,
+                None => panic!("out of memory mapping file of {} bytes", len),
+            }
+        };
+        MapBacking::File {
+            fd: fd,
+            owned: owned,
+            base: base,
+            len: len,
+            next: AtomicUsize::new(0),
         }
     }
 
@@ -235,6 +445,126 @@ impl MapAllocBuilder {
         self.obj_size = Some(obj_size);
         self
     }
+
+    /// Surrounds each allocation with guard pages.
+    ///
+    /// `guard_pages` makes it so that each allocated object is preceded by `before` and followed
+    /// by `after` inaccessible pages, any access to which will crash the program. The default is
+    /// to have no guard pages. If a huge page size is configured, guard pages are huge pages.
+    ///
+    /// See the "Guard Pages" section of the `MapAllocBuilder` documentation for more details.
+    ///
+    /// # Panics
+    ///
+    /// Each object's guard pages split its mapping in up to three, so allocating can exhaust the
+    /// kernel's limit on the number of mappings per process (`vm.max_map_count` on Linux).
+    /// Allocation panics if the guard pages cannot be made inaccessible.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `guard_pages` is only supported on Linux and Mac.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn guard_pages(mut self, before: usize, after: usize) -> MapAllocBuilder {
+        self.guard_before = before;
+        self.guard_after = after;
+        self
+    }
+
+    /// Aligns allocations to the end of their pages rather than the beginning.
+    ///
+    /// `align_end` makes it so that each object returned by the `Alloc` implementation is placed
+    /// as close to the end of its last page as its alignment allows, rather than at the beginning
+    /// of its first page. Combined with trailing guard pages (see `guard_pages`), this makes it so
+    /// that reading or writing past the end of an object crashes the program immediately. Objects
+    /// whose size is a multiple of their alignment end exactly at the guard page. The default is
+    /// to place objects at the beginning of their first page.
+    ///
+    /// Objects allocated through the `UntypedObjectAlloc` implementation are always a whole number
+    /// of pages, so they are unaffected.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `align_end` is only supported on Linux and Mac.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn align_end(mut self) -> MapAllocBuilder {
+        self.align_end = true;
+        self
+    }
+
+    /// Maps memory which is shared with child processes.
+    ///
+    /// `shared` makes it so that allocated memory is mapped with `MAP_SHARED`, so that it remains
+    /// shared with any child processes created with `fork` after it is allocated, rather than
+    /// being copied on write. The default is to map private memory.
+    ///
+    /// See the "Shared and File-Backed Memory" section of the `MapAllocBuilder` documentation for
+    /// more details.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `shared` is only supported on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn shared(mut self) -> MapAllocBuilder {
+        self.backing = Backing::Shared;
+        self
+    }
+
+    /// Allocates memory from a new anonymous in-memory file.
+    ///
+    /// `memfd` makes it so that `build` creates a new file of `size` bytes using `memfd_create`,
+    /// out of which all objects are allocated. The file is closed when the `MapAlloc` is dropped.
+    /// Memory is only consumed by the parts of the file which have been accessed.
+    ///
+    /// See the "Shared and File-Backed Memory" section of the `MapAllocBuilder` documentation for
+    /// more details.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `memfd` is only supported on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn memfd(mut self, size: usize) -> MapAllocBuilder {
+        self.backing = Backing::Memfd(size);
+        self
+    }
+
+    /// Allocates memory from an existing file.
+    ///
+    /// `file` makes it so that `build` maps the entirety of the file referred to by `fd`, out of
+    /// which all objects are allocated. The file must be opened with access modes compatible with
+    /// the configured permissions (e.g., read/write for the default permissions). The `MapAlloc`
+    /// does not take ownership of `fd`, which may be closed once `build` has returned.
+    ///
+    /// See the "Shared and File-Backed Memory" section of the `MapAllocBuilder` documentation for
+    /// more details.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `file` is only supported on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn file(mut self, fd: RawFd) -> MapAllocBuilder {
+        self.backing = Backing::File(fd);
+        self
+    }
+
+    /// Sets the strategy used to decommit memory.
+    ///
+    /// `decommit_strategy` sets the strategy used by `MapAlloc::uncommit` to release the memory
+    /// backing an object. The default is `DecommitStrategy::DontNeed` on Linux and
+    /// `DecommitStrategy::Free` on Mac. `DecommitStrategy::Free` and `DecommitStrategy::Remap`
+    /// cannot be used with `shared`, `memfd`, or `file`, and `DecommitStrategy::Remap` cannot be
+    /// used with huge pages.
+    ///
+    /// See the "Decommitting Memory" section of the `MapAllocBuilder` documentation for more
+    /// details.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `decommit_strategy` is only supported on Linux and Mac.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn decommit_strategy(mut self, strategy: DecommitStrategy) -> MapAllocBuilder {
+        self.decommit = strategy;
+        self
+    }
 }
 
 impl Default for MapAllocBuilder {
@@ -247,16 +577,51 @@ impl Default for MapAllocBuilder {
             pagesize: sysconf::page::pagesize(),
             huge_pagesize: None,
             obj_size: None,
+            guard_before: 0,
+            guard_after: 0,
+            align_end: false,
+            #[cfg(target_os = "linux")]
+            backing: Backing::Private,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            decommit: DecommitStrategy::default(),
         }
     }
 }
 
+// The memory backing the mappings of a MapAlloc.
+#[cfg(target_os = "linux")]
+enum MapBacking {
+    Private,
+    Shared,
+    // A file which is mapped in its entirety at base. Objects are allocated by bumping next
+    // through the mapping.
+    File {
+        fd: RawFd,
+        // whether the MapAlloc created fd, and so must close it
+        owned: bool,
+        base: usize,
+        len: usize,
+        next: AtomicUsize,
+    },
+}
+
 pub struct MapAlloc {
     pagesize: usize,
     huge_pagesize: Option<usize>,
     perms: perms::Perm,
     commit: bool,
     obj_size: usize,
+    // number of guard pages before and after each object
+    guard_before: usize,
+    guard_after: usize,
+    align_end: bool,
+    #[cfg(target_os = "linux")]
+    backing: MapBacking,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    decommit: DecommitStrategy,
+    // statistics reported by AllocatorStats
+    bytes_mapped: AtomicUsize,
+    live_objects: AtomicUsize,
 }
 
 impl Default for MapAlloc {
@@ -265,6 +630,22 @@ impl Default for MapAlloc {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl Drop for MapAlloc {
+    fn drop(&mut self) {
+        if let MapBacking::File { fd, owned, base, len, .. } = self.backing {
+            unsafe {
+                if len > 0 {
+                    unmap(base as *mut u8, len);
+                }
+                if owned {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+}
+
 impl MapAlloc {
     // alloc_helper performs the requested allocation, properly handling the case in which map
     // returns null.
@@ -289,12 +670,106 @@ impl MapAlloc {
             mark_unused(ptr::null_mut(), self.pagesize);
             self.alloc_helper(size)
         } else {
+            self.bytes_mapped.fetch_add(size, Ordering::Relaxed);
             Some(ptr)
         };
+        #[cfg(target_os = "linux")]
+        {
+            use libc::{MAP_ANONYMOUS, MAP_SHARED};
+            match self.backing {
+                MapBacking::Private => {}
+                MapBacking::Shared => {
+                    let flags = MAP_ANONYMOUS | MAP_SHARED;
+                    return map_with(size, self.perms, self.commit, self.huge_pagesize, flags, -1, 0)
+                               .and_then(f);
+                }
+                MapBacking::File { .. } => return self.alloc_file(size),
+            }
+        }
         // NOTE: self.commit is guaranteed to be false on Mac.
         map(size, self.perms, self.commit, self.huge_pagesize).and_then(f)
     }
 
+    // alloc_file allocates size bytes from the file backing the allocator.
+    #[cfg(target_os = "linux")]
+    unsafe fn alloc_file(&self, size: usize) -> Option<*mut u8> {
+        if let MapBacking::File { base, len, ref next, .. } = self.backing {
+            let mut offset = next.load(Ordering::Relaxed);
+            loop {
+                if len - offset < size {
+                    return None;
+                }
+                let old = next.compare_and_swap(offset, offset + size, Ordering::Relaxed);
+                if old == offset {
+                    break;
+                }
+                offset = old;
+            }
+            self.bytes_mapped.fetch_add(size, Ordering::Relaxed);
+            // NOTE: The mapping was created with a non-zero length, so base is not null.
+            Some((base + offset) as *mut u8)
+        } else {
+            unreachable!()
+        }
+    }
+
+    // unmap_region releases a region previously returned by alloc_helper.
+    unsafe fn unmap_region(&self, region: *mut u8, size: usize) {
+        #[cfg(target_os = "linux")]
+        {
+            if let MapBacking::File { owned, .. } = self.backing {
+                // The file stays mapped until the MapAlloc is dropped, so we can only release the
+                // memory backing the region and make it inaccessible.
+                if owned {
+                    use libc::{c_void, MADV_REMOVE};
+                    let ret = libc::madvise(region as *mut c_void, size, MADV_REMOVE);
+                    assert_eq!(ret, 0, "madvise failed: {}", errno());
+                }
+                protect(region, size, perms::PROT_NONE);
+                return;
+            }
+        }
+        unmap(region, size);
+    }
+
+    /// Returns the file descriptor of the file backing this allocator.
+    ///
+    /// `fd` returns the descriptor of the file created by `MapAllocBuilder::memfd` or passed to
+    /// `MapAllocBuilder::file`, or `None` if the allocator is not file-backed. The descriptor of a
+    /// file created by `memfd` is closed when the `MapAlloc` is dropped.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `fd` is only supported on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn fd(&self) -> Option<RawFd> {
+        match self.backing {
+            MapBacking::File { fd, .. } => Some(fd),
+            _ => None,
+        }
+    }
+
+    /// Returns the offset of an allocated object within the file backing this allocator.
+    ///
+    /// Another process which has mapped the file (see `fd`) can find the object at this offset
+    /// within its mapping. `file_offset` returns `None` if the allocator is not file-backed.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `file_offset` is only supported on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn file_offset(&self, ptr: *mut u8) -> Option<usize> {
+        match self.backing {
+            MapBacking::File { base, len, .. } => {
+                debug_assert!(ptr as usize >= base && (ptr as usize) < base + len,
+                              "ptr {:?} not allocated from this allocator",
+                              ptr);
+                Some(ptr as usize - base)
+            }
+            _ => None,
+        }
+    }
+
     /// Commits an existing allocated object.
     ///
     /// `commit` moves the given object into the committed state. After `commit` has returned, the
@@ -327,7 +802,8 @@ impl MapAlloc {
     ///
     /// On Windows, after `uncommit` has returned, the object cannot be accessed without crashing
     /// the program. On Linux and Mac, the memory can still safely be accessed, but it may have
-    /// been zeroed.
+    /// been zeroed. The memory is released using the configured `DecommitStrategy` (see the
+    /// "Decommitting Memory" section of the `MapAllocBuilder` documentation).
     #[cfg(any(target_os = "linux", target_os = "macos", windows))]
     pub unsafe fn uncommit(&self, ptr: *mut u8, layout: Layout) {
         // TODO: What to do about sizes that are not multiples of the page size? These are legal
@@ -335,10 +811,103 @@ impl MapAlloc {
         // properly?
         #[cfg(debug_assertions)]
         self.debug_verify_ptr(ptr, layout.clone());
-        uncommit(ptr, layout.size());
+        // with align_end, the object might not start at the beginning of its first page
+        let pages = self.obj_pages(ptr);
+        let size = layout.size() + (ptr as usize - pages as usize);
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        decommit(pages, size, self.perms, self.decommit);
+        #[cfg(windows)]
+        uncommit(pages, size);
+    }
+
+    /// Changes the permissions of an existing allocated object.
+    ///
+    /// `protect` changes the permissions of every page containing any part of the given object.
+    /// `read`, `write`, and `exec` have the same meaning as the corresponding builder options, and
+    /// the same platform limitations apply (see the "Memory Permissions" section of the
+    /// `MapAllocBuilder` documentation). Other objects are unaffected since each object occupies
+    /// its own pages. Guard pages (see `MapAllocBuilder::guard_pages`) remain inaccessible.
+    ///
+    /// When making newly-written code executable, call `flush_icache` before executing it.
+    ///
+    /// If the allocator uses `DecommitStrategy::Remap`, calling `uncommit` on the object restores
+    /// the permissions configured on the `MapAllocBuilder`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `protect` is only supported on Linux and Mac.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub unsafe fn protect(&self,
+                          ptr: *mut u8,
+                          layout: Layout,
+                          read: bool,
+                          write: bool,
+                          exec: bool) {
+        #[cfg(debug_assertions)]
+        self.debug_verify_ptr(ptr, layout.clone());
+        // with align_end, the object might not start at the beginning of its first page
+        let pages = self.obj_pages(ptr);
+        protect(pages,
+                layout.size() + (ptr as usize - pages as usize),
+                perms::get_perm(read, write, exec));
+    }
+
+    // unmapped updates statistics after unmapping size bytes containing num_objs objects.
+    fn unmapped(&self, size: usize, num_objs: usize) {
+        self.bytes_mapped.fetch_sub(size, Ordering::Relaxed);
+        self.live_objects.fetch_sub(num_objs, Ordering::Relaxed);
+    }
+
+    // guard_size returns the total size of the guard pages surrounding each object.
+    fn guard_size(&self) -> usize {
+        (self.guard_before + self.guard_after) * self.pagesize
+    }
+
+    // place_obj sets up the guard pages in a newly-mapped region which holds an object whose pages
+    // are size bytes long, and returns a pointer to the object.
+    unsafe fn place_obj(&self, region: *mut u8, size: usize, layout: &Layout) -> *mut u8 {
+        let pages = region.offset((self.guard_before * self.pagesize) as isize);
+        // The region was just mapped, so there's nothing for madvise to release. protect panics
+        // rather than silently leaving the object without guard pages if mprotect fails (e.g.,
+        // because splitting the mapping would exceed vm.max_map_count).
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            if self.guard_before > 0 {
+                protect(region, self.guard_before * self.pagesize, perms::PROT_NONE);
+            }
+            if self.guard_after > 0 {
+                protect(pages.offset(size as isize),
+                        self.guard_after * self.pagesize,
+                        perms::PROT_NONE);
+            }
+        }
+        if self.align_end {
+            // Since pages is page-aligned, rounding the offset down to a multiple of the alignment
+            // yields an aligned pointer.
+            pages.offset(((size - layout.size()) & !(layout.align() - 1)) as isize)
+        } else {
+            pages
+        }
+    }
+
+    // obj_pages returns the beginning of the first page of the object at ptr.
+    fn obj_pages(&self, ptr: *mut u8) -> *mut u8 {
+        if self.align_end {
+            // objects are never offset by a page or more
+            (ptr as usize & !(self.pagesize - 1)) as *mut u8
+        } else {
+            ptr
+        }
+    }
+
+    // region returns the beginning of the mapping containing the object at ptr, including its
+    // guard pages.
+    fn region(&self, ptr: *mut u8) -> *mut u8 {
+        (self.obj_pages(ptr) as usize - self.guard_before * self.pagesize) as *mut u8
     }
 
     fn debug_verify_ptr(&self, ptr: *mut u8, layout: Layout) {
+        let ptr = self.obj_pages(ptr);
         if let Some(huge) = self.huge_pagesize {
             debug_assert_eq!(ptr as usize % huge,
                              0,
@@ -363,10 +932,22 @@ unsafe impl<'a> Alloc for &'a MapAlloc {
     }
 
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        unmap(ptr, layout.size());
+        let size = next_multiple(layout.size(), self.pagesize) + self.guard_size();
+        self.unmap_region(self.region(ptr), size);
+        self.unmapped(size, 1);
     }
 
     unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        #[cfg(target_os = "linux")]
+        {
+            // Unlike new mappings and memfds, an existing file may have non-zero contents.
+            if let MapBacking::File { owned: false, .. } = self.backing {
+                let size = layout.size();
+                let ptr = <&'a MapAlloc as Alloc>::alloc(self, layout)?;
+                ptr::write_bytes(ptr, 0, size);
+                return Ok(ptr);
+            }
+        }
         <&'a MapAlloc as Alloc>::alloc(self, layout)
     }
 
@@ -378,14 +959,26 @@ unsafe impl<'a> Alloc for &'a MapAlloc {
         }
 
         let size = next_multiple(layout.size(), self.pagesize);
-        match self.alloc_helper(size) {
-            Some(ptr) => Ok(Excess(ptr, size)),
+        let region_size = match size.checked_add(self.guard_size()) {
+            Some(region_size) => region_size,
+            None => return Err(AllocErr::Exhausted { request: layout }),
+        };
+        match self.alloc_helper(region_size) {
+            Some(region) => {
+                self.live_objects.fetch_add(1, Ordering::Relaxed);
+                let ptr = self.place_obj(region, size, &layout);
+                let pages = region.offset((self.guard_before * self.pagesize) as isize);
+                Ok(Excess(ptr, size - (ptr as usize - pages as usize)))
+            }
             None => Err(AllocErr::Exhausted { request: layout }),
         }
     }
 }
 
-unsafe impl<'a> UntypedObjectAlloc for &'a MapAlloc {
+// NOTE: Since MapAlloc doesn't have any mutable state, it can be shared between threads, and
+// implementing SharedUntypedObjectAlloc also provides an UntypedObjectAlloc implementation for
+// &MapAlloc.
+unsafe impl SharedUntypedObjectAlloc for MapAlloc {
     fn layout(&self) -> Layout {
         if cfg!(debug_assertions) {
             Layout::from_size_align(self.obj_size, self.pagesize).unwrap()
@@ -394,17 +987,86 @@ unsafe impl<'a> UntypedObjectAlloc for &'a MapAlloc {
         }
     }
 
-    unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
+    unsafe fn alloc(&self) -> Result<*mut u8, Error> {
         // TODO: There's probably a method that does this more cleanly.
-        match self.alloc_excess(self.layout()) {
-            Ok(Excess(ptr, _)) => Ok(ptr),
-            Err(AllocErr::Exhausted { .. }) => Err(Exhausted),
-            Err(AllocErr::Unsupported { .. }) => unreachable!(),
+        let layout = SharedUntypedObjectAlloc::layout(self);
+        <&MapAlloc as Alloc>::alloc_excess(&mut (&*self), layout.clone())
+            .map(|Excess(ptr, _)| ptr)
+            .map_err(|err| Error::from_alloc_err(err, layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8) {
+        let size = self.obj_size + self.guard_size();
+        self.unmap_region(self.region(ptr), size);
+        self.unmapped(size, 1);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    unsafe fn alloc_batch(&self, out: &mut [*mut u8]) -> usize {
+        // On Unix, munmap can unmap any page-aligned subset of an existing mapping, so we can
+        // perform a single large mapping, split it into objects, and later unmap each of those
+        // objects individually. On Windows, VirtualFree can only release entire regions, so the
+        // default implementation is used instead.
+        if out.is_empty() {
+            return 0;
+        }
+        // each object is mapped along with its guard pages
+        let layout = SharedUntypedObjectAlloc::layout(self);
+        let stride = self.obj_size + self.guard_size();
+        let size = match stride.checked_mul(out.len()) {
+            Some(size) => size,
+            None => return 0,
+        };
+        match self.alloc_helper(size) {
+            Some(ptr) => {
+                for (i, slot) in out.iter_mut().enumerate() {
+                    let region = ptr.offset((i * stride) as isize);
+                    *slot = self.place_obj(region, self.obj_size, &layout);
+                }
+                self.live_objects.fetch_add(out.len(), Ordering::Relaxed);
+                out.len()
+            }
+            // The single large mapping failed, but smaller mappings might still succeed, so fall
+            // back to allocating one object at a time.
+            None => {
+                for (i, slot) in out.iter_mut().enumerate() {
+                    match self.alloc_helper(stride) {
+                        Some(ptr) => *slot = self.place_obj(ptr, self.obj_size, &layout),
+                        None => {
+                            self.live_objects.fetch_add(i, Ordering::Relaxed);
+                            return i;
+                        }
+                    }
+                }
+                self.live_objects.fetch_add(out.len(), Ordering::Relaxed);
+                out.len()
+            }
         }
     }
+}
 
-    unsafe fn dealloc(&mut self, ptr: *mut u8) {
-        unmap(ptr, self.obj_size);
+/// Reports statistics for the allocator.
+///
+/// Every object is mapped directly, so `cached_objects` is always zero, and `slabs` is the number
+/// of pages mapped, including guard pages. Since `MapAlloc` cannot observe which pages of an
+/// uncommitted mapping have been accessed, `bytes_committed` only includes memory mapped with the
+/// "commit" option enabled.
+impl AllocatorStats for MapAlloc {
+    fn stats(&self) -> Stats {
+        let bytes_mapped = self.bytes_mapped.load(Ordering::Relaxed);
+        let live_objects = self.live_objects.load(Ordering::Relaxed);
+        Stats {
+            live_objects: live_objects,
+            cached_objects: 0,
+            bytes_mapped: bytes_mapped,
+            bytes_committed: if self.commit {
+                // guard pages are never committed
+                bytes_mapped.saturating_sub(live_objects * self.guard_size())
+            } else {
+                0
+            },
+            slabs: bytes_mapped / self.pagesize,
+        }
     }
 }
 
@@ -431,13 +1093,168 @@ unsafe impl UntypedObjectAlloc for MapAlloc {
         <&MapAlloc as UntypedObjectAlloc>::layout(&(&*self))
     }
 
-    unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
         <&MapAlloc as UntypedObjectAlloc>::alloc(&mut (&*self))
     }
 
     unsafe fn dealloc(&mut self, ptr: *mut u8) {
         <&MapAlloc as UntypedObjectAlloc>::dealloc(&mut (&*self), ptr);
     }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        <&MapAlloc as UntypedObjectAlloc>::alloc_batch(&mut (&*self), out)
+    }
+}
+
+/// A reserved range of address space.
+///
+/// A `Reservation` reserves a contiguous range of address space without backing it with memory.
+/// Ranges of pages within the reservation can be committed, after which they can be accessed with
+/// the permissions with which the reservation was created (see `MapAllocBuilder::reserve`), and
+/// decommitted, after which their memory is returned to the operating system and they can no
+/// longer be accessed. This allows a single large range of address space - possibly much larger
+/// than physical memory - to be reserved up front, and used piecemeal. The entire range is
+/// released when the `Reservation` is dropped.
+///
+/// # Platform-specific behavior
+///
+/// On Linux and Mac, committed pages are only backed by physical memory once they are first
+/// accessed. On Windows, `grow` always fails, since `VirtualFree` can only release a reserved
+/// region as a whole.
+#[derive(Debug)]
+pub struct Reservation {
+    ptr: *mut u8,
+    len: usize,
+    pagesize: usize,
+    perms: perms::Perm,
+}
+
+// The only state is the range of address space, and the operating system synchronizes committing
+// and decommitting it.
+unsafe impl Send for Reservation {}
+unsafe impl Sync for Reservation {}
+
+impl Reservation {
+    /// Reserves a range of address space for read/write memory.
+    ///
+    /// `new` is equivalent to `MapAllocBuilder::default().reserve(size)`.
+    pub fn new(size: usize) -> Option<Reservation> {
+        MapAllocBuilder::default().reserve(size)
+    }
+
+    // map reserves len bytes, properly handling the case in which the operating system returns
+    // null. See MapAlloc::alloc_helper for details.
+    unsafe fn map(len: usize, pagesize: usize, perms: perms::Perm) -> Option<Reservation> {
+        if len == 0 {
+            return None;
+        }
+        let ptr = match reserve(len) {
+            Some(ptr) => ptr,
+            None => return None,
+        };
+        if ptr.is_null() {
+            if len > pagesize {
+                unmap(pagesize as *mut u8, len - pagesize);
+            }
+            return Reservation::map(len, pagesize, perms);
+        }
+        Some(Reservation {
+                 ptr: ptr,
+                 len: len,
+                 pagesize: pagesize,
+                 perms: perms,
+             })
+    }
+
+    /// Returns a pointer to the beginning of the reservation.
+    pub fn ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Returns the size of the reservation in bytes.
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Commits a range of pages within the reservation.
+    ///
+    /// After `commit` has returned, the `size` bytes starting at `ptr` can be accessed. The range
+    /// must lie within the reservation, and is extended to page boundaries. Committing
+    /// already-committed pages preserves their contents.
+    pub unsafe fn commit(&self, ptr: *mut u8, size: usize) {
+        self.debug_verify_range(ptr, size);
+        let pages = (ptr as usize & !(self.pagesize - 1)) as *mut u8;
+        let size = next_multiple(size + (ptr as usize - pages as usize), self.pagesize);
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        protect(pages, size, self.perms);
+        #[cfg(windows)]
+        commit(pages, size, self.perms);
+    }
+
+    /// Decommits a range of pages within the reservation.
+    ///
+    /// `decommit` releases the memory backing the `size` bytes starting at `ptr`, after which they
+    /// cannot be accessed until they are committed again. When they are, they will be
+    /// zero-filled. `ptr` must be page-aligned, and the range must lie within the reservation.
+    pub unsafe fn decommit(&self, ptr: *mut u8, size: usize) {
+        debug_assert_eq!(ptr as usize % self.pagesize,
+                         0,
+                         "ptr {:?} not aligned to page size {}",
+                         ptr,
+                         self.pagesize);
+        self.debug_verify_range(ptr, size);
+        #[cfg(target_os = "linux")]
+        {
+            // Unlike MADV_DONTNEED, MADV_FREE doesn't guarantee that the pages will be zero-filled.
+            decommit(ptr, size, perms::PROT_NONE, DecommitStrategy::DontNeed);
+            protect(ptr, size, perms::PROT_NONE);
+        }
+        // On Mac, MADV_DONTNEED doesn't guarantee that the pages will be zero-filled either.
+        #[cfg(target_os = "macos")]
+        remap(ptr, size, perms::PROT_NONE);
+        #[cfg(windows)]
+        uncommit(ptr, size);
+    }
+
+    /// Attempts to grow the reservation in place.
+    ///
+    /// `grow` attempts to extend the reservation to `size` bytes (rounded up to a multiple of the
+    /// page size) by reserving the address space immediately following it. The new pages are
+    /// uncommitted. If that address space is already in use, `grow` fails and returns false, and
+    /// the reservation is unchanged. If `size` is not larger than the reservation, `grow` does
+    /// nothing and returns true.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Windows, `grow` always returns false unless `size` is not larger than the reservation.
+    pub fn grow(&mut self, size: usize) -> bool {
+        let size = next_multiple(size, self.pagesize);
+        if size <= self.len {
+            return true;
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        unsafe {
+            let end = self.ptr.offset(self.len as isize);
+            if reserve_at(end, size - self.len) {
+                self.len = size;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn debug_verify_range(&self, ptr: *mut u8, size: usize) {
+        debug_assert!(ptr >= self.ptr && ptr as usize + size <= self.ptr as usize + self.len,
+                      "range [{:?}, {:?}) not contained in reservation",
+                      ptr,
+                      (ptr as usize + size) as *mut u8);
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        unsafe { unmap(self.ptr, self.len) };
+    }
 }
 
 fn next_multiple(size: usize, unit: usize) -> usize {
@@ -449,6 +1266,43 @@ fn next_multiple(size: usize, unit: usize) -> usize {
     }
 }
 
+/// Flushes the instruction cache for a range of memory.
+///
+/// After writing instructions to memory, `flush_icache` must be called before executing them, or
+/// else the CPU may execute stale instructions (see the "A warning about executable memory"
+/// section of the `MapAllocBuilder` documentation). On x86 and x86-64, whose instruction caches
+/// are coherent with memory, `flush_icache` only prevents the compiler from reordering writes to
+/// the range after it.
+///
+/// # Platform-specific behavior
+///
+/// `flush_icache` is only supported on Linux and Mac.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub unsafe fn flush_icache(ptr: *mut u8, size: usize) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        use core::sync::atomic::{compiler_fence, Ordering};
+        let _ = (ptr, size);
+        compiler_fence(Ordering::SeqCst);
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        // provided by compiler-rt (or libgcc) on architectures which need it
+        extern "C" {
+            fn __clear_cache(begin: *mut libc::c_char, end: *mut libc::c_char);
+        }
+        __clear_cache(ptr as *mut libc::c_char, ptr.offset(size as isize) as *mut libc::c_char);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+unsafe fn protect(ptr: *mut u8, size: usize, perms: i32) {
+    use libc::{mprotect, c_void};
+    // NOTE: Don't inline the call to mprotect; then errno might be called before mprotect.
+    let ret = mprotect(ptr as *mut c_void, size, perms);
+    assert_eq!(ret, 0, "mprotect failed: {}", errno());
+}
+
 #[cfg(target_os = "linux")]
 unsafe fn mark_unused(ptr: *mut u8, size: usize) {
     use libc::{c_void, MADV_DONTNEED, PROT_NONE};
@@ -473,7 +1327,22 @@ unsafe fn map(size: usize,
               commit: bool,
               huge_pagesize: Option<usize>)
               -> Option<*mut u8> {
-    use libc::{ENOMEM, MAP_ANONYMOUS, MAP_FAILED, MAP_HUGETLB, MAP_PRIVATE, MAP_POPULATE};
+    use libc::{MAP_ANONYMOUS, MAP_PRIVATE};
+    map_with(size, perms, commit, huge_pagesize, MAP_ANONYMOUS | MAP_PRIVATE, -1, 0)
+}
+
+// map_with is like map, but allows the caller to specify the type of mapping (typ, which is
+// combined with the flags implied by commit and huge_pagesize), and the file and offset to map.
+#[cfg(target_os = "linux")]
+unsafe fn map_with(size: usize,
+                   perms: i32,
+                   commit: bool,
+                   huge_pagesize: Option<usize>,
+                   typ: i32,
+                   fd: RawFd,
+                   offset: usize)
+                   -> Option<*mut u8> {
+    use libc::{ENOMEM, MAP_FAILED, MAP_HUGETLB, MAP_POPULATE, off_t};
 
     // TODO: Figure out when it's safe to pass MAP_UNINITIALIZED (it's not defined in all
     // versions of libc). Be careful about not invalidating alloc_zeroed.
@@ -498,9 +1367,9 @@ unsafe fn map(size: usize,
     let ptr = libc::mmap(ptr::null_mut(),
                          size,
                          perms,
-                         MAP_ANONYMOUS | MAP_PRIVATE | flags,
-                         -1,
-                         0);
+                         typ | flags,
+                         fd,
+                         offset as off_t);
 
     if ptr == MAP_FAILED {
         if errno().0 == ENOMEM {
@@ -513,6 +1382,73 @@ unsafe fn map(size: usize,
     }
 }
 
+// memfd_create creates a new anonymous in-memory file of size bytes.
+#[cfg(target_os = "linux")]
+unsafe fn memfd_create(size: usize) -> RawFd {
+    use libc::{c_char, off_t, SYS_memfd_create};
+
+    // not defined in all versions of libc
+    const MFD_CLOEXEC: libc::c_uint = 1;
+    let name = b"mmap-alloc\0";
+    // NOTE: Don't inline the calls; then errno might be called before them.
+    let fd = libc::syscall(SYS_memfd_create, name.as_ptr() as *const c_char, MFD_CLOEXEC);
+    assert!(fd >= 0, "memfd_create failed: {}", errno());
+    let fd = fd as RawFd;
+    let ret = libc::ftruncate(fd, size as off_t);
+    assert_eq!(ret, 0, "ftruncate failed: {}", errno());
+    fd
+}
+
+// file_size returns the size of the file fd.
+#[cfg(target_os = "linux")]
+unsafe fn file_size(fd: RawFd) -> usize {
+    let mut stat: libc::stat = core::mem::zeroed();
+    let ret = libc::fstat(fd, &mut stat);
+    assert_eq!(ret, 0, "fstat failed: {}", errno());
+    stat.st_size as usize
+}
+
+// reserve maps size bytes of inaccessible address space which is not backed by memory.
+#[cfg(target_os = "linux")]
+unsafe fn reserve(size: usize) -> Option<*mut u8> {
+    use libc::{MAP_ANONYMOUS, MAP_NORESERVE, MAP_PRIVATE};
+    let typ = MAP_ANONYMOUS | MAP_PRIVATE | MAP_NORESERVE;
+    map_with(size, perms::PROT_NONE, false, None, typ, -1, 0)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn reserve(size: usize) -> Option<*mut u8> {
+    map(size, perms::PROT_NONE, false, None)
+}
+
+#[cfg(windows)]
+unsafe fn reserve(size: usize) -> Option<*mut u8> {
+    map(size, perms::PROT_NONE, false, None)
+}
+
+// reserve_at attempts to reserve size bytes of address space starting at ptr, returning false if
+// any of it is already in use.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+unsafe fn reserve_at(ptr: *mut u8, size: usize) -> bool {
+    use libc::{c_void, MAP_FAILED, MAP_PRIVATE};
+    #[cfg(target_os = "linux")]
+    let typ = libc::MAP_ANONYMOUS | MAP_PRIVATE | libc::MAP_NORESERVE;
+    #[cfg(target_os = "macos")]
+    let typ = libc::MAP_ANON | MAP_PRIVATE;
+
+    // Without MAP_FIXED, the address is only a hint, and if it is in use, the kernel will choose
+    // another one rather than replacing the existing mapping.
+    let ret = libc::mmap(ptr as *mut c_void, size, perms::PROT_NONE, typ, -1, 0);
+    if ret == MAP_FAILED {
+        false
+    } else if ret as *mut u8 != ptr {
+        unmap(ret as *mut u8, size);
+        false
+    } else {
+        true
+    }
+}
+
 // commit must be false
 #[cfg(target_os = "macos")]
 unsafe fn map(size: usize,
@@ -614,19 +1550,77 @@ unsafe fn commit(ptr: *mut u8, size: usize, perms: u32) {
     assert_eq!(ret as *mut u8, ptr);
 }
 
-#[cfg(target_os = "linux")]
-unsafe fn uncommit(ptr: *mut u8, size: usize) {
-    use libc::{c_void, MADV_DONTNEED};
+// set once madv_free_supported has probed for MADV_FREE support
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+static MADV_FREE_PROBED: AtomicBool = ATOMIC_BOOL_INIT;
+// set if the probe found that the kernel rejects MADV_FREE so that we don't keep trying it
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+static MADV_FREE_UNSUPPORTED: AtomicBool = ATOMIC_BOOL_INIT;
 
-    // TODO: Other options such as MADV_FREE are available on newer versions of Linux. Is there
-    // a way that we can use those when available? Is that even desirable?
-    libc::madvise(ptr as *mut c_void, size, MADV_DONTNEED);
+// madv_free_supported returns whether the kernel supports MADV_FREE. Kernels older than 4.5 reject
+// it with EINVAL, but so do newer kernels for shared, file-backed, and hugetlb mappings, so a
+// failure on an arbitrary mapping says nothing about the kernel. Instead, support is probed once
+// using a private, anonymous mapping of a single page.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn madv_free_supported() -> bool {
+    use libc::{c_void, MADV_FREE};
+
+    if !MADV_FREE_PROBED.load(Ordering::Acquire) {
+        let pagesize = sysconf::page::pagesize();
+        let page = match unsafe { map(pagesize, perms::PROT_READ_WRITE, false, None) } {
+            Some(page) => page,
+            // try again next time
+            None => return false,
+        };
+        let ret = unsafe { libc::madvise(page as *mut c_void, pagesize, MADV_FREE) };
+        unsafe { unmap(page, pagesize) };
+        // Concurrent probes all store the same results.
+        MADV_FREE_UNSUPPORTED.store(ret != 0, Ordering::Relaxed);
+        MADV_FREE_PROBED.store(true, Ordering::Release);
+    }
+    !MADV_FREE_UNSUPPORTED.load(Ordering::Relaxed)
 }
 
-#[cfg(target_os = "macos")]
-unsafe fn uncommit(ptr: *mut u8, size: usize) {
-    use libc::{c_void, MADV_FREE};
-    libc::madvise(ptr as *mut c_void, size, MADV_FREE);
+// decommit releases the memory backing size bytes starting at ptr using strategy. perms are the
+// permissions of the pages, which are preserved.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+unsafe fn decommit(ptr: *mut u8, size: usize, perms: i32, strategy: DecommitStrategy) {
+    use libc::{c_void, MADV_DONTNEED, MADV_FREE};
+
+    let advice = match strategy {
+        DecommitStrategy::DontNeed => MADV_DONTNEED,
+        DecommitStrategy::Free => {
+            if madv_free_supported() {
+                MADV_FREE
+            } else {
+                MADV_DONTNEED
+            }
+        }
+        #[cfg(target_os = "linux")]
+        DecommitStrategy::Remove => libc::MADV_REMOVE,
+        DecommitStrategy::Remap => return remap(ptr, size, perms),
+    };
+    // NOTE: Don't inline the call to madvise; then errno might be called before madvise.
+    let ret = libc::madvise(ptr as *mut c_void, size, advice);
+    if ret != 0 && advice != MADV_DONTNEED {
+        // Mappings which don't support MADV_FREE or MADV_REMOVE reject them with EINVAL or
+        // EOPNOTSUPP. This depends on the mapping, so it isn't remembered.
+        libc::madvise(ptr as *mut c_void, size, MADV_DONTNEED);
+    }
+}
+
+// remap replaces size bytes starting at ptr with a new private mapping with the given
+// permissions.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+unsafe fn remap(ptr: *mut u8, size: usize, perms: i32) {
+    use libc::{c_void, MAP_ANON, MAP_FAILED, MAP_FIXED, MAP_PRIVATE};
+    let ret = libc::mmap(ptr as *mut c_void,
+                         size,
+                         perms,
+                         MAP_ANON | MAP_PRIVATE | MAP_FIXED,
+                         -1,
+                         0);
+    assert_ne!(ret, MAP_FAILED, "mmap failed: {}", errno());
 }
 
 #[cfg(windows)]
@@ -870,8 +1864,16 @@ mod tests {
             test_zero_filled(ptr, pagesize());
             #[cfg(windows)]
             commit(ptr, pagesize(), PROT_READ_WRITE);
-            uncommit(ptr, pagesize());
-            uncommit(ptr, pagesize());
+            #[cfg(windows)]
+            {
+                uncommit(ptr, pagesize());
+                uncommit(ptr, pagesize());
+            }
+            #[cfg(not(windows))]
+            {
+                decommit(ptr, pagesize(), PROT_READ_WRITE, DecommitStrategy::default());
+                decommit(ptr, pagesize(), PROT_READ_WRITE, DecommitStrategy::default());
+            }
             unmap(ptr, pagesize());
         }
     }
@@ -921,6 +1923,463 @@ mod tests {
         }
     }
 
+    #[cfg(not(windows))]
+    #[test]
+    fn test_alloc_batch() {
+        unsafe {
+            // Check that:
+            // - Allocating a batch of objects works
+            // - The returned pointers are non-null and page-aligned
+            // - The objects are zero-filled, readable, and writable
+            // - Each object can be individually deallocated
+            let mut alloc = MapAllocBuilder::default()
+                .obj_size(2 * pagesize())
+                .build();
+            let mut ptrs = [ptr::null_mut(); 8];
+            assert_eq!(UntypedObjectAlloc::alloc_batch(&mut alloc, &mut ptrs), ptrs.len());
+            for &ptr in &ptrs {
+                test_valid_map_address(ptr);
+                test_zero_filled(ptr, 2 * pagesize());
+                test_write_read(ptr, 2 * pagesize());
+            }
+            for i in 0..4 {
+                UntypedObjectAlloc::dealloc(&mut alloc, ptrs[2 * i]);
+            }
+            for i in 0..4 {
+                test_write_read(ptrs[2 * i + 1], 2 * pagesize());
+            }
+            UntypedObjectAlloc::dealloc_batch(&mut alloc, &[ptrs[1], ptrs[3], ptrs[5], ptrs[7]]);
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        unsafe {
+            // Check that statistics are updated by both the Alloc and UntypedObjectAlloc
+            // implementations.
+            let mut alloc = MapAllocBuilder::default()
+                .obj_size(2 * pagesize())
+                .build();
+            assert_eq!(alloc.stats(), Default::default());
+
+            let layout = Layout::from_size_align(pagesize() + 1, 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            let obj = UntypedObjectAlloc::alloc(&mut alloc).unwrap();
+            let stats = alloc.stats();
+            assert_eq!(stats.live_objects, 2);
+            assert_eq!(stats.cached_objects, 0);
+            assert_eq!(stats.bytes_mapped, 4 * pagesize());
+            assert_eq!(stats.bytes_committed, 0);
+            assert_eq!(stats.slabs, 4);
+
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+            UntypedObjectAlloc::dealloc(&mut alloc, obj);
+            assert_eq!(alloc.stats(), Default::default());
+        }
+    }
+
+    // Returns the permissions of the page containing ptr as listed in /proc/self/maps (e.g.,
+    // "rw-p").
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    fn page_perms(ptr: *mut u8) -> String {
+        use std::fs::File;
+        use std::io::Read;
+
+        let mut maps = String::new();
+        File::open("/proc/self/maps")
+            .unwrap()
+            .read_to_string(&mut maps)
+            .unwrap();
+        for line in maps.lines() {
+            let mut fields = line.split_whitespace();
+            let mut range = fields.next().unwrap().split('-');
+            let begin = usize::from_str_radix(range.next().unwrap(), 16).unwrap();
+            let end = usize::from_str_radix(range.next().unwrap(), 16).unwrap();
+            if begin <= ptr as usize && (ptr as usize) < end {
+                return fields.next().unwrap().to_string();
+            }
+        }
+        panic!("address {:?} not mapped", ptr);
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_guard_pages() {
+        unsafe {
+            // Check that:
+            // - Objects are surrounded by the requested number of inaccessible guard pages
+            // - The objects themselves are readable and writable
+            // - Guard pages are included in the statistics, and are unmapped along with their
+            //   objects
+            let mut alloc = MapAllocBuilder::default()
+                .guard_pages(1, 2)
+                .obj_size(2 * pagesize())
+                .build();
+            let layout = Layout::from_size_align(3 * pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            let obj = UntypedObjectAlloc::alloc(&mut alloc).unwrap();
+            let mut objs = [ptr::null_mut(); 4];
+            assert_eq!(UntypedObjectAlloc::alloc_batch(&mut alloc, &mut objs), objs.len());
+
+            let check = |ptr: *mut u8, size: usize| {
+                test_valid_map_address(ptr);
+                test_write_read(ptr, size);
+                assert_eq!(page_perms(ptr.offset(-(pagesize() as isize))), "---p");
+                assert_eq!(page_perms(ptr.offset(size as isize)), "---p");
+                assert_eq!(page_perms(ptr.offset((size + pagesize()) as isize)), "---p");
+            };
+            check(ptr, 3 * pagesize());
+            check(obj, 2 * pagesize());
+            for &obj in &objs {
+                check(obj, 2 * pagesize());
+            }
+            assert_eq!(alloc.stats().bytes_mapped, (6 + 5 * 5) * pagesize());
+
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+            UntypedObjectAlloc::dealloc(&mut alloc, obj);
+            UntypedObjectAlloc::dealloc_batch(&mut alloc, &objs);
+            assert_eq!(alloc.stats(), Default::default());
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_align_end() {
+        unsafe {
+            // Check that:
+            // - Objects end as close to the trailing guard page as their alignment allows
+            // - The excess reported by alloc_excess extends to the guard page
+            // - Objects can be uncommitted and deallocated
+            let mut alloc = MapAllocBuilder::default()
+                .guard_pages(0, 1)
+                .align_end()
+                .build();
+            for &(size, align) in &[(1, 1), (24, 8), (100, 16), (pagesize(), 8)] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let Excess(ptr, excess) = <MapAlloc as Alloc>::alloc_excess(&mut alloc,
+                                                                            layout.clone())
+                        .unwrap();
+                assert_eq!(ptr as usize % align, 0);
+                let end = ptr as usize + size;
+                assert!(end % pagesize() == 0 || pagesize() - end % pagesize() < align);
+                assert_eq!((ptr as usize + excess) % pagesize(), 0);
+                test_write_read(ptr, size);
+                assert_eq!(page_perms((ptr as usize + excess) as *mut u8), "---p");
+                alloc.uncommit(ptr, layout.clone());
+                <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+            }
+            assert_eq!(alloc.stats(), Default::default());
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_protect() {
+        unsafe {
+            // Check that:
+            // - Objects can be made read-only, and their contents are preserved
+            // - Objects can be made writable again
+            // - The permissions of neighboring objects are unaffected
+            let mut alloc = MapAllocBuilder::default().build();
+            let layout = Layout::from_size_align(2 * pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            let other = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            test_write(ptr, 2 * pagesize());
+
+            alloc.protect(ptr, layout.clone(), true, false, false);
+            assert_eq!(page_perms(ptr), "r--p");
+            assert_eq!(page_perms(ptr.offset(pagesize() as isize)), "r--p");
+            assert_eq!(page_perms(other), "rw-p");
+            for i in 0..(2 * pagesize()) {
+                assert_eq!(*ptr.offset(i as isize), 1);
+            }
+
+            alloc.protect(ptr, layout.clone(), true, true, false);
+            assert_eq!(page_perms(ptr), "rw-p");
+            test_write_read(ptr, 2 * pagesize());
+
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout.clone());
+            <MapAlloc as Alloc>::dealloc(&mut alloc, other, layout);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_protect_exec() {
+        unsafe {
+            // Check that code written to writable memory can be executed after making the memory
+            // executable and flushing the instruction cache.
+            use core::mem;
+
+            let mut alloc = MapAllocBuilder::default().build();
+            let layout = Layout::from_size_align(pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            // mov eax, 42; ret
+            let code = [0xb8, 0x2a, 0x00, 0x00, 0x00, 0xc3];
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr, code.len());
+            alloc.protect(ptr, layout.clone(), true, false, true);
+            flush_icache(ptr, code.len());
+            let f: extern "C" fn() -> i32 = mem::transmute(ptr);
+            assert_eq!(f(), 42);
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_shared() {
+        unsafe {
+            // Check that writes made by a child process to shared memory are visible to the
+            // parent.
+            use errno::errno;
+            use libc::{_exit, fork, waitpid};
+
+            let mut alloc = MapAllocBuilder::default().shared().build();
+            let layout = Layout::from_size_align(pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            test_zero_filled(ptr, pagesize());
+            assert_eq!(page_perms(ptr), "rw-s");
+            let pid = fork();
+            assert!(pid >= 0, "fork failed: {}", errno());
+            if pid == 0 {
+                *ptr = 42;
+                _exit(0);
+            }
+            let mut status = 0;
+            assert_eq!(waitpid(pid, &mut status, 0), pid);
+            assert_eq!(*ptr, 42);
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_memfd() {
+        unsafe {
+            // Check that:
+            // - Objects are visible through a separate mapping of the memfd at their file offset
+            // - Allocation fails once the memfd is exhausted
+            // - Deallocated objects are inaccessible
+            use libc::{mmap, MAP_SHARED, MAP_FAILED};
+
+            let mut alloc = MapAllocBuilder::default()
+                .memfd(4 * pagesize())
+                .build();
+            let fd = alloc.fd().unwrap();
+            let layout = Layout::from_size_align(pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            let obj = UntypedObjectAlloc::alloc(&mut alloc).unwrap();
+            test_zero_filled(obj, pagesize());
+            *obj = 42;
+
+            let view = mmap(ptr::null_mut(), 4 * pagesize(), PROT_READ, MAP_SHARED, fd, 0);
+            assert_ne!(view, MAP_FAILED);
+            let view = view as *mut u8;
+            assert_eq!(alloc.file_offset(ptr), Some(0));
+            assert_eq!(*view.offset(alloc.file_offset(obj).unwrap() as isize), 42);
+
+            let mut objs = [ptr::null_mut(); 3];
+            assert_eq!(UntypedObjectAlloc::alloc_batch(&mut alloc, &mut objs), 2);
+            assert!(UntypedObjectAlloc::alloc(&mut alloc).is_err());
+            assert_eq!(alloc.stats().bytes_mapped, 4 * pagesize());
+
+            UntypedObjectAlloc::dealloc(&mut alloc, obj);
+            assert_eq!(page_perms(obj), "---s");
+            // the memory has been released, so the other mapping sees zeroes
+            assert_eq!(*view.offset(alloc.file_offset(obj).unwrap() as isize), 0);
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+            UntypedObjectAlloc::dealloc_batch(&mut alloc, &objs[..2]);
+            assert_eq!(alloc.stats(), Default::default());
+            unmap(view, 4 * pagesize());
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_file() {
+        unsafe {
+            // Check that:
+            // - Objects allocated from an existing file contain its contents, except when
+            //   allocated with alloc_zeroed
+            // - Writes to objects are written back to the file
+            use libc::getpid;
+            use std::env;
+            use std::fs::{self, OpenOptions};
+            use std::io::{Read, Write};
+            use std::os::unix::io::AsRawFd;
+
+            let path = env::temp_dir().join(format!("mmap-alloc-test-{}", getpid()));
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(&vec![7; 2 * pagesize()]).unwrap();
+            let mut alloc = MapAllocBuilder::default()
+                .file(file.as_raw_fd())
+                .build();
+            let layout = Layout::from_size_align(pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            assert_eq!(*ptr, 7);
+            let zeroed = <MapAlloc as Alloc>::alloc_zeroed(&mut alloc, layout.clone()).unwrap();
+            test_zero_filled(zeroed, pagesize());
+            assert!(<MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).is_err());
+            *ptr = 42;
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout.clone());
+            <MapAlloc as Alloc>::dealloc(&mut alloc, zeroed, layout);
+            drop(alloc);
+
+            let mut contents = Vec::new();
+            let mut file = fs::File::open(&path).unwrap();
+            file.read_to_end(&mut contents).unwrap();
+            assert_eq!(contents[0], 42);
+            assert_eq!(contents[1], 7);
+            assert!(contents[pagesize()..].iter().all(|&b| b == 0));
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_reservation() {
+        unsafe {
+            // Check that:
+            // - Reserved memory is inaccessible until it is committed
+            // - Committed memory is zero-filled and readable and writable
+            // - Decommitted memory is inaccessible, and zero-filled once it is recommitted
+            // - Growing either extends the reservation or leaves it unchanged
+            let mut res = Reservation::new(8 * pagesize() - 1).unwrap();
+            let ptr = res.ptr();
+            assert_eq!(res.size(), 8 * pagesize());
+            assert_eq!(page_perms(ptr), "---p");
+
+            let page = ptr.offset(2 * pagesize() as isize);
+            res.commit(page, 2 * pagesize());
+            assert_eq!(page_perms(page), "rw-p");
+            assert_eq!(page_perms(ptr), "---p");
+            assert_eq!(page_perms(page.offset(2 * pagesize() as isize)), "---p");
+            test_zero_filled(page, 2 * pagesize());
+            test_write_read(page, 2 * pagesize());
+
+            res.decommit(page, 2 * pagesize());
+            assert_eq!(page_perms(page), "---p");
+            res.commit(page, 2 * pagesize());
+            test_zero_filled(page, 2 * pagesize());
+
+            // Whether the address space following the reservation is free is up to the kernel.
+            if res.grow(16 * pagesize()) {
+                assert_eq!(res.size(), 16 * pagesize());
+                let end = ptr.offset(15 * pagesize() as isize);
+                assert_eq!(page_perms(end), "---p");
+                res.commit(end, pagesize());
+                test_write_read(end, pagesize());
+            } else {
+                assert_eq!(res.size(), 8 * pagesize());
+            }
+            assert!(res.grow(pagesize()));
+            assert_eq!(res.ptr(), ptr);
+
+            // commit extends unaligned ranges to page boundaries
+            res.commit(ptr.offset(1), 1);
+            assert_eq!(page_perms(ptr), "rw-p");
+            test_write_read(ptr, pagesize());
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_reservation_perms() {
+        unsafe {
+            // Check that committed memory has the permissions with which the reservation was
+            // created.
+            let res = MapAllocBuilder::default()
+                .no_write()
+                .reserve(pagesize())
+                .unwrap();
+            res.commit(res.ptr(), pagesize());
+            assert_eq!(page_perms(res.ptr()), "r--p");
+            test_zero_filled(res.ptr(), pagesize());
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "test-no-std")))]
+    #[test]
+    fn test_decommit_strategy() {
+        unsafe {
+            // Check that for each strategy:
+            // - Uncommitted objects remain readable and writable
+            // - Uncommitted objects are zero-filled, except with MADV_FREE, which may preserve
+            //   their contents
+            // - MADV_REMOVE falls back to MADV_DONTNEED for private mappings
+            let strategies = [DecommitStrategy::DontNeed,
+                              DecommitStrategy::Free,
+                              DecommitStrategy::Remove,
+                              DecommitStrategy::Remap];
+            for &strategy in &strategies {
+                let mut alloc = MapAllocBuilder::default()
+                    .decommit_strategy(strategy)
+                    .build();
+                let layout = Layout::from_size_align(2 * pagesize(), 1).unwrap();
+                let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+                test_write(ptr, 2 * pagesize());
+                alloc.uncommit(ptr, layout.clone());
+                assert_eq!(page_perms(ptr), "rw-p");
+                if strategy != DecommitStrategy::Free {
+                    test_zero_filled(ptr, 2 * pagesize());
+                }
+                test_write_read(ptr, 2 * pagesize());
+                <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+            }
+
+            // Check that MADV_REMOVE releases the memory of shared mappings.
+            let mut alloc = MapAllocBuilder::default()
+                .shared()
+                .decommit_strategy(DecommitStrategy::Remove)
+                .build();
+            let layout = Layout::from_size_align(pagesize(), 1).unwrap();
+            let ptr = <MapAlloc as Alloc>::alloc(&mut alloc, layout.clone()).unwrap();
+            test_write(ptr, pagesize());
+            alloc.uncommit(ptr, layout.clone());
+            test_zero_filled(ptr, pagesize());
+            <MapAlloc as Alloc>::dealloc(&mut alloc, ptr, layout);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[should_panic]
+    fn test_remap_shared_panic() {
+        // Check that the Remap strategy cannot be used with shared mappings.
+        MapAllocBuilder::default()
+            .shared()
+            .decommit_strategy(DecommitStrategy::Remap)
+            .build();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    #[should_panic(expected = "not supported with huge pages")]
+    fn test_remap_huge_panic() {
+        // Check that the Remap strategy cannot be used with huge pages.
+        MapAllocBuilder::default()
+            .huge_pagesize(2 << 20)
+            .decommit_strategy(DecommitStrategy::Remap)
+            .build();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[should_panic]
+    fn test_free_memfd_panic() {
+        // Check that the Free strategy cannot be used with memfd-backed mappings.
+        MapAllocBuilder::default()
+            .memfd(pagesize())
+            .decommit_strategy(DecommitStrategy::Free)
+            .build();
+    }
+
     #[cfg(not(windows))]
     #[test]
     #[should_panic]
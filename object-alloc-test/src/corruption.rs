@@ -516,6 +516,7 @@ pub struct TestBuilder<T, O: ObjectAlloc<T>, F: Fn() -> O> {
     new: F,
     test_iters: usize,
     qc_tests: Option<usize>,
+    threads: usize,
     _marker: PhantomData<(T, O)>,
 }
 
@@ -543,6 +544,7 @@ impl<T, O: ObjectAlloc<T>, F: Fn() -> O> TestBuilder<T, O, F> {
             new,
             test_iters: 100_000,
             qc_tests: None,
+            threads: 1,
             _marker: PhantomData,
         }
     }
@@ -568,6 +570,22 @@ impl<T, O: ObjectAlloc<T>, F: Fn() -> O> TestBuilder<T, O, F> {
         self.qc_tests = Some(tests);
         self
     }
+
+    /// Configure the number of threads used by the `test` method.
+    ///
+    /// Each thread calls the function passed to `new` to obtain its own allocator and performs
+    /// `test_iters` iterations using it. In order to test a concurrent allocator, that function
+    /// should return handles to the same underlying allocator so that objects freed on one thread
+    /// may be allocated on another. Objects are only checked for having been dropped once every
+    /// thread has finished and the function passed to `new` has itself been dropped. The default
+    /// is 1.
+    ///
+    /// The `quickcheck` method always uses a single thread.
+    pub fn threads(mut self, threads: usize) -> TestBuilder<T, O, F> {
+        assert!(threads > 0, "must use at least one thread");
+        self.threads = threads;
+        self
+    }
 }
 
 impl<T: Copy, O: ObjectAlloc<CorruptionTesterDefault<T>>, F: Fn() -> O>
@@ -600,19 +618,53 @@ impl<T: Copy, O: ObjectAlloc<CorruptionTesterUnsafe<T>>, F: Fn() -> O>
     }
 }
 
-impl<C: CorruptionTesterWrapper, O: ObjectAlloc<C>, F: Fn() -> O> TestBuilder<C, O, F> {
+impl<C: CorruptionTesterWrapper, O: ObjectAlloc<C>, F: Fn() -> O> TestBuilder<C, O, F>
+    where C: 'static,
+          O: 'static,
+          F: Send + Sync + 'static
+{
     fn priv_test(self) {
-        let mut tester = Tester::new((self.new)());
+        use std::thread;
 
-        for _ in 0..self.test_iters {
-            if rand::random() && !tester.alloced.is_empty() {
-                tester.dealloc(rand::random());
-            } else {
-                tester.alloc();
-            }
+        if self.threads == 1 {
+            let mut tester = Tester::new((self.new)());
+            random_ops(&mut tester, self.test_iters);
+            tester.drop_and_check();
+            return;
         }
 
-        tester.drop_and_check();
+        let objects = Arc::new(Mutex::new(Objects::default()));
+        let new = Arc::new(self.new);
+        let iters = self.test_iters;
+        let threads: Vec<_> = (0..self.threads)
+            .map(|_| {
+                     let objects = objects.clone();
+                     let new = new.clone();
+                     thread::spawn(move || {
+                                       let mut tester = Tester::with_objects(new(), objects);
+                                       random_ops(&mut tester, iters);
+                                       tester.finish();
+                                   })
+                 })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Make sure that any allocator state shared by the threads' allocators is dropped.
+        drop(new);
+        check_freed::<C>(&objects);
+    }
+}
+
+fn random_ops<C: CorruptionTesterWrapper, O: ObjectAlloc<C>>(tester: &mut Tester<C, O>,
+                                                             iters: usize) {
+    for _ in 0..iters {
+        if rand::random() && !tester.alloced.is_empty() {
+            tester.dealloc(rand::random());
+        } else {
+            tester.alloc();
+        }
     }
 }
 
@@ -691,30 +743,42 @@ mod quickcheck {
 }
 
 use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// The set of objects seen by one or more `Tester`s.
+///
+/// Objects are stored as addresses so that `Objects` can be shared between threads.
+#[derive(Default)]
+struct Objects {
+    alloced: HashSet<usize>,
+    freed: HashSet<usize>,
+}
 
 struct Tester<C: CorruptionTesterWrapper, O: ObjectAlloc<C>> {
     alloc: O,
     alloced: Vec<*mut C>,
-    alloced_set: HashSet<*mut C>,
-    freed: HashSet<*mut C>,
+    objects: Arc<Mutex<Objects>>,
 }
 
 impl<C: CorruptionTesterWrapper, O: ObjectAlloc<C>> Tester<C, O> {
     fn new(alloc: O) -> Tester<C, O> {
+        Tester::with_objects(alloc, Arc::new(Mutex::new(Objects::default())))
+    }
+
+    fn with_objects(alloc: O, objects: Arc<Mutex<Objects>>) -> Tester<C, O> {
         Tester {
             alloc: alloc,
             alloced: Vec::new(),
-            alloced_set: HashSet::new(),
-            freed: HashSet::new(),
+            objects: objects,
         }
     }
 
-
     fn alloc(&mut self) {
         let obj = unsafe { self.alloc.alloc().unwrap() };
+        let mut objects = self.objects.lock().unwrap();
         // check for double-allocate of the same pointer
-        assert!(!self.alloced_set.contains(&obj));
-        if self.freed.remove(&obj) {
+        assert!(!objects.alloced.contains(&(obj as usize)));
+        if objects.freed.remove(&(obj as usize)) {
             // We alloced the same pointer at some point in the past and then freed it. It should
             // either be still constructed (Valid) because it hadn't been dropped yet or New
             // because it was dropped and then re-initialized using default() or unsafe_default().
@@ -745,8 +809,8 @@ impl<C: CorruptionTesterWrapper, O: ObjectAlloc<C>> Tester<C, O> {
                 }
             }
         }
+        objects.alloced.insert(obj as usize);
         self.alloced.push(obj);
-        self.alloced_set.insert(obj);
     }
 
     fn dealloc(&mut self, idx: usize) {
@@ -754,14 +818,20 @@ impl<C: CorruptionTesterWrapper, O: ObjectAlloc<C>> Tester<C, O> {
         let obj: *mut C = self.alloced.swap_remove(idx % len);
         // make sure it's still valid
         assert_eq!(unsafe { (*obj).state() }, State::Valid);
-        self.alloced_set.remove(&obj);
-        self.freed.insert(obj);
+        {
+            // Record the free before performing it; once the object has been returned to the
+            // allocator, another thread may allocate it.
+            let mut objects = self.objects.lock().unwrap();
+            objects.alloced.remove(&(obj as usize));
+            objects.freed.insert(obj as usize);
+        }
         unsafe {
             self.alloc.dealloc(obj);
         }
     }
 
-    fn drop_and_check(mut self) {
+    /// Deallocate all remaining objects and drop the allocator.
+    fn finish(mut self) -> Arc<Mutex<Objects>> {
         use self::core::mem::drop;
 
         while !self.alloced.is_empty() {
@@ -769,20 +839,29 @@ impl<C: CorruptionTesterWrapper, O: ObjectAlloc<C>> Tester<C, O> {
             self.dealloc(idx);
         }
 
-        let Tester { alloc, freed, .. } = self;
-
+        let Tester { alloc, objects, .. } = self;
         drop(alloc);
-        for obj in freed {
-            use self::core::mem;
-            if !mapped::is_mapped_range(obj as *mut u8, mem::size_of::<C>()) {
-                // the underlying memory already got freed back to the kernel
-                continue;
-            }
-            match unsafe { (*obj).state() } {
-                State::Invalid | State::Dropped => {}
-                state => {
-                    panic!("freed object at {:?} in unexpected state: {:?}", obj, state);
-                }
+        objects
+    }
+
+    fn drop_and_check(self) {
+        check_freed::<C>(&self.finish());
+    }
+}
+
+/// Verify that no freed object is still constructed once its allocator has been dropped.
+fn check_freed<C: CorruptionTesterWrapper>(objects: &Mutex<Objects>) {
+    for &obj in &objects.lock().unwrap().freed {
+        use self::core::mem;
+        let obj = obj as *mut C;
+        if !mapped::is_mapped_range(obj as *mut u8, mem::size_of::<C>()) {
+            // the underlying memory already got freed back to the kernel
+            continue;
+        }
+        match unsafe { (*obj).state() } {
+            State::Invalid | State::Dropped => {}
+            state => {
+                panic!("freed object at {:?} in unexpected state: {:?}", obj, state);
             }
         }
     }
@@ -30,6 +30,10 @@ impl Drop for Ptr {
     }
 }
 
+// The raw pointers in a LeakyAlloc are owned allocations from the global heap, so a LeakyAlloc can
+// safely be moved between threads.
+unsafe impl Send for LeakyAlloc {}
+
 impl LeakyAlloc {
     /// Creates a new `LeakyAlloc`.
     pub fn new() -> LeakyAlloc {
@@ -58,9 +62,9 @@ mod tests {
     extern crate core;
     extern crate object_alloc;
 
-    use self::alloc::heap::{Alloc, AllocErr, Layout};
+    use self::alloc::heap::{Alloc, Layout};
     use self::core::marker::PhantomData;
-    use self::object_alloc::{Exhausted, ObjectAlloc};
+    use self::object_alloc::{Error, ObjectAlloc};
 
     struct LeakyObjectAlloc<T: Default> {
         alloc: super::LeakyAlloc,
@@ -77,13 +81,11 @@ mod tests {
     }
 
     unsafe impl<T: Default> ObjectAlloc<T> for LeakyObjectAlloc<T> {
-        unsafe fn alloc(&mut self) -> Result<*mut T, Exhausted> {
-            let ptr = match Alloc::alloc(&mut self.alloc, Layout::new::<T>()) {
+        unsafe fn alloc(&mut self) -> Result<*mut T, Error> {
+            let layout = Layout::new::<T>();
+            let ptr = match Alloc::alloc(&mut self.alloc, layout.clone()) {
                 Ok(ptr) => ptr as *mut T,
-                Err(AllocErr::Exhausted { .. }) => return Err(Exhausted),
-                Err(AllocErr::Unsupported { details }) => {
-                    unreachable!("unexpected unsupported alloc: {}", details)
-                }
+                Err(err) => return Err(Error::from_alloc_err(err, layout)),
             };
 
             use self::core::ptr::write;
@@ -0,0 +1,168 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! Adapters between general-purpose allocators and object allocators.
+//!
+//! `AllocObjectAlloc` serves an `UntypedObjectAlloc` from an `Alloc` by always allocating the same
+//! `Layout`. `SizeClassAlloc` goes the other direction, serving an `Alloc` from a table of
+//! `UntypedObjectAlloc`s, each of which serves a single size class.
+
+use alloc::allocator::{Alloc, AllocErr, Layout};
+use core::marker::PhantomData;
+
+use {Error, SharedUntypedObjectAlloc, UntypedObjectAlloc};
+
+/// An `UntypedObjectAlloc` that uses an arbitrary allocator.
+///
+/// An `AllocObjectAlloc` allocates objects of a fixed `Layout` from an allocator implementing
+/// `Alloc`.
+#[derive(Clone)]
+pub struct AllocObjectAlloc<A: Alloc> {
+    alloc: A,
+    layout: Layout,
+}
+
+impl<A: Alloc> AllocObjectAlloc<A> {
+    /// Constructs a new `AllocObjectAlloc` which allocates objects of layout `layout` from
+    /// `alloc`.
+    pub fn new(alloc: A, layout: Layout) -> AllocObjectAlloc<A> {
+        AllocObjectAlloc {
+            alloc: alloc,
+            layout: layout,
+        }
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.alloc
+    }
+}
+
+unsafe impl<A: Alloc> UntypedObjectAlloc for AllocObjectAlloc<A> {
+    fn layout(&self) -> Layout {
+        self.layout.clone()
+    }
+
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
+        self.alloc
+            .alloc(self.layout.clone())
+            .map_err(|err| Error::from_alloc_err(err, self.layout.clone()))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        self.alloc.dealloc(ptr, self.layout.clone());
+    }
+}
+
+/// An `Alloc` which serves allocations from a table of size classes.
+///
+/// A `SizeClassAlloc` holds a table of `UntypedObjectAlloc`s, one per size class, sorted in order
+/// of increasing object size. Each allocation is served by the first size class whose objects are
+/// large enough and sufficiently aligned for the requested `Layout`. Requests which no size class
+/// can satisfy fail with `AllocErr::Unsupported`.
+///
+/// `C` is the type of the table, and can be anything which can be viewed as a slice of
+/// allocators, such as an array or a `Vec`. If the allocators implement
+/// `SharedUntypedObjectAlloc`, then `&SizeClassAlloc` implements `Alloc` as well, which makes it
+/// suitable for use with thread-safe frontends such as `malloc-bind`'s `Malloc`.
+pub struct SizeClassAlloc<A, C> {
+    classes: C,
+    _marker: PhantomData<A>,
+}
+
+impl<A, C> SizeClassAlloc<A, C> {
+    /// Returns the underlying table of allocators.
+    pub fn into_inner(self) -> C {
+        self.classes
+    }
+}
+
+impl<A: UntypedObjectAlloc, C: AsRef<[A]>> SizeClassAlloc<A, C> {
+    /// Constructs a new `SizeClassAlloc`.
+    ///
+    /// # Panics
+    ///
+    /// `new` panics if `classes` is empty or its allocators are not sorted in order of strictly
+    /// increasing object size.
+    pub fn new(classes: C) -> SizeClassAlloc<A, C> {
+        {
+            let classes = classes.as_ref();
+            assert!(!classes.is_empty(), "no size classes");
+            for pair in classes.windows(2) {
+                assert!(pair[0].layout().size() < pair[1].layout().size(),
+                        "size classes must be sorted by increasing size");
+            }
+        }
+        SizeClassAlloc {
+            classes: classes,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Find the index of the first size class whose layout satisfies `layout`.
+fn class_for<A, F: Fn(&A) -> Layout>(classes: &[A], layout: &Layout, f: F) -> Option<usize> {
+    classes
+        .iter()
+        .position(|class| {
+                      let class = f(class);
+                      class.size() >= layout.size() && class.align() >= layout.align()
+                  })
+}
+
+fn unsupported() -> AllocErr {
+    AllocErr::Unsupported { details: "no size class satisfies the requested layout" }
+}
+
+unsafe impl<A: UntypedObjectAlloc, C: AsRef<[A]> + AsMut<[A]>> Alloc for SizeClassAlloc<A, C> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let idx = class_for(self.classes.as_ref(), &layout, UntypedObjectAlloc::layout)
+            .ok_or_else(unsupported)?;
+        self.classes.as_mut()[idx]
+            .alloc()
+            .map_err(|err| err.with_layout(layout).into())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let idx = class_for(self.classes.as_ref(), &layout, UntypedObjectAlloc::layout)
+            .expect("dealloc called with a layout not supported by any size class");
+        self.classes.as_mut()[idx].dealloc(ptr);
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        match class_for(self.classes.as_ref(), layout, UntypedObjectAlloc::layout) {
+            Some(idx) => {
+                (layout.size(), UntypedObjectAlloc::layout(&self.classes.as_ref()[idx]).size())
+            }
+            None => (layout.size(), layout.size()),
+        }
+    }
+}
+
+unsafe impl<'a, A: SharedUntypedObjectAlloc, C: AsRef<[A]>> Alloc for &'a SizeClassAlloc<A, C> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let classes = self.classes.as_ref();
+        let idx = class_for(classes, &layout, SharedUntypedObjectAlloc::layout)
+            .ok_or_else(unsupported)?;
+        SharedUntypedObjectAlloc::alloc(&classes[idx])
+            .map_err(|err| err.with_layout(layout).into())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let classes = self.classes.as_ref();
+        let idx = class_for(classes, &layout, SharedUntypedObjectAlloc::layout)
+            .expect("dealloc called with a layout not supported by any size class");
+        SharedUntypedObjectAlloc::dealloc(&classes[idx], ptr);
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        let classes = self.classes.as_ref();
+        match class_for(classes, layout, SharedUntypedObjectAlloc::layout) {
+            Some(idx) => (layout.size(), SharedUntypedObjectAlloc::layout(&classes[idx]).size()),
+            None => (layout.size(), layout.size()),
+        }
+    }
+}
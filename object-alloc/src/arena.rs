@@ -0,0 +1,101 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! An arena of objects allocated by an `ObjectAlloc`.
+
+use core::cell::{Cell, RefCell};
+use core::ptr;
+
+use {Error, ObjectAlloc};
+
+/// The object type allocated by the allocator of an `ObjectArena`.
+///
+/// An `ArenaObject` holds a `T` along with an intrusive link to the previously-allocated object in
+/// the same arena. An allocator for an `ObjectArena<T>` must allocate `ArenaObject<T>`s.
+pub struct ArenaObject<T> {
+    next: *mut ArenaObject<T>,
+    value: T,
+}
+
+impl<T> ArenaObject<T> {
+    /// Constructs a new `ArenaObject`.
+    ///
+    /// `new` is intended to be used as, or called from, an allocator's initialization function.
+    pub fn new(value: T) -> ArenaObject<T> {
+        ArenaObject {
+            next: ptr::null_mut(),
+            value: value,
+        }
+    }
+}
+
+impl<T: Default> Default for ArenaObject<T> {
+    fn default() -> ArenaObject<T> {
+        ArenaObject::new(T::default())
+    }
+}
+
+/// An arena which allocates objects from an `ObjectAlloc` and frees them all at once.
+///
+/// Objects allocated from an `ObjectArena` live as long as the arena itself, and are all returned
+/// to the underlying allocator when the arena is dropped. Allocated objects are kept on an
+/// intrusive linked list, so the arena requires no memory other than the objects themselves.
+pub struct ObjectArena<T, A: ObjectAlloc<ArenaObject<T>>> {
+    alloc: RefCell<A>,
+    head: Cell<*mut ArenaObject<T>>,
+}
+
+impl<T, A: ObjectAlloc<ArenaObject<T>>> ObjectArena<T, A> {
+    /// Constructs a new `ObjectArena` which allocates objects from `alloc`.
+    pub fn new(alloc: A) -> ObjectArena<T, A> {
+        ObjectArena {
+            alloc: RefCell::new(alloc),
+            head: Cell::new(ptr::null_mut()),
+        }
+    }
+
+    /// Allocates a new object.
+    ///
+    /// The state of the returned object is as described in the documentation for `alloc`.
+    #[cfg_attr(feature = "cargo-clippy", allow(mut_from_ref))]
+    pub fn alloc(&self) -> Result<&mut T, Error> {
+        unsafe {
+            let obj = self.alloc.borrow_mut().alloc()?;
+            (*obj).next = self.head.get();
+            self.head.set(obj);
+            Ok(&mut (*obj).value)
+        }
+    }
+
+    /// Returns all allocated objects to the allocator.
+    pub fn clear(&mut self) {
+        let mut alloc = self.alloc.borrow_mut();
+        let mut obj = self.head.get();
+        while !obj.is_null() {
+            unsafe {
+                let next = (*obj).next;
+                alloc.dealloc(obj);
+                obj = next;
+            }
+        }
+        self.head.set(ptr::null_mut());
+    }
+
+    /// Returns all allocated objects to the allocator and returns the allocator.
+    pub fn into_inner(mut self) -> A {
+        self.clear();
+        // Move the allocator out without running ObjectArena's Drop implementation.
+        let alloc = unsafe { ptr::read(&self.alloc) };
+        ::core::mem::forget(self);
+        alloc.into_inner()
+    }
+}
+
+impl<T, A: ObjectAlloc<ArenaObject<T>>> Drop for ObjectArena<T, A> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
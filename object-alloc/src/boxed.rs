@@ -0,0 +1,215 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! Smart pointers to objects allocated by an `ObjectAlloc`.
+//!
+//! The smart pointers in this module borrow their allocator through a `RefCell` so that many
+//! objects from the same allocator may be alive at once. The allocator is only borrowed for the
+//! duration of an allocation or deallocation. Thus, if an allocator drops an object during
+//! `dealloc`, and that object owns another smart pointer from the same allocator, the nested
+//! `dealloc` will panic.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+
+use {Error, ObjectAlloc};
+
+/// An owned object allocated by an `ObjectAlloc`.
+///
+/// An `ObjectBox` is like a `Box`, except that its object is allocated from - and returned to -
+/// an `ObjectAlloc` instead of the global heap. Since `ObjectAlloc`s may cache deallocated objects
+/// in a constructed state, the object will not necessarily be dropped when the `ObjectBox` is
+/// dropped.
+pub struct ObjectBox<'a, T: 'a, A: 'a + ObjectAlloc<T>> {
+    ptr: *mut T,
+    alloc: &'a RefCell<A>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, A: ObjectAlloc<T>> ObjectBox<'a, T, A> {
+    /// Allocates a new object from `alloc`.
+    ///
+    /// The state of the returned object is as described in the documentation for `alloc`.
+    pub fn new(alloc: &'a RefCell<A>) -> Result<ObjectBox<'a, T, A>, Error> {
+        let ptr = unsafe { alloc.borrow_mut().alloc()? };
+        Ok(ObjectBox {
+               ptr: ptr,
+               alloc: alloc,
+               _marker: PhantomData,
+           })
+    }
+
+    /// Constructs an `ObjectBox` from a raw pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by `alloc`, must not have been deallocated, and must not be
+    /// owned by any other `ObjectBox`.
+    pub unsafe fn from_raw(ptr: *mut T, alloc: &'a RefCell<A>) -> ObjectBox<'a, T, A> {
+        ObjectBox {
+            ptr: ptr,
+            alloc: alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Consumes the `ObjectBox`, returning the raw pointer to its object.
+    ///
+    /// The caller becomes responsible for returning the object to the allocator, either by calling
+    /// `dealloc` directly or by reconstructing an `ObjectBox` using `from_raw`.
+    pub fn into_raw(b: ObjectBox<'a, T, A>) -> *mut T {
+        let ptr = b.ptr;
+        mem::forget(b);
+        ptr
+    }
+
+    /// Returns the allocator from which the object was allocated.
+    pub fn allocator(b: &ObjectBox<'a, T, A>) -> &'a RefCell<A> {
+        b.alloc
+    }
+}
+
+impl<'a, T, A: ObjectAlloc<T>> Deref for ObjectBox<'a, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T, A: ObjectAlloc<T>> DerefMut for ObjectBox<'a, T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<'a, T, A: ObjectAlloc<T>> Drop for ObjectBox<'a, T, A> {
+    fn drop(&mut self) {
+        unsafe { self.alloc.borrow_mut().dealloc(self.ptr) }
+    }
+}
+
+impl<'a, T: Debug, A: ObjectAlloc<T>> Debug for ObjectBox<'a, T, A> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+/// The object type allocated by the allocators of `ObjectRc`s.
+///
+/// An `RcObject` holds a `T` along with its reference count. An allocator for `ObjectRc<T>`s must
+/// allocate `RcObject<T>`s.
+pub struct RcObject<T> {
+    refcnt: Cell<usize>,
+    value: T,
+}
+
+impl<T> RcObject<T> {
+    /// Constructs a new `RcObject`.
+    ///
+    /// `new` is intended to be used as, or called from, an allocator's initialization function.
+    pub fn new(value: T) -> RcObject<T> {
+        RcObject {
+            refcnt: Cell::new(0),
+            value: value,
+        }
+    }
+}
+
+impl<T: Default> Default for RcObject<T> {
+    fn default() -> RcObject<T> {
+        RcObject::new(T::default())
+    }
+}
+
+/// A reference-counted object allocated by an `ObjectAlloc`.
+///
+/// An `ObjectRc` is like an `Rc`, except that its object is allocated from - and returned to - an
+/// `ObjectAlloc` instead of the global heap. When the last `ObjectRc` pointing to an object is
+/// dropped, the object is returned to the allocator. Like `Rc`, `ObjectRc` does not support weak
+/// references, and only provides shared access to the object.
+pub struct ObjectRc<'a, T: 'a, A: 'a + ObjectAlloc<RcObject<T>>> {
+    ptr: *mut RcObject<T>,
+    alloc: &'a RefCell<A>,
+    _marker: PhantomData<RcObject<T>>,
+}
+
+impl<'a, T, A: ObjectAlloc<RcObject<T>>> ObjectRc<'a, T, A> {
+    /// Allocates a new object from `alloc`.
+    ///
+    /// The state of the returned object is as described in the documentation for `alloc`.
+    pub fn new(alloc: &'a RefCell<A>) -> Result<ObjectRc<'a, T, A>, Error> {
+        let ptr = unsafe { alloc.borrow_mut().alloc()? };
+        unsafe { (*ptr).refcnt.set(1) };
+        Ok(ObjectRc {
+               ptr: ptr,
+               alloc: alloc,
+               _marker: PhantomData,
+           })
+    }
+
+    /// Returns the number of `ObjectRc`s pointing to this object.
+    pub fn count(this: &ObjectRc<'a, T, A>) -> usize {
+        this.inner().refcnt.get()
+    }
+
+    /// Returns a mutable reference to the object if there are no other `ObjectRc`s pointing to it.
+    pub fn get_mut<'b>(this: &'b mut ObjectRc<'a, T, A>) -> Option<&'b mut T> {
+        if ObjectRc::count(this) == 1 {
+            Some(unsafe { &mut (*this.ptr).value })
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the two `ObjectRc`s point to the same object.
+    pub fn ptr_eq(this: &ObjectRc<'a, T, A>, other: &ObjectRc<'a, T, A>) -> bool {
+        this.ptr == other.ptr
+    }
+
+    fn inner(&self) -> &RcObject<T> {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T, A: ObjectAlloc<RcObject<T>>> Clone for ObjectRc<'a, T, A> {
+    fn clone(&self) -> ObjectRc<'a, T, A> {
+        let refcnt = &self.inner().refcnt;
+        refcnt.set(refcnt.get() + 1);
+        ObjectRc {
+            ptr: self.ptr,
+            alloc: self.alloc,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, A: ObjectAlloc<RcObject<T>>> Deref for ObjectRc<'a, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<'a, T, A: ObjectAlloc<RcObject<T>>> Drop for ObjectRc<'a, T, A> {
+    fn drop(&mut self) {
+        let refcnt = self.inner().refcnt.get() - 1;
+        self.inner().refcnt.set(refcnt);
+        if refcnt == 0 {
+            unsafe { self.alloc.borrow_mut().dealloc(self.ptr) }
+        }
+    }
+}
+
+impl<'a, T: Debug, A: ObjectAlloc<RcObject<T>>> Debug for ObjectRc<'a, T, A> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
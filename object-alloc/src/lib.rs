@@ -9,16 +9,136 @@
 #![feature(core_intrinsics)]
 
 extern crate alloc;
-use alloc::allocator::Layout;
+use alloc::allocator::{AllocErr, Layout};
+use core::fmt;
 use core::intrinsics::abort;
+use core::slice;
 
-/// An error indicating that no memory is available.
+pub mod adapter;
+pub mod arena;
+pub mod boxed;
+
+/// The kind of failure that caused an allocation request to fail.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// No memory is available.
+    ///
+    /// The request failed due to resources being unavailable - for example, the system is out of
+    /// address space or physical memory. It strongly implies that *some* sequence of deallocations
+    /// would allow a subsequent reissuing of the original allocation request to succeed.
+    Exhausted,
+
+    /// Satisfying the request would exceed a configured memory limit.
+    LimitExceeded,
+
+    /// The allocator does not support the requested `Layout`.
+    ///
+    /// Unlike the other kinds, an `Unsupported` failure will never succeed if retried.
+    Unsupported,
+
+    /// The failure was injected deliberately (e.g., by a testing allocator).
+    Injected,
+}
+
+/// An error indicating that an allocation request failed.
 ///
-/// The `Exhausted` error indicates that an allocation request has failed due to resources being
-/// unavailable. It strongly implies that *some* sequence of deallocations would allow a subsequent
-/// reissuing of the original allocation request to succeed.
+/// An `Error` describes why the request failed and the `Layout` of the object that was requested.
+/// It can be converted into an `AllocErr` using `From`, and constructed from an `AllocErr` using
+/// `from_alloc_err`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    layout: Layout,
+}
+
+impl Error {
+    /// Constructs a new `Error` of kind `kind` for a request for `layout`.
+    pub fn new(kind: ErrorKind, layout: Layout) -> Error {
+        Error {
+            kind: kind,
+            layout: layout,
+        }
+    }
+
+    /// Constructs a new `Error` of kind `ErrorKind::Exhausted` for a request for `layout`.
+    pub fn exhausted(layout: Layout) -> Error {
+        Error::new(ErrorKind::Exhausted, layout)
+    }
+
+    /// Constructs an `Error` from an `AllocErr` returned by a request for `layout`.
+    ///
+    /// `AllocErr::Exhausted` is converted to `ErrorKind::Exhausted`, and `AllocErr::Unsupported`
+    /// is converted to `ErrorKind::Unsupported`.
+    pub fn from_alloc_err(err: AllocErr, layout: Layout) -> Error {
+        let kind = match err {
+            AllocErr::Exhausted { .. } => ErrorKind::Exhausted,
+            AllocErr::Unsupported { .. } => ErrorKind::Unsupported,
+        };
+        Error::new(kind, layout)
+    }
+
+    /// Returns the kind of failure that caused the request to fail.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the `Layout` of the object that was requested.
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Returns a copy of this `Error` for a request for `layout`.
+    ///
+    /// `with_layout` is useful for allocators which make requests of other allocators and want to
+    /// report their own failures in terms of the original request.
+    pub fn with_layout(self, layout: Layout) -> Error {
+        Error::new(self.kind, layout)
+    }
+
+    fn description(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::Exhausted => "allocator memory exhausted",
+            ErrorKind::LimitExceeded => "allocator memory limit exceeded",
+            ErrorKind::Unsupported => "unsupported allocation layout",
+            ErrorKind::Injected => "injected allocation failure",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} (size {}, align {})",
+               self.description(),
+               self.layout.size(),
+               self.layout.align())
+    }
+}
+
+impl From<Error> for AllocErr {
+    /// Converts an `Error` into an `AllocErr`.
+    ///
+    /// `ErrorKind::Unsupported` is converted to `AllocErr::Unsupported`, and all other kinds are
+    /// converted to `AllocErr::Exhausted`.
+    fn from(err: Error) -> AllocErr {
+        match err.kind {
+            ErrorKind::Unsupported => AllocErr::Unsupported { details: err.description() },
+            _ => AllocErr::Exhausted { request: err.layout },
+        }
+    }
+}
+
+/// How much cached memory to release in a call to `reclaim`.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub struct Exhausted;
+pub enum Reclaim {
+    /// Release all cached memory.
+    All,
+    /// Release cached memory until at least this many bytes have been released, or until there
+    /// is no more cached memory to release.
+    AtLeast(usize),
+    /// Release cached memory until no more than this many bytes of cached memory remain.
+    KeepAtMost(usize),
+}
 
 /// Allocators which allocate objects of a particular type.
 ///
@@ -71,7 +191,7 @@ pub unsafe trait ObjectAlloc<T> {
     ///
     /// The memory returned by `alloc` is guaranteed to be aligned according to the requirements of
     /// `T` (that is, according to `core::mem::align_of::<T>()`).
-    unsafe fn alloc(&mut self) -> Result<*mut T, Exhausted>;
+    unsafe fn alloc(&mut self) -> Result<*mut T, Error>;
 
     /// Deallocates an object previously returned by `alloc`.
     ///
@@ -86,6 +206,57 @@ pub unsafe trait ObjectAlloc<T> {
     /// time in between.
     unsafe fn dealloc(&mut self, x: *mut T);
 
+    /// Allocates multiple objects of type `T`.
+    ///
+    /// `alloc_batch` attempts to allocate `out.len()` objects, storing pointers to them in `out`,
+    /// and returns the number of objects allocated. If `n` is returned, then `out[..n]` contains
+    /// pointers to newly-allocated objects, and the contents of `out[n..]` are unspecified. A
+    /// return value less than `out.len()` indicates that an allocation failed as if `alloc` had
+    /// returned an `Error`.
+    ///
+    /// Each allocated object is subject to the same guarantees as objects returned from `alloc`,
+    /// and may be deallocated using either `dealloc` or `dealloc_batch`.
+    ///
+    /// The default implementation simply calls `alloc` once for each element of `out`.
+    /// Implementations are encouraged to override it when objects can be allocated in bulk more
+    /// cheaply than they can be allocated one at a time.
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut T]) -> usize {
+        for (i, slot) in out.iter_mut().enumerate() {
+            match self.alloc() {
+                Ok(ptr) => *slot = ptr,
+                Err(_) => return i,
+            }
+        }
+        out.len()
+    }
+
+    /// Deallocates multiple objects previously returned by `alloc` or `alloc_batch`.
+    ///
+    /// `dealloc_batch` is equivalent to calling `dealloc` on each element of `objs`, and the same
+    /// requirements apply to each element. If any element of `objs` appears more than once, the
+    /// behavior of `dealloc_batch` is undefined.
+    ///
+    /// The default implementation simply calls `dealloc` once for each element of `objs`.
+    unsafe fn dealloc_batch(&mut self, objs: &[*mut T]) {
+        for &x in objs {
+            self.dealloc(x);
+        }
+    }
+
+    /// Releases cached memory back to the underlying allocator or system.
+    ///
+    /// `reclaim` releases memory that the allocator is holding in order to serve future
+    /// allocations - for example, empty slabs of cached objects - returning up to the amount
+    /// described by `amount`, and returns the number of bytes released. Any initialized objects in
+    /// the released memory are dropped. `reclaim` never affects objects which are currently
+    /// allocated. It is intended to be called in response to memory pressure.
+    ///
+    /// The default implementation releases nothing and returns 0.
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        let _ = amount;
+        0
+    }
+
     /// Allocator-specific method for signalling an out-of-memory condition.
     ///
     /// `oom` aborts the thread or process, optionally performing cleanup or logging diagnostic
@@ -127,7 +298,7 @@ pub unsafe trait UntypedObjectAlloc {
     ///
     /// The memory returned by `alloc` is guaranteed to abide by the `Layout` returned from
     /// `layout`.
-    unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted>;
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error>;
 
     /// Deallocates an object previously returned by `alloc`.
     ///
@@ -135,6 +306,48 @@ pub unsafe trait UntypedObjectAlloc {
     /// the behavior of `dealloc` is undefined.
     unsafe fn dealloc(&mut self, x: *mut u8);
 
+    /// Allocates multiple objects.
+    ///
+    /// `alloc_batch` attempts to allocate `out.len()` objects, storing pointers to them in `out`,
+    /// and returns the number of objects allocated. If `n` is returned, then `out[..n]` contains
+    /// pointers to newly-allocated objects, and the contents of `out[n..]` are unspecified. A
+    /// return value less than `out.len()` indicates that an allocation failed as if `alloc` had
+    /// returned an `Error`.
+    ///
+    /// The default implementation simply calls `alloc` once for each element of `out`.
+    /// Implementations are encouraged to override it when objects can be allocated in bulk more
+    /// cheaply than they can be allocated one at a time.
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        for (i, slot) in out.iter_mut().enumerate() {
+            match self.alloc() {
+                Ok(ptr) => *slot = ptr,
+                Err(_) => return i,
+            }
+        }
+        out.len()
+    }
+
+    /// Deallocates multiple objects previously returned by `alloc` or `alloc_batch`.
+    ///
+    /// `dealloc_batch` is equivalent to calling `dealloc` on each element of `objs`, and the same
+    /// requirements apply to each element. If any element of `objs` appears more than once, the
+    /// behavior of `dealloc_batch` is undefined.
+    ///
+    /// The default implementation simply calls `dealloc` once for each element of `objs`.
+    unsafe fn dealloc_batch(&mut self, objs: &[*mut u8]) {
+        for &x in objs {
+            self.dealloc(x);
+        }
+    }
+
+    /// Releases cached memory back to the underlying allocator or system.
+    ///
+    /// The semantics of `reclaim` are the same as those of `ObjectAlloc::reclaim`.
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        let _ = amount;
+        0
+    }
+
     /// Allocator-specific method for signalling an out-of-memory condition.
     ///
     /// `oom` aborts the thread or process, optionally performing cleanup or logging diagnostic
@@ -157,6 +370,248 @@ pub unsafe trait UntypedObjectAlloc {
     }
 }
 
+/// Allocators which allocate objects of a particular type and which may be used concurrently from
+/// multiple threads.
+///
+/// `SharedObjectAlloc` is like `ObjectAlloc`, except that its methods take `&self` instead of
+/// `&mut self`, and implementors are required to be `Sync`. This allows a single allocator to be
+/// shared by reference between multiple threads without the caller wrapping it in a lock or
+/// cloning it once per thread. Implementors are responsible for their own synchronization, which
+/// may range from per-thread caches to sharded locks. All of the initialization, dropping, and
+/// safety requirements of `ObjectAlloc` apply equally to `SharedObjectAlloc`.
+///
+/// Objects may be deallocated by a different thread than the one that allocated them.
+///
+/// If `A` implements `SharedObjectAlloc<T>`, then `&A` implements both `SharedObjectAlloc<T>` and
+/// `ObjectAlloc<T>`, so a reference to a shared allocator can be passed anywhere an `ObjectAlloc`
+/// is expected.
+pub unsafe trait SharedObjectAlloc<T>: Sync {
+    /// Allocates an object of type `T`.
+    ///
+    /// The semantics of `alloc` are the same as those of `ObjectAlloc::alloc`.
+    unsafe fn alloc(&self) -> Result<*mut T, Error>;
+
+    /// Deallocates an object previously returned by `alloc`.
+    ///
+    /// The semantics of `dealloc` are the same as those of `ObjectAlloc::dealloc`. `x` need not
+    /// have been allocated by the calling thread.
+    unsafe fn dealloc(&self, x: *mut T);
+
+    /// Allocates multiple objects of type `T`.
+    ///
+    /// The semantics of `alloc_batch` are the same as those of `ObjectAlloc::alloc_batch`.
+    unsafe fn alloc_batch(&self, out: &mut [*mut T]) -> usize {
+        for (i, slot) in out.iter_mut().enumerate() {
+            match self.alloc() {
+                Ok(ptr) => *slot = ptr,
+                Err(_) => return i,
+            }
+        }
+        out.len()
+    }
+
+    /// Deallocates multiple objects previously returned by `alloc` or `alloc_batch`.
+    ///
+    /// The semantics of `dealloc_batch` are the same as those of `ObjectAlloc::dealloc_batch`.
+    unsafe fn dealloc_batch(&self, objs: &[*mut T]) {
+        for &x in objs {
+            self.dealloc(x);
+        }
+    }
+
+    /// Releases cached memory back to the underlying allocator or system.
+    ///
+    /// The semantics of `reclaim` are the same as those of `ObjectAlloc::reclaim`.
+    fn reclaim(&self, amount: Reclaim) -> usize {
+        let _ = amount;
+        0
+    }
+
+    /// Allocator-specific method for signalling an out-of-memory condition.
+    ///
+    /// The semantics of `oom` are the same as those of `ObjectAlloc::oom`.
+    fn oom(&self) -> ! {
+        unsafe { abort() }
+    }
+}
+
+/// An allocator for objects whose type or size is not known at compile time, and which may be
+/// used concurrently from multiple threads.
+///
+/// `SharedUntypedObjectAlloc` is to `UntypedObjectAlloc` as `SharedObjectAlloc` is to
+/// `ObjectAlloc`: its methods take `&self` instead of `&mut self`, and implementors are required
+/// to be `Sync`.
+///
+/// If `A` implements `SharedUntypedObjectAlloc`, then `&A` implements both
+/// `SharedUntypedObjectAlloc` and `UntypedObjectAlloc`.
+pub unsafe trait SharedUntypedObjectAlloc: Sync {
+    /// Obtains the `Layout` of allocated objects.
+    ///
+    /// The semantics of `layout` are the same as those of `UntypedObjectAlloc::layout`.
+    fn layout(&self) -> Layout;
+
+    /// Allocates an object.
+    ///
+    /// The semantics of `alloc` are the same as those of `UntypedObjectAlloc::alloc`.
+    unsafe fn alloc(&self) -> Result<*mut u8, Error>;
+
+    /// Deallocates an object previously returned by `alloc`.
+    ///
+    /// The semantics of `dealloc` are the same as those of `UntypedObjectAlloc::dealloc`. `x` need
+    /// not have been allocated by the calling thread.
+    unsafe fn dealloc(&self, x: *mut u8);
+
+    /// Allocates multiple objects.
+    ///
+    /// The semantics of `alloc_batch` are the same as those of `UntypedObjectAlloc::alloc_batch`.
+    unsafe fn alloc_batch(&self, out: &mut [*mut u8]) -> usize {
+        for (i, slot) in out.iter_mut().enumerate() {
+            match self.alloc() {
+                Ok(ptr) => *slot = ptr,
+                Err(_) => return i,
+            }
+        }
+        out.len()
+    }
+
+    /// Deallocates multiple objects previously returned by `alloc` or `alloc_batch`.
+    ///
+    /// The semantics of `dealloc_batch` are the same as those of
+    /// `UntypedObjectAlloc::dealloc_batch`.
+    unsafe fn dealloc_batch(&self, objs: &[*mut u8]) {
+        for &x in objs {
+            self.dealloc(x);
+        }
+    }
+
+    /// Releases cached memory back to the underlying allocator or system.
+    ///
+    /// The semantics of `reclaim` are the same as those of `UntypedObjectAlloc::reclaim`.
+    fn reclaim(&self, amount: Reclaim) -> usize {
+        let _ = amount;
+        0
+    }
+
+    /// Allocator-specific method for signalling an out-of-memory condition.
+    ///
+    /// The semantics of `oom` are the same as those of `UntypedObjectAlloc::oom`.
+    fn oom(&self) -> ! {
+        unsafe { abort() }
+    }
+}
+
+unsafe impl<'a, T, A: SharedObjectAlloc<T> + ?Sized> SharedObjectAlloc<T> for &'a A {
+    unsafe fn alloc(&self) -> Result<*mut T, Error> {
+        SharedObjectAlloc::alloc(*self)
+    }
+
+    unsafe fn dealloc(&self, x: *mut T) {
+        SharedObjectAlloc::dealloc(*self, x);
+    }
+
+    unsafe fn alloc_batch(&self, out: &mut [*mut T]) -> usize {
+        SharedObjectAlloc::alloc_batch(*self, out)
+    }
+
+    unsafe fn dealloc_batch(&self, objs: &[*mut T]) {
+        SharedObjectAlloc::dealloc_batch(*self, objs);
+    }
+
+    fn reclaim(&self, amount: Reclaim) -> usize {
+        SharedObjectAlloc::reclaim(*self, amount)
+    }
+
+    fn oom(&self) -> ! {
+        SharedObjectAlloc::oom(*self)
+    }
+}
+
+unsafe impl<'a, T, A: SharedObjectAlloc<T> + ?Sized> ObjectAlloc<T> for &'a A {
+    unsafe fn alloc(&mut self) -> Result<*mut T, Error> {
+        SharedObjectAlloc::alloc(*self)
+    }
+
+    unsafe fn dealloc(&mut self, x: *mut T) {
+        SharedObjectAlloc::dealloc(*self, x);
+    }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut T]) -> usize {
+        SharedObjectAlloc::alloc_batch(*self, out)
+    }
+
+    unsafe fn dealloc_batch(&mut self, objs: &[*mut T]) {
+        SharedObjectAlloc::dealloc_batch(*self, objs);
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        SharedObjectAlloc::reclaim(*self, amount)
+    }
+
+    fn oom(&mut self) -> ! {
+        SharedObjectAlloc::oom(*self)
+    }
+}
+
+unsafe impl<'a, A: SharedUntypedObjectAlloc + ?Sized> SharedUntypedObjectAlloc for &'a A {
+    fn layout(&self) -> Layout {
+        SharedUntypedObjectAlloc::layout(*self)
+    }
+
+    unsafe fn alloc(&self) -> Result<*mut u8, Error> {
+        SharedUntypedObjectAlloc::alloc(*self)
+    }
+
+    unsafe fn dealloc(&self, x: *mut u8) {
+        SharedUntypedObjectAlloc::dealloc(*self, x);
+    }
+
+    unsafe fn alloc_batch(&self, out: &mut [*mut u8]) -> usize {
+        SharedUntypedObjectAlloc::alloc_batch(*self, out)
+    }
+
+    unsafe fn dealloc_batch(&self, objs: &[*mut u8]) {
+        SharedUntypedObjectAlloc::dealloc_batch(*self, objs);
+    }
+
+    fn reclaim(&self, amount: Reclaim) -> usize {
+        SharedUntypedObjectAlloc::reclaim(*self, amount)
+    }
+
+    fn oom(&self) -> ! {
+        SharedUntypedObjectAlloc::oom(*self)
+    }
+}
+
+unsafe impl<'a, A: SharedUntypedObjectAlloc + ?Sized> UntypedObjectAlloc for &'a A {
+    fn layout(&self) -> Layout {
+        SharedUntypedObjectAlloc::layout(*self)
+    }
+
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
+        SharedUntypedObjectAlloc::alloc(*self)
+    }
+
+    unsafe fn dealloc(&mut self, x: *mut u8) {
+        SharedUntypedObjectAlloc::dealloc(*self, x);
+    }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        SharedUntypedObjectAlloc::alloc_batch(*self, out)
+    }
+
+    unsafe fn dealloc_batch(&mut self, objs: &[*mut u8]) {
+        SharedUntypedObjectAlloc::dealloc_batch(*self, objs);
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        SharedUntypedObjectAlloc::reclaim(*self, amount)
+    }
+
+    fn oom(&mut self) -> ! {
+        SharedUntypedObjectAlloc::oom(*self)
+    }
+}
+
 unsafe impl<T> UntypedObjectAlloc for ObjectAlloc<T> {
     fn layout(&self) -> Layout {
         // NOTE: This is safe because the layout method doesn't guarantee that it provides the most
@@ -166,11 +621,59 @@ unsafe impl<T> UntypedObjectAlloc for ObjectAlloc<T> {
         Layout::new::<T>()
     }
 
-    unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
         ObjectAlloc::alloc(self).map(|x| x as *mut u8)
     }
 
     unsafe fn dealloc(&mut self, x: *mut u8) {
         ObjectAlloc::dealloc(self, x as *mut T);
     }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        // NOTE: This is safe because *mut u8 and *mut T have the same size and representation.
+        let out = slice::from_raw_parts_mut(out.as_mut_ptr() as *mut *mut T, out.len());
+        ObjectAlloc::alloc_batch(self, out)
+    }
+
+    unsafe fn dealloc_batch(&mut self, objs: &[*mut u8]) {
+        let objs = slice::from_raw_parts(objs.as_ptr() as *const *mut T, objs.len());
+        ObjectAlloc::dealloc_batch(self, objs);
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        ObjectAlloc::reclaim(self, amount)
+    }
+}
+
+/// A snapshot of an allocator's memory usage.
+///
+/// Allocators which cannot track a particular statistic report it as zero, and should document
+/// which statistics they report.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Stats {
+    /// The number of objects that have been allocated and not yet deallocated.
+    pub live_objects: usize,
+    /// The number of objects held by the allocator which can be used to serve future allocations
+    /// without obtaining more memory.
+    pub cached_objects: usize,
+    /// The number of bytes of backing memory obtained from the underlying allocator or system.
+    pub bytes_mapped: usize,
+    /// The number of bytes of backing memory that are known to be committed (that is, backed by
+    /// physical memory). This is never greater than `bytes_mapped`.
+    pub bytes_committed: usize,
+    /// The number of slabs or pages of backing memory.
+    pub slabs: usize,
+}
+
+/// Allocators which can report statistics about their memory usage.
+///
+/// `AllocatorStats` is intended for monitoring, and in particular for determining how much memory
+/// is held by an allocator's caches (`cached_objects`) rather than by its clients
+/// (`live_objects`).
+pub trait AllocatorStats {
+    /// Returns a snapshot of this allocator's memory usage.
+    ///
+    /// For allocators which may be used concurrently, the individual statistics may not be
+    /// consistent with one another.
+    fn stats(&self) -> Stats;
 }
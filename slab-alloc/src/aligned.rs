@@ -25,35 +25,74 @@
 extern crate alloc;
 extern crate object_alloc;
 
-use {OBJECTS_PER_SLAB, PAGE_SIZE, stack};
+use {Config, stack};
 use stack::{SlabHeader, Layout};
 use init::InitSystem;
+use util::ptrmap::*;
 use self::alloc::allocator;
 use self::object_alloc::UntypedObjectAlloc;
 
-pub struct ConfigData;
+pub struct ConfigData {
+    // The set of slabs currently allocated. It is only used to answer ownership queries about
+    // arbitrary pointers - ptr_to_slab never consults it.
+    slabs: Map<u8, SlabHeader>,
+}
 
 impl stack::ConfigData for ConfigData {
+    fn post_alloc(&mut self, _layout: &Layout, _slab_size: usize, slab: *mut SlabHeader) {
+        self.slabs.insert(slab as *mut u8, slab);
+    }
+
+    fn pre_dealloc(&mut self, _layout: &Layout, _slab_size: usize, slab: *mut SlabHeader) {
+        self.slabs.delete(slab as *mut u8);
+    }
+
     fn ptr_to_slab(&self, slab_size: usize, ptr: *mut u8) -> *mut SlabHeader {
         let slab = ptr as usize & !(slab_size - 1);
         debug_assert_eq!(slab % slab_size, 0);
         slab as *mut SlabHeader
     }
+
+    fn try_ptr_to_slab(&self, slab_size: usize, ptr: *mut u8) -> Option<*mut SlabHeader> {
+        self.slabs
+            .try_get(self.ptr_to_slab(slab_size, ptr) as *mut u8)
+    }
 }
 
 pub type System<A> = stack::System<A, ConfigData>;
 
+/// The initial size of the map of allocated slabs.
+const SLAB_MAP_SIZE: usize = 16;
+
 impl<A: UntypedObjectAlloc> System<A> {
-    pub fn new(layout: allocator::Layout, alloc: A) -> Option<System<A>> {
-        if let Some((slab_layout, _)) = Layout::for_slab_size(layout, alloc.layout().size()) {
-            Some(Self::from_config_data(ConfigData, slab_layout, alloc))
+    pub fn new(layout: allocator::Layout, alloc: A, config: &Config) -> Option<System<A>> {
+        let slab_size = alloc.layout().size();
+        if let Some((mut slab_layout, _)) = Layout::for_slab_size(layout, slab_size, config.debug) {
+            if !config.coloring {
+                slab_layout.disable_coloring();
+            }
+            let data = ConfigData { slabs: Map::new(SLAB_MAP_SIZE, slab_size) };
+            Some(Self::from_config_data(data, slab_layout, alloc))
         } else {
             None
         }
     }
 }
 
-pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout) -> usize {
+/// Choose the size of aligned slabs for objects of the given layout.
+///
+/// `backing_size_for` returns `None` if `config` requires a slab size which aligned slabs cannot
+/// use.
+pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout,
+                                       config: &Config)
+                                       -> Option<usize> {
+    match config.slab_size {
+        Some(size) if size.is_power_of_two() => return Some(size),
+        // aligned slabs must be aligned to their size, which must therefore be a power of two
+        Some(_) => return None,
+        None => {}
+    }
+
     struct PowerOfTwoIterator(usize);
 
     impl Iterator for PowerOfTwoIterator {
@@ -71,13 +110,24 @@ pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout) -> usize {
     }
 
     let unused = |slab_size: usize| {
-        Layout::for_slab_size(layout.clone(), slab_size).map(|(layout, unused)| {
-                                                                 (layout.num_obj, unused)
-                                                             })
+        Layout::for_slab_size(layout.clone(), slab_size, config.debug)
+            .map(|(layout, unused)| (layout.num_obj, unused))
     };
 
+    if config.prefer_aligned {
+        // Backing allocators are only required to support page-sized aligned slabs, so rather than
+        // optimizing for space usage, use the smallest size that fits at least one object.
+        let mut size = config.page_size;
+        while unused(size).is_none() {
+            size = size.checked_mul(2).expect("object too large for an aligned slab");
+        }
+        return Some(size);
+    }
+
     // We guarantee that we never request aligned slabs smaller than a page (see the Documentation
-    // on SlabAllocBuilder::build_backing), so we start off with an initial size of *PAGE_SIZE and
-    // go up from there.
-    ::util::size::choose_size(PowerOfTwoIterator(*PAGE_SIZE), unused, OBJECTS_PER_SLAB)
+    // on SlabAllocBuilder::build_backing), so we start off with an initial size of the page size
+    // and go up from there.
+    Some(::util::size::choose_size(PowerOfTwoIterator(config.page_size),
+                                   unused,
+                                   config.objects_per_slab))
 }
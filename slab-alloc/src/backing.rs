@@ -4,8 +4,6 @@
 // Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
 // may not be copied, modified, or distributed except according to those terms.
 
-// don't conflict with the alloc module
-extern crate alloc as crate_alloc;
 extern crate object_alloc;
 
 use self::object_alloc::UntypedObjectAlloc;
@@ -34,55 +32,15 @@ pub trait BackingAlloc {
     type Large: UntypedObjectAlloc;
 }
 
-/// An `UntypedObjectAlloc` that uses arbitrary allocators.
-pub mod alloc {
-    extern crate alloc;
-    extern crate object_alloc;
-    use self::alloc::allocator::{Alloc, AllocErr, Layout};
-    use self::object_alloc::{Exhausted, UntypedObjectAlloc};
-
-    /// An `UntypedObjectAlloc` that uses an arbitrary allocator.
-    #[derive(Clone)]
-    pub struct AllocObjectAlloc<A: Alloc> {
-        alloc: A,
-        layout: Layout,
-    }
-
-    impl<A: Alloc> AllocObjectAlloc<A> {
-        pub fn new(alloc: A, layout: Layout) -> AllocObjectAlloc<A> {
-            AllocObjectAlloc { alloc, layout }
-        }
-    }
-
-    unsafe impl<A: Alloc> UntypedObjectAlloc for AllocObjectAlloc<A> {
-        fn layout(&self) -> Layout {
-            self.layout.clone()
-        }
-
-        unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
-            match self.alloc.alloc(self.layout.clone()) {
-                Ok(ptr) => Ok(ptr),
-                Err(AllocErr::Exhausted { .. }) => Err(Exhausted),
-                Err(AllocErr::Unsupported { details }) => {
-                    unreachable!("unexpected unsupported alloc: {}", details)
-                }
-            }
-        }
-
-        unsafe fn dealloc(&mut self, ptr: *mut u8) {
-            self.alloc.dealloc(ptr, self.layout.clone());
-        }
-    }
-}
-
 /// A `BackingAlloc` that uses the heap.
 #[cfg(feature = "std")]
 pub mod heap {
     extern crate alloc;
+    extern crate sysconf;
     use self::alloc::heap::{Heap, Layout};
-    use super::alloc::AllocObjectAlloc;
+    use self::sysconf::page::pagesize;
+    use object_alloc::adapter::AllocObjectAlloc;
     use super::BackingAlloc;
-    use PAGE_SIZE;
 
     pub struct HeapBackingAlloc;
 
@@ -92,7 +50,7 @@ pub mod heap {
     }
 
     pub fn get_aligned(layout: Layout) -> Option<AllocObjectAlloc<Heap>> {
-        if layout.size() > *PAGE_SIZE {
+        if layout.size() > pagesize() {
             None
         } else {
             Some(AllocObjectAlloc::new(Heap, layout))
@@ -115,9 +73,9 @@ pub mod mmap {
     use self::mmap_alloc::{MapAlloc, MapAllocBuilder};
     #[cfg(not(target_os = "linux"))]
     use self::mmap_alloc::MapAlloc;
-    use super::alloc::AllocObjectAlloc;
+    use self::sysconf::page::pagesize;
+    use object_alloc::adapter::AllocObjectAlloc;
     use super::BackingAlloc;
-    use PAGE_SIZE;
 
     pub struct MmapBackingAlloc;
 
@@ -128,7 +86,7 @@ pub mod mmap {
 
     pub fn get_aligned(layout: Layout) -> Option<AllocObjectAlloc<MapAlloc>> {
         debug_assert_eq!(layout.size(), layout.align());
-        if layout.size() != *PAGE_SIZE {
+        if layout.size() != pagesize() {
             #[cfg(target_os = "linux")]
             {
                 if self::sysconf::page::hugepage_supported(layout.size()) {
@@ -148,7 +106,246 @@ pub mod mmap {
     }
 
     pub fn get_large(layout: Layout) -> AllocObjectAlloc<MapAlloc> {
-        debug_assert!(layout.align() <= *PAGE_SIZE);
+        debug_assert!(layout.align() <= pagesize());
         AllocObjectAlloc::new(MapAlloc::default(), layout)
     }
 }
+
+/// A `BackingAlloc` that carves slabs out of a fixed region of memory.
+///
+/// `StaticRegionBackingAlloc` requires neither the `std` nor the `os` feature, and is intended for
+/// environments such as kernels which have no operating system to request memory from. All of an
+/// allocator's slabs are allocated from a single region provided by the user (see
+/// `SlabAllocBuilder::build_static`). Slabs are allocated by bumping a pointer through the region,
+/// and freed slabs are kept on a free list to be reused; since all of an allocator's slabs are the
+/// same size, no memory is lost to fragmentation. Memory is never returned from the region, and
+/// once the region is exhausted, allocating a new slab fails.
+pub mod static_region {
+    extern crate alloc;
+    use core::cell::Cell;
+    use core::ptr;
+    use self::alloc::allocator::Layout;
+    use object_alloc::{Error, UntypedObjectAlloc};
+    use super::BackingAlloc;
+
+    pub struct StaticRegionBackingAlloc;
+
+    impl BackingAlloc for StaticRegionBackingAlloc {
+        type Aligned = RegionAlloc;
+        type Large = RegionAlloc;
+    }
+
+    /// An `UntypedObjectAlloc` which allocates slabs from a fixed region of memory.
+    pub struct RegionAlloc {
+        layout: Layout,
+        // the first byte of the region which has never been allocated
+        next: usize,
+        end: usize,
+        // a stack of freed slabs, linked through their first word
+        free: *mut FreeSlab,
+    }
+
+    struct FreeSlab {
+        next: *mut FreeSlab,
+    }
+
+    // RegionAlloc has exclusive access to its region.
+    unsafe impl Send for RegionAlloc {}
+
+    impl RegionAlloc {
+        fn new(region: &'static mut [u8], layout: Layout) -> RegionAlloc {
+            debug_assert!(layout.size() >= ::core::mem::size_of::<FreeSlab>());
+            let begin = region.as_mut_ptr() as usize;
+            RegionAlloc {
+                layout: layout,
+                next: begin,
+                end: begin + region.len(),
+                free: ptr::null_mut(),
+            }
+        }
+
+    }
+
+    /// Returns the address of the first slab with the given layout which fits entirely within the
+    /// range `[begin, end)`, if any.
+    fn first_slab(begin: usize, end: usize, layout: &Layout) -> Option<usize> {
+        let align = layout.align();
+        let slab = match begin.checked_add(align - 1) {
+            Some(begin) => begin & !(align - 1),
+            None => return None,
+        };
+        if slab <= end && end - slab >= layout.size() {
+            Some(slab)
+        } else {
+            None
+        }
+    }
+
+    unsafe impl UntypedObjectAlloc for RegionAlloc {
+        fn layout(&self) -> Layout {
+            self.layout.clone()
+        }
+
+        unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
+            if !self.free.is_null() {
+                let slab = self.free;
+                self.free = (*slab).next;
+                return Ok(slab as *mut u8);
+            }
+            match first_slab(self.next, self.end, &self.layout) {
+                Some(slab) => {
+                    self.next = slab + self.layout.size();
+                    Ok(slab as *mut u8)
+                }
+                None => Err(Error::exhausted(self.layout.clone())),
+            }
+        }
+
+        unsafe fn dealloc(&mut self, x: *mut u8) {
+            let slab = x as *mut FreeSlab;
+            (*slab).next = self.free;
+            self.free = slab;
+        }
+    }
+
+    /// Gets an allocator for aligned slabs from `region`.
+    ///
+    /// If `region` is too small to hold a single slab of the requested size and alignment, it is
+    /// left in place and `None` is returned.
+    pub fn get_aligned(region: &Cell<Option<&'static mut [u8]>>,
+                       layout: Layout)
+                       -> Option<RegionAlloc> {
+        let mem = region.take().expect("static region already in use");
+        let begin = mem.as_ptr() as usize;
+        if first_slab(begin, begin + mem.len(), &layout).is_some() {
+            Some(RegionAlloc::new(mem, layout))
+        } else {
+            region.set(Some(mem));
+            None
+        }
+    }
+
+    pub fn get_large(region: &Cell<Option<&'static mut [u8]>>, layout: Layout) -> RegionAlloc {
+        RegionAlloc::new(region.take().expect("static region already in use"), layout)
+    }
+}
+
+/// A `BackingAlloc` that derives aligned slabs from an allocator of page-aligned memory.
+///
+/// Many sources of memory - memory mappings, for example - can only guarantee page alignment,
+/// which is enough for large slabs but not for aligned slabs. `ArenaBackingAlloc` allocates large
+/// slabs directly from such a source, and allocates aligned slabs by allocating arenas from the
+/// source which are large enough to hold several aligned slabs no matter where the first aligned
+/// address in the arena falls, and carving slabs out of them (see
+/// `SlabAllocBuilder::build_arena`).
+///
+/// An arena can only be returned to the source all at once, so arenas are kept until the
+/// allocator is dropped. Aligned slabs freed in the meantime are reused.
+pub mod arena {
+    extern crate alloc;
+    use core::marker::PhantomData;
+    use core::ptr;
+    #[cfg(not(feature = "std"))]
+    use self::alloc::vec::Vec;
+    use self::alloc::allocator::Layout;
+    use object_alloc::{Error, UntypedObjectAlloc};
+    use super::BackingAlloc;
+
+    /// The number of aligned slabs carved out of each arena.
+    const ARENA_SLABS: usize = 8;
+
+    pub struct ArenaBackingAlloc<A: UntypedObjectAlloc>(PhantomData<A>);
+
+    impl<A: UntypedObjectAlloc> BackingAlloc for ArenaBackingAlloc<A> {
+        type Aligned = AlignedArenaAlloc<A>;
+        type Large = A;
+    }
+
+    /// An `UntypedObjectAlloc` which carves aligned slabs out of arenas of page-aligned memory.
+    pub struct AlignedArenaAlloc<A: UntypedObjectAlloc> {
+        layout: Layout,
+        alloc: A,
+        arenas: Vec<*mut u8>,
+        // the range of the most recently allocated arena out of which slabs have not been carved
+        next: usize,
+        end: usize,
+        // a stack of freed slabs, linked through their first word
+        free: *mut FreeSlab,
+    }
+
+    struct FreeSlab {
+        next: *mut FreeSlab,
+    }
+
+    // AlignedArenaAlloc has exclusive access to its arenas.
+    unsafe impl<A: UntypedObjectAlloc + Send> Send for AlignedArenaAlloc<A> {}
+
+    unsafe impl<A: UntypedObjectAlloc> UntypedObjectAlloc for AlignedArenaAlloc<A> {
+        fn layout(&self) -> Layout {
+            self.layout.clone()
+        }
+
+        unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
+            if !self.free.is_null() {
+                let slab = self.free;
+                self.free = (*slab).next;
+                return Ok(slab as *mut u8);
+            }
+
+            let size = self.layout.size();
+            if self.end - self.next < size {
+                let arena = match self.alloc.alloc() {
+                    Ok(arena) => arena,
+                    Err(err) => return Err(err.with_layout(self.layout.clone())),
+                };
+                self.arenas.push(arena);
+                self.next = (arena as usize + size - 1) & !(size - 1);
+                self.end = arena as usize + self.alloc.layout().size();
+                debug_assert!(self.end - self.next >= ARENA_SLABS * size);
+            }
+            let slab = self.next;
+            self.next += size;
+            Ok(slab as *mut u8)
+        }
+
+        unsafe fn dealloc(&mut self, x: *mut u8) {
+            let slab = x as *mut FreeSlab;
+            (*slab).next = self.free;
+            self.free = slab;
+        }
+    }
+
+    impl<A: UntypedObjectAlloc> Drop for AlignedArenaAlloc<A> {
+        fn drop(&mut self) {
+            for &arena in &self.arenas {
+                unsafe { self.alloc.dealloc(arena) };
+            }
+        }
+    }
+
+    /// Gets an allocator for aligned slabs of the given layout.
+    ///
+    /// `get_large` is called with the layout of the arenas, which is aligned to `page_size`.
+    pub fn get_aligned<A, F>(get_large: &F,
+                             layout: Layout,
+                             page_size: usize)
+                             -> AlignedArenaAlloc<A>
+        where A: UntypedObjectAlloc,
+              F: Fn(Layout) -> A
+    {
+        let size = layout.size();
+        debug_assert_eq!(size, layout.align());
+        debug_assert!(size >= page_size);
+        // Since the arena is page-aligned, the first size-aligned address in the arena is at most
+        // size - page_size bytes from its beginning.
+        let arena_size = ARENA_SLABS * size + (size - page_size);
+        AlignedArenaAlloc {
+            layout: layout,
+            alloc: get_large(Layout::from_size_align(arena_size, page_size).unwrap()),
+            arenas: Vec::new(),
+            next: 0,
+            end: 0,
+            free: ptr::null_mut(),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! Named, per-type object caches.
+//!
+//! In the original slab allocator, each kernel subsystem creates a cache for each of its object
+//! types once with `kmem_cache_create`, and then allocates and frees objects from it anywhere in
+//! its code. The `slab_cache!` macro provides the same style of use: it declares a static
+//! `SlabCache`, from which objects can be allocated without threading a `&mut SlabAlloc` through
+//! the code that uses them.
+
+use core::cell::RefCell;
+use core::fmt::{self, Debug, Formatter};
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use std::thread::LocalKey;
+
+use object_alloc::{Error, ObjectAlloc, Reclaim};
+
+/// Declares one or more static `SlabCache`s.
+///
+/// Each cache is declared as `static NAME: T = init;`, optionally preceded by `pub` and by
+/// attributes. `init` is an expression evaluating to an `ObjectAlloc<T>` (usually a `SlabAlloc`),
+/// which is evaluated once on each thread that uses the cache.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// slab_cache! {
+///     static NODES: Node = SlabAllocBuilder::default().build();
+/// }
+///
+/// let node = NODES.alloc_box().unwrap();
+/// ```
+#[macro_export]
+macro_rules! slab_cache {
+    () => ();
+    ($(#[$attr:meta])* static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => (
+        __slab_cache_inner!($(#[$attr])* [] $name, $t, $init);
+        slab_cache!($($rest)*);
+    );
+    ($(#[$attr:meta])* static $name:ident: $t:ty = $init:expr) => (
+        __slab_cache_inner!($(#[$attr])* [] $name, $t, $init);
+    );
+    ($(#[$attr:meta])* pub static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => (
+        __slab_cache_inner!($(#[$attr])* [pub] $name, $t, $init);
+        slab_cache!($($rest)*);
+    );
+    ($(#[$attr:meta])* pub static $name:ident: $t:ty = $init:expr) => (
+        __slab_cache_inner!($(#[$attr])* [pub] $name, $t, $init);
+    );
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __slab_cache_inner {
+    ($(#[$attr:meta])* [$($vis:tt)*] $name:ident, $t:ty, $init:expr) => (
+        $(#[$attr])*
+        $($vis)* static $name: $crate::SlabCache<$t> = {
+            thread_local!(static LOCAL: $crate::__SlabCacheLocal<$t> =
+                              $crate::__SlabCacheLocal::new($init));
+            $crate::SlabCache { __local: &LOCAL }
+        };
+    );
+}
+
+/// A thread's instance of a cache's allocator.
+#[doc(hidden)]
+pub struct __SlabCacheLocal<T: 'static> {
+    alloc: RefCell<Box<ObjectAlloc<T>>>,
+    // objects freed while alloc was already borrowed, which are freed once it is released
+    deferred: RefCell<Vec<*mut T>>,
+}
+
+impl<T: 'static> __SlabCacheLocal<T> {
+    pub fn new<A: ObjectAlloc<T> + 'static>(alloc: A) -> __SlabCacheLocal<T> {
+        __SlabCacheLocal {
+            alloc: RefCell::new(Box::new(alloc)),
+            deferred: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn with<R, F: FnOnce(&mut ObjectAlloc<T>) -> R>(&self, f: F) -> R {
+        let ret = f(&mut **self.alloc.borrow_mut());
+        self.free_deferred();
+        ret
+    }
+
+    unsafe fn free(&self, ptr: *mut T) {
+        // Freeing an object or reclaiming memory can drop cached objects which own other objects
+        // from this cache. Those are freed once the outer call has released the allocator.
+        if self.alloc.try_borrow_mut().is_err() {
+            self.deferred.borrow_mut().push(ptr);
+        } else {
+            self.with(|alloc| alloc.dealloc(ptr));
+        }
+    }
+
+    fn free_deferred(&self) {
+        loop {
+            let ptr = self.deferred.borrow_mut().pop();
+            match ptr {
+                // dropping objects here adds to deferred rather than recursing
+                Some(ptr) => unsafe { self.alloc.borrow_mut().dealloc(ptr) },
+                None => return,
+            }
+        }
+    }
+}
+
+/// A named cache of objects of type `T`.
+///
+/// `SlabCache`s are declared using the `slab_cache!` macro, and play the role of caches created
+/// with `kmem_cache_create` in the original slab allocator.
+///
+/// Each thread has its own instance of the cache's allocator, which is constructed by evaluating
+/// the cache's initializer the first time the cache is used on that thread, and dropped when the
+/// thread exits. As a result, objects must be freed on the same thread that allocated them. To
+/// share objects between threads, use an initializer which clones a handle to a
+/// `ConcurrentSlabAlloc`, in which case objects may be freed on any thread.
+///
+/// Objects may be freed to a cache while it is in use further up the stack - for example, by the
+/// destructor of a cached object which owns a `CacheBox` from the same cache, which runs when the
+/// cache's allocator drops the object while freeing or reclaiming memory. Such objects are freed
+/// once the outer call returns. Objects freed after the thread's instance of the cache has been
+/// destroyed (for example, from the destructor of another thread-local variable) are leaked.
+/// Allocating from a cache or reclaiming its memory while it is in use, or after it has been
+/// destroyed, panics.
+pub struct SlabCache<T: 'static> {
+    // Caches are constructed by the slab_cache! macro in a static initializer, so this field has
+    // to be public.
+    #[doc(hidden)]
+    pub __local: &'static LocalKey<__SlabCacheLocal<T>>,
+}
+
+impl<T: 'static> SlabCache<T> {
+    /// Allocates a new object from the current thread's instance of the cache.
+    ///
+    /// The state of the returned object is as described in the documentation for the `alloc`
+    /// method of the cache's allocator.
+    pub unsafe fn alloc(&self) -> Result<*mut T, Error> {
+        self.__local.with(|local| local.with(|alloc| alloc.alloc()))
+    }
+
+    /// Returns an object to the current thread's instance of the cache.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from this cache, and must not have been freed since. Unless
+    /// the cache's allocator supports freeing objects from other threads, `ptr` must have been
+    /// allocated on the current thread.
+    pub unsafe fn free(&self, ptr: *mut T) {
+        // if the thread's instance of the cache has already been destroyed, leak the object
+        let _ = self.__local.try_with(|local| local.free(ptr));
+    }
+
+    /// Allocates a new object, returning a `CacheBox` which frees it when dropped.
+    ///
+    /// The state of the returned object is as described in the documentation for `alloc`.
+    pub fn alloc_box(&'static self) -> Result<CacheBox<T>, Error> {
+        let ptr = unsafe { self.alloc()? };
+        Ok(CacheBox {
+               ptr: ptr,
+               cache: self,
+           })
+    }
+
+    /// Reclaims memory from the current thread's instance of the cache.
+    ///
+    /// See the documentation for `ObjectAlloc::reclaim` for details.
+    pub fn reclaim(&self, amount: Reclaim) -> usize {
+        self.__local.with(|local| local.with(|alloc| alloc.reclaim(amount)))
+    }
+}
+
+/// An owned object allocated from a `SlabCache`.
+///
+/// A `CacheBox` is analogous to `object_alloc::boxed::ObjectBox`, but since caches are static, it
+/// doesn't borrow anything, and the object is returned to its cache when the `CacheBox` is
+/// dropped. Since caches are per-thread, `CacheBox`es cannot be sent between threads.
+///
+/// A `CacheBox` may be owned by an object from the same cache; see the documentation for
+/// `SlabCache` for what happens when such objects are dropped.
+pub struct CacheBox<T: 'static> {
+    ptr: *mut T,
+    cache: &'static SlabCache<T>,
+}
+
+impl<T: 'static> CacheBox<T> {
+    /// Constructs a `CacheBox` from a raw pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from `cache` on the current thread, must not have been
+    /// freed, and must not be owned by any other `CacheBox`.
+    pub unsafe fn from_raw(ptr: *mut T, cache: &'static SlabCache<T>) -> CacheBox<T> {
+        CacheBox {
+            ptr: ptr,
+            cache: cache,
+        }
+    }
+
+    /// Consumes the `CacheBox`, returning the raw pointer to its object.
+    ///
+    /// The caller becomes responsible for returning the object to the cache, either by calling
+    /// `free` directly or by reconstructing a `CacheBox` using `from_raw`.
+    pub fn into_raw(b: CacheBox<T>) -> *mut T {
+        let ptr = b.ptr;
+        mem::forget(b);
+        ptr
+    }
+
+    /// Returns the cache from which the object was allocated.
+    pub fn cache(b: &CacheBox<T>) -> &'static SlabCache<T> {
+        b.cache
+    }
+}
+
+impl<T: 'static> Deref for CacheBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T: 'static> DerefMut for CacheBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T: 'static> Drop for CacheBox<T> {
+    fn drop(&mut self) {
+        unsafe { self.cache.free(self.ptr) }
+    }
+}
+
+impl<T: Debug + 'static> Debug for CacheBox<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
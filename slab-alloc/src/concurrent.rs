@@ -0,0 +1,328 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! A slab allocator which can be used from multiple threads.
+//!
+//! `ConcurrentSlabAlloc` implements the magazine layer described in [Magazines and Vmem: Extending
+//! the Slab Allocator to Many CPUs and Arbitrary Resources][1] on top of a `SlabAlloc`. Each
+//! handle to the allocator - one per thread - owns two magazines, each of which holds a stack of
+//! cached objects, and allocates from and frees to them without synchronization. When both of a
+//! handle's magazines are empty (or full), it exchanges one of them with the depot, a pair of
+//! `BagPipe`s holding full and empty magazines which are shared between all handles. Only when the
+//! depot has no full magazines to offer does a handle lock the underlying `SlabAlloc`.
+//!
+//! Since magazines hold constructed objects, objects freed on one thread and allocated on another
+//! are not re-initialized, just as with a single-threaded `SlabAlloc`.
+//!
+//! [1]: https://www.usenix.org/legacy/event/usenix01/full_papers/bonwick/bonwick.pdf
+
+extern crate bagpipe;
+
+use core::{mem, ptr};
+use std::sync::{Arc, Mutex};
+
+use self::bagpipe::BagPipe;
+use self::bagpipe::bag::WeakBag;
+use self::bagpipe::queue::FAAQueueLowLevel;
+
+use {ReclaimPolicy, SlabAlloc};
+use reclaim::DefaultReclaimPolicy;
+use alloc::allocator::Layout;
+use backing::BackingAlloc;
+use init::InitSystem;
+use large::SlabMap;
+use object_alloc::{Error, ObjectAlloc, Reclaim, UntypedObjectAlloc};
+
+/// The number of objects held by a full magazine.
+const MAGAZINE_SIZE: usize = 32;
+
+struct Magazine {
+    len: usize,
+    objs: [*mut u8; MAGAZINE_SIZE],
+}
+
+impl Magazine {
+    fn new() -> *mut Magazine {
+        Box::into_raw(Box::new(Magazine {
+                                   len: 0,
+                                   objs: [ptr::null_mut(); MAGAZINE_SIZE],
+                               }))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == MAGAZINE_SIZE
+    }
+
+    fn push(&mut self, obj: *mut u8) {
+        debug_assert!(!self.is_full());
+        self.objs[self.len] = obj;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> *mut u8 {
+        debug_assert!(!self.is_empty());
+        self.len -= 1;
+        self.objs[self.len]
+    }
+
+    /// Return all of the magazine's objects to `alloc`, leaving it empty.
+    fn drain<A: UntypedObjectAlloc>(&mut self, alloc: &mut A) {
+        unsafe { alloc.dealloc_batch(&self.objs[..self.len]) };
+        self.len = 0;
+    }
+}
+
+type MagazinePipe = BagPipe<FAAQueueLowLevel<*mut Magazine>>;
+
+/// The state shared by all handles to a `ConcurrentSlabAlloc`.
+struct Depot<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> {
+    slabs: Mutex<SlabAlloc<T, I, B, P>>,
+    layout: Layout,
+    // the large slab system's map, which can be used without holding the lock on slabs
+    large_slab_map: Option<Arc<SlabMap>>,
+    full: MagazinePipe,
+    empty: MagazinePipe,
+}
+
+// The SlabAlloc is only ever accessed with its Mutex held, the large slab map supports concurrent
+// lookups, and the only other state is the magazines, which are owned by the depot while they are
+// in one of its BagPipes.
+unsafe impl<T, I, B, P> Send for Depot<T, I, B, P>
+    where T: Send,
+          I: InitSystem + Send,
+          B: BackingAlloc,
+          B::Aligned: Send,
+          B::Large: Send,
+          P: ReclaimPolicy + Send
+{
+}
+unsafe impl<T, I, B, P> Sync for Depot<T, I, B, P>
+    where T: Send,
+          I: InitSystem + Send,
+          B: BackingAlloc,
+          B::Aligned: Send,
+          B::Large: Send,
+          P: ReclaimPolicy + Send
+{
+}
+
+impl<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> Drop for Depot<T, I, B, P> {
+    fn drop(&mut self) {
+        // By the time the depot is dropped, every handle has returned its magazines, so all
+        // cached objects are in the full pipe.
+        let slabs = self.slabs.get_mut().unwrap();
+        while let Some(mag) = self.full.pop_mut() {
+            unsafe {
+                (*mag).drain(slabs);
+                Box::from_raw(mag);
+            }
+        }
+        while let Some(mag) = self.empty.pop_mut() {
+            unsafe { Box::from_raw(mag) };
+        }
+    }
+}
+
+/// A handle to a slab allocator which can be shared between threads.
+///
+/// A `ConcurrentSlabAlloc` is constructed from a `SlabAlloc`, and can be cloned to obtain more
+/// handles to the same underlying allocator. Each handle caches objects locally, and is intended
+/// to be used by a single thread at a time. Objects allocated using one handle may be freed using
+/// any other handle to the same allocator.
+///
+/// The underlying `SlabAlloc` is dropped once all handles have been dropped. As with `SlabAlloc`,
+/// all objects must have been freed by then.
+pub struct ConcurrentSlabAlloc<T, I, B, P = DefaultReclaimPolicy>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    depot: Arc<Depot<T, I, B, P>>,
+    full: MagazinePipe,
+    empty: MagazinePipe,
+    // never null; owned by this handle
+    loaded: *mut Magazine,
+    previous: *mut Magazine,
+}
+
+unsafe impl<T, I, B, P> Send for ConcurrentSlabAlloc<T, I, B, P>
+    where T: Send,
+          I: InitSystem + Send,
+          B: BackingAlloc,
+          B::Aligned: Send,
+          B::Large: Send,
+          P: ReclaimPolicy + Send
+{
+}
+
+impl<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> ConcurrentSlabAlloc<T, I, B, P> {
+    /// Constructs a new `ConcurrentSlabAlloc` which allocates objects from `alloc`.
+    pub fn new(alloc: SlabAlloc<T, I, B, P>) -> ConcurrentSlabAlloc<T, I, B, P> {
+        let layout = UntypedObjectAlloc::layout(&alloc);
+        let large_slab_map = alloc.large_slab_map();
+        ConcurrentSlabAlloc::from_depot(Arc::new(Depot {
+                                                     slabs: Mutex::new(alloc),
+                                                     layout: layout,
+                                                     large_slab_map: large_slab_map,
+                                                     full: MagazinePipe::new(),
+                                                     empty: MagazinePipe::new(),
+                                                 }))
+    }
+
+    fn from_depot(depot: Arc<Depot<T, I, B, P>>) -> ConcurrentSlabAlloc<T, I, B, P> {
+        ConcurrentSlabAlloc {
+            full: depot.full.clone(),
+            empty: depot.empty.clone(),
+            depot: depot,
+            loaded: Magazine::new(),
+            previous: Magazine::new(),
+        }
+    }
+
+    fn alloc_obj(&mut self) -> Result<*mut u8, Error> {
+        unsafe {
+            if (*self.loaded).is_empty() {
+                if (*self.previous).is_empty() {
+                    match self.full.pop_mut() {
+                        Some(mag) => {
+                            self.empty.push_mut(self.previous);
+                            self.previous = mag;
+                        }
+                        None => return self.alloc_slabs(),
+                    }
+                }
+                mem::swap(&mut self.loaded, &mut self.previous);
+            }
+            Ok((*self.loaded).pop())
+        }
+    }
+
+    /// Refill the loaded magazine from the underlying `SlabAlloc`.
+    ///
+    /// `alloc_slabs` is only called when both magazines are empty.
+    fn alloc_slabs(&mut self) -> Result<*mut u8, Error> {
+        let mut slabs = self.depot.slabs.lock().unwrap();
+        let mag = unsafe { &mut *self.loaded };
+        mag.len = unsafe { UntypedObjectAlloc::alloc_batch(&mut *slabs, &mut mag.objs[..]) };
+        if mag.is_empty() {
+            // Get the error describing why allocation failed.
+            unsafe { UntypedObjectAlloc::alloc(&mut *slabs) }
+        } else {
+            Ok(mag.pop())
+        }
+    }
+
+    fn dealloc_obj(&mut self, obj: *mut u8) {
+        // Objects freed to the wrong allocator would otherwise go unnoticed until they were
+        // returned to the SlabAlloc, possibly on another thread. Large slabs can be checked
+        // without taking the lock.
+        debug_assert!(self.depot
+                          .large_slab_map
+                          .as_ref()
+                          .map_or(true, |map| map.try_get(obj).is_some()),
+                      "slab-alloc: object {:?} not allocated by this allocator",
+                      obj);
+        unsafe {
+            if (*self.loaded).is_full() {
+                if (*self.previous).is_full() {
+                    let mag = self.empty.pop_mut().unwrap_or_else(Magazine::new);
+                    self.full.push_mut(self.previous);
+                    self.previous = mag;
+                }
+                mem::swap(&mut self.loaded, &mut self.previous);
+            }
+            (*self.loaded).push(obj);
+        }
+    }
+
+    /// Return cached objects to the underlying `SlabAlloc` and reclaim its memory.
+    ///
+    /// Objects cached by this handle and by the depot are returned to the `SlabAlloc`, after which
+    /// `amount` is passed to the `SlabAlloc`'s `reclaim` method. Objects cached by other handles
+    /// are not affected.
+    fn reclaim_slabs(&mut self, amount: Reclaim) -> usize {
+        let mut slabs = self.depot.slabs.lock().unwrap();
+        unsafe {
+            (*self.loaded).drain(&mut *slabs);
+            (*self.previous).drain(&mut *slabs);
+            while let Some(mag) = self.full.pop_mut() {
+                (*mag).drain(&mut *slabs);
+                Box::from_raw(mag);
+            }
+            while let Some(mag) = self.empty.pop_mut() {
+                Box::from_raw(mag);
+            }
+        }
+        UntypedObjectAlloc::reclaim(&mut *slabs, amount)
+    }
+}
+
+impl<T, I, B, P> Clone for ConcurrentSlabAlloc<T, I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    /// Creates a new handle to the same underlying allocator.
+    fn clone(&self) -> ConcurrentSlabAlloc<T, I, B, P> {
+        ConcurrentSlabAlloc::from_depot(self.depot.clone())
+    }
+}
+
+impl<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> Drop for ConcurrentSlabAlloc<T, I, B, P> {
+    fn drop(&mut self) {
+        for &mag in &[self.loaded, self.previous] {
+            if unsafe { (*mag).is_empty() } {
+                self.empty.push_mut(mag);
+            } else {
+                self.full.push_mut(mag);
+            }
+        }
+    }
+}
+
+unsafe impl<T, I, B, P> ObjectAlloc<T> for ConcurrentSlabAlloc<T, I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    unsafe fn alloc(&mut self) -> Result<*mut T, Error> {
+        ConcurrentSlabAlloc::alloc_obj(self).map(|ptr| ptr as *mut T)
+    }
+
+    unsafe fn dealloc(&mut self, x: *mut T) {
+        ConcurrentSlabAlloc::dealloc_obj(self, x as *mut u8);
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        ConcurrentSlabAlloc::reclaim_slabs(self, amount)
+    }
+}
+
+unsafe impl<T, I, B, P> UntypedObjectAlloc for ConcurrentSlabAlloc<T, I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    fn layout(&self) -> Layout {
+        self.depot.layout.clone()
+    }
+
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
+        ConcurrentSlabAlloc::alloc_obj(self)
+    }
+
+    unsafe fn dealloc(&mut self, x: *mut u8) {
+        ConcurrentSlabAlloc::dealloc_obj(self, x);
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        ConcurrentSlabAlloc::reclaim_slabs(self, amount)
+    }
+}
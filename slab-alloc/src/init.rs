@@ -59,7 +59,7 @@ pub trait InitSystem {
     ///
     /// For implementations that perform initialization, `drop` drops `obj` if it is currently
     /// initialized. For implementations that do not perform initialization, `drop` is a no-op.
-    fn drop(obj: *mut u8, init_status: Self::Status);
+    fn drop(&self, obj: *mut u8, init_status: Self::Status);
 }
 
 pub struct NopInitSystem;
@@ -86,7 +86,7 @@ impl InitSystem for NopInitSystem {
         packed as *mut u8
     }
     fn init(&self, _obj: *mut u8, _init_status: ()) {}
-    fn drop(_obj: *mut u8, _init_status: ()) {}
+    fn drop(&self, _obj: *mut u8, _init_status: ()) {}
 }
 
 pub struct InitInitSystem<T, I: Initializer<T>> {
@@ -136,11 +136,10 @@ impl<T, I: Initializer<T>> InitSystem for InitInitSystem<T, I> {
         }
     }
 
-    fn drop(obj: *mut u8, init: bool) {
+    fn drop(&self, obj: *mut u8, init: bool) {
         if init {
             unsafe {
-                use core::ptr::drop_in_place;
-                drop_in_place(obj as *mut T);
+                self.init.drop(obj as *mut T);
             }
         }
     }
@@ -148,6 +147,14 @@ impl<T, I: Initializer<T>> InitSystem for InitInitSystem<T, I> {
 
 pub unsafe trait Initializer<T> {
     unsafe fn init(&self, ptr: *mut T);
+
+    /// Drop an initialized object.
+    ///
+    /// The default implementation runs `T`'s destructor.
+    unsafe fn drop(&self, ptr: *mut T) {
+        use core::ptr::drop_in_place;
+        drop_in_place(ptr);
+    }
 }
 
 #[derive(Default)]
@@ -196,3 +203,21 @@ unsafe impl<T, F: Fn(*mut T)> Initializer<T> for UnsafeFnInitializer<T, F> {
         (self.0)(ptr);
     }
 }
+
+pub struct UnsafeFnDropInitializer<T, F: Fn(*mut T), D: Fn(*mut T)>(F, D, PhantomData<T>);
+
+impl<T, F: Fn(*mut T), D: Fn(*mut T)> UnsafeFnDropInitializer<T, F, D> {
+    pub fn new(f: F, d: D) -> UnsafeFnDropInitializer<T, F, D> {
+        UnsafeFnDropInitializer(f, d, PhantomData)
+    }
+}
+
+unsafe impl<T, F: Fn(*mut T), D: Fn(*mut T)> Initializer<T> for UnsafeFnDropInitializer<T, F, D> {
+    unsafe fn init(&self, ptr: *mut T) {
+        (self.0)(ptr);
+    }
+
+    unsafe fn drop(&self, ptr: *mut T) {
+        (self.1)(ptr);
+    }
+}
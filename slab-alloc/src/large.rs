@@ -26,58 +26,90 @@
 extern crate alloc;
 extern crate object_alloc;
 
-use {PAGE_SIZE, PAGE_ALIGN_MASK, OBJECTS_PER_SLAB};
+use Config;
 use stack;
 use stack::{SlabHeader, Layout};
 use init::InitSystem;
 use util::ptrmap::*;
 // use size::TypeSize;
 use self::alloc::allocator;
+use self::alloc::arc::Arc;
 use self::object_alloc::UntypedObjectAlloc;
 
-pub struct ConfigData {
-    pub map: Map<u8, SlabHeader>,
+/// The map from objects to the large slabs containing them.
+///
+/// The map is shared between a slab system and any `ConcurrentSlabAlloc` built on top of it, which
+/// uses it to look up objects without locking the slab system. It is only modified while slabs
+/// are allocated or freed, so an object's entry is present for as long as the object is allocated.
+pub struct SlabMap {
+    map: ConcurrentMap<u8, SlabHeader>,
     map_by_page_addr: bool,
+    page_size: usize,
+}
+
+impl SlabMap {
+    /// Returns the slab containing `ptr`, or `None` if `ptr` does not point into one of the
+    /// allocator's slabs. `try_get` never dereferences `ptr` or the returned slab.
+    pub fn try_get(&self, ptr: *mut u8) -> Option<*mut SlabHeader> {
+        self.map.try_get(self.key(ptr))
+    }
+
+    fn get(&self, ptr: *mut u8) -> *mut SlabHeader {
+        self.map.get(self.key(ptr))
+    }
+
+    fn key(&self, ptr: *mut u8) -> *mut u8 {
+        if self.map_by_page_addr {
+            ((ptr as usize) & !(self.page_size - 1)) as *mut u8
+        } else {
+            ptr
+        }
+    }
+}
+
+pub struct ConfigData {
+    pub map: Arc<SlabMap>,
 }
 
 impl stack::ConfigData for ConfigData {
     fn post_alloc(&mut self, layout: &Layout, slab_size: usize, slab: *mut SlabHeader) {
-        if self.map_by_page_addr {
-            for i in 0..(slab_size / *PAGE_SIZE) {
-                let page_ptr = ((slab as usize) + (i * *PAGE_SIZE)) as *mut u8;
-                debug_assert_eq!(page_ptr as usize % *PAGE_SIZE, 0);
-                self.map.insert(page_ptr, slab);
+        let map = &self.map;
+        if map.map_by_page_addr {
+            for i in 0..(slab_size / map.page_size) {
+                let page_ptr = ((slab as usize) + (i * map.page_size)) as *mut u8;
+                debug_assert_eq!(page_ptr as usize % map.page_size, 0);
+                map.map.insert(page_ptr, slab);
             }
         } else {
             for i in 0..layout.num_obj {
                 let ptr = layout.nth_obj(slab, unsafe { (*slab).get_color() }, i);
-                self.map.insert(ptr, slab);
+                map.map.insert(ptr, slab);
             }
         }
     }
 
     fn pre_dealloc(&mut self, layout: &Layout, slab_size: usize, slab: *mut SlabHeader) {
-        if self.map_by_page_addr {
-            for i in 0..(slab_size / *PAGE_SIZE) {
-                let page_ptr = ((slab as usize) + (i * *PAGE_SIZE)) as *mut u8;
-                debug_assert_eq!(page_ptr as usize % *PAGE_SIZE, 0);
-                self.map.delete(page_ptr);
+        let map = &self.map;
+        if map.map_by_page_addr {
+            for i in 0..(slab_size / map.page_size) {
+                let page_ptr = ((slab as usize) + (i * map.page_size)) as *mut u8;
+                debug_assert_eq!(page_ptr as usize % map.page_size, 0);
+                map.map.delete(page_ptr);
             }
         } else {
             for i in 0..layout.num_obj {
                 let ptr = layout.nth_obj(slab, unsafe { (*slab).get_color() }, i);
-                self.map.delete(ptr);
+                map.map.delete(ptr);
             }
         }
     }
 
     fn ptr_to_slab(&self, _slab_size: usize, ptr: *mut u8) -> *mut SlabHeader {
-        if self.map_by_page_addr {
-            self.map
-                .get(((ptr as usize) & *PAGE_ALIGN_MASK) as *mut u8)
-        } else {
-            self.map.get(ptr)
-        }
+        self.map.get(ptr)
+    }
+
+    fn try_ptr_to_slab(&self, _slab_size: usize, ptr: *mut u8) -> Option<*mut SlabHeader> {
+        self.map.try_get(ptr)
     }
 }
 
@@ -86,35 +118,44 @@ pub type System<A> = stack::System<A, ConfigData>;
 pub const DEFAULT_MAP_SIZE: usize = 256;
 
 impl<A: UntypedObjectAlloc> System<A> {
-    pub fn new(layout: allocator::Layout, alloc: A) -> Option<System<A>> {
-        if let Some((slab_layout, _)) =
-            Layout::for_slab_size(layout.clone(), alloc.layout().size()) {
-            let map_by_page_addr = layout.size() < *PAGE_SIZE;
+    pub fn new(layout: allocator::Layout, alloc: A, config: &Config) -> Option<System<A>> {
+        if let Some((mut slab_layout, _)) =
+            Layout::for_slab_size(layout.clone(), alloc.layout().size(), config.debug) {
+            if !config.coloring {
+                slab_layout.disable_coloring();
+            }
+            let map_by_page_addr = layout.size() < config.page_size;
             let map_key_align = if map_by_page_addr {
-                *PAGE_SIZE
+                config.page_size
             } else {
                 layout.size().next_power_of_two()
             };
-            Some(Self::from_config_data(ConfigData {
-                                            map: Map::new(DEFAULT_MAP_SIZE, map_key_align),
-                                            map_by_page_addr: map_by_page_addr,
-                                        },
-                                        slab_layout,
-                                        alloc))
+            let map = SlabMap {
+                map: ConcurrentMap::new(DEFAULT_MAP_SIZE, map_key_align),
+                map_by_page_addr: map_by_page_addr,
+                page_size: config.page_size,
+            };
+            Some(Self::from_config_data(ConfigData { map: Arc::new(map) }, slab_layout, alloc))
         } else {
             None
         }
     }
 }
 
-pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout) -> usize {
-    struct PageIterator(usize);
+/// Choose the size of large slabs for objects of the given layout.
+pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout, config: &Config) -> usize {
+    if let Some(size) = config.slab_size {
+        return size;
+    }
+
+    // the current size and the page size
+    struct PageIterator(usize, usize);
 
     impl Iterator for PageIterator {
         type Item = usize;
 
         fn next(&mut self) -> Option<usize> {
-            let (next, _) = self.0.overflowing_add(*PAGE_SIZE);
+            let (next, _) = self.0.overflowing_add(self.1);
             if next > self.0 {
                 self.0 = next;
                 Some(next)
@@ -125,23 +166,25 @@ pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout) -> usize {
     }
 
     let unused = |slab_size: usize| {
-        Layout::for_slab_size(layout.clone(), slab_size).map(|(layout, unused)| {
-                                                                 (layout.num_obj, unused)
-                                                             })
+        Layout::for_slab_size(layout.clone(), slab_size, config.debug)
+            .map(|(layout, unused)| (layout.num_obj, unused))
     };
 
     // pick a reasonable lower bound on slab size to avoid wasting unnecessary work on slab sizes
     // that are too small to accomodate even a single object; for large object sizes, this can save
     // a considerable amount of work (e.g., for 128M objects, counting up from 1 page would take
     // ~32K iterations)
-    let init_size = if layout.size() % *PAGE_SIZE == 0 {
+    let page_size = config.page_size;
+    let init_size = if layout.size() % page_size == 0 {
         layout.size()
     } else {
         // round down to a multiple of the page size
-        (layout.size() / *PAGE_SIZE) * *PAGE_SIZE
+        (layout.size() / page_size) * page_size
     };
 
-    ::util::size::choose_size(PageIterator(init_size), unused, OBJECTS_PER_SLAB)
+    ::util::size::choose_size(PageIterator(init_size, page_size),
+                              unused,
+                              config.objects_per_slab)
 }
 
 #[cfg(not(feature = "use-stdlib-hashmap"))]
@@ -149,7 +192,8 @@ pub fn backing_size_for<I: InitSystem>(layout: &allocator::Layout) -> usize {
 mod tests {
     extern crate alloc;
 
-    use {DefaultInitSystem, SizedSlabAlloc};
+    use {Config, DefaultInitSystem, SizedSlabAlloc};
+    use reclaim::DefaultReclaimPolicy;
     use init::DefaultInitializer;
     use self::alloc::allocator::Layout;
 
@@ -163,7 +207,11 @@ mod tests {
             let mut alloc =
                 SizedSlabAlloc::new(DefaultInitSystem::<T>::new(DefaultInitializer::new()),
                                     layout.clone(),
-                                    super::System::new(layout, heap::get_large(backing_layout))
+                                    &Config::default(),
+                                    DefaultReclaimPolicy::default(),
+                                    super::System::new(layout,
+                                                       heap::get_large(backing_layout),
+                                                       &Config::default())
                                         .unwrap());
             let mut ptrs = Vec::new();
 
@@ -171,7 +219,7 @@ mod tests {
             for _ in 0..size {
                 ptrs.push(alloc.alloc().unwrap());
             }
-            let buckets = alloc.slab_system.data.map.map.dump_by_bucket();
+            let buckets = alloc.slab_system.data.map.map.map.dump_by_bucket();
             for p in ptrs {
                 alloc.dealloc(p);
             }
@@ -27,19 +27,11 @@
 //! [1]: http://www.usenix.org/publications/library/proceedings/bos94/full_papers/bonwick.ps
 
 // TODO:
-// - Sort partially-full slabs so that the emptiest slabs come first. This makes it more likely
-//   that almost-full slabs will become completely full and eligible to be freed back to the system.
-//   NOTE: Consider using a simplified heap like the one used in SuperMalloc.
 // - Find a fitting algorithm slabs that trades off space usage with ability to perform coloring
 //   (rather than always prioritizing space usage)
 // - Would it be worth it to special-case 64-byte allocations to ensure that they are 64-byte
 //   aligned so that each object gets its own cache line? It should be sufficient to artificially
 //   override the align parameter to be 64 bytes when the size is 64 bytes.
-// - Add a feature flag to disable using aligned slabs for backing sizes larger than a page (to
-//   enable benchmarking aligned vs large slabs)
-// - Consider improvements to the slab size selection algorithm. For example, might we be willing
-//   to take sub-optimal space utilization in order to use an aligned slab with fewer than the
-//   number of objects per slab, and thus reap the performance benefits of aligned slabs?
 // - Make sure everything is exception-safe.
 
 // Guarantees made by Rust about the memory layout (see https://doc.rust-lang.org/nomicon/repr-rust.html):
@@ -51,15 +43,26 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 #![feature(alloc, allocator_api)]
 #![feature(plugin)]
+#![cfg_attr(feature = "std", feature(thread_local_state))]
 #![cfg_attr(test, feature(test))]
 
 #![plugin(interpolate_idents)]
 
+// walk and cache are declared first so that their macros are available in the other modules.
+#[macro_use]
+mod walk;
+#[cfg(feature = "std")]
+#[macro_use]
+mod cache;
 mod aligned;
 mod backing;
+#[cfg(feature = "std")]
+mod concurrent;
 mod init;
 mod large;
+mod pool;
 mod ptr_map;
+pub mod reclaim;
 mod stack;
 #[cfg(test)]
 mod tests;
@@ -68,25 +71,37 @@ mod util;
 extern crate alloc;
 #[cfg(feature = "std")]
 extern crate core;
-#[macro_use]
-extern crate lazy_static;
 extern crate object_alloc;
 #[cfg(test)]
 #[macro_use]
 extern crate object_alloc_test;
+#[cfg(feature = "os")]
 extern crate sysconf;
 
+use core::cell::Cell;
 use core::marker::PhantomData;
 use core::default::Default;
-use core::mem;
+use core::{mem, slice};
 use self::util::list::*;
-use util::workingset::WorkingSet;
+use self::util::ptrmap::Map;
 use init::*;
 use self::init::InitSystem;
-use self::object_alloc::{Exhausted, ObjectAlloc, UntypedObjectAlloc};
+use self::object_alloc::{AllocatorStats, Error, ObjectAlloc, Reclaim, Stats,
+                         UntypedObjectAlloc};
 use self::alloc::allocator::Layout;
 
 pub use backing::BackingAlloc;
+pub use backing::arena::ArenaBackingAlloc;
+pub use backing::static_region::StaticRegionBackingAlloc;
+#[cfg(feature = "std")]
+pub use cache::{CacheBox, SlabCache, __SlabCacheLocal};
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentSlabAlloc;
+pub use pool::ObjectPool;
+pub use reclaim::ReclaimPolicy;
+use reclaim::DefaultReclaimPolicy;
+pub use walk::{AllocSite, LeakReport, SlabInfo};
+use walk::HeapWalk;
 #[cfg(feature = "std")]
 use backing::heap::HeapBackingAlloc;
 #[cfg(feature = "os")]
@@ -96,65 +111,284 @@ use init::NopInitSystem;
 type DefaultInitSystem<T> = init::InitInitSystem<T, init::DefaultInitializer<T>>;
 type FnInitSystem<T, F> = init::InitInitSystem<T, init::FnInitializer<T, F>>;
 type UnsafeFnInitSystem<T, F> = init::InitInitSystem<T, init::UnsafeFnInitializer<T, F>>;
+type UnsafeFnDropInitSystem<T, F, D> = init::InitInitSystem<T,
+                                                            init::UnsafeFnDropInitializer<T, F, D>>;
 
-lazy_static!{
-    static ref PAGE_SIZE: usize = self::sysconf::page::pagesize();
-    static ref PAGE_ALIGN_MASK: usize = !(*PAGE_SIZE - 1);
-}
+/// The page size assumed if the `os` feature is disabled and no page size is configured.
+#[cfg(not(feature = "os"))]
+const DEFAULT_PAGE_SIZE: usize = 4096;
 
-const WORKING_PERIOD_SECONDS: u64 = 15;
-const OBJECTS_PER_SLAB: usize = 8;
+/// The default target number of objects per slab used when choosing slab sizes.
+const DEFAULT_OBJECTS_PER_SLAB: usize = 8;
+/// The cache line size assumed by `cache_line_align`.
+const CACHE_LINE_SIZE: usize = 64;
+/// The initial size of the map of allocation sites.
+const SITE_MAP_SIZE: usize = 64;
 
 /// A typed slab allocator.
-pub struct SlabAlloc<T, I: InitSystem, B: BackingAlloc> {
-    alloc: PrivateSlabAlloc<I, B>,
+pub struct SlabAlloc<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy = DefaultReclaimPolicy> {
+    alloc: PrivateSlabAlloc<I, B, P>,
     _marker: PhantomData<T>,
 }
 
 /// An untyped slab allocator.
-pub struct UntypedSlabAlloc<I: InitSystem, B: BackingAlloc> {
-    alloc: PrivateUntypedSlabAlloc<I, B>,
+pub struct UntypedSlabAlloc<I, B, P = DefaultReclaimPolicy>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    alloc: PrivateUntypedSlabAlloc<I, B, P>,
+}
+
+enum PrivateSlabAlloc<I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> {
+    Aligned(SizedSlabAlloc<I, aligned::System<B::Aligned>, P>),
+    Large(SizedSlabAlloc<I, large::System<B::Large>, P>),
+}
+
+enum PrivateUntypedSlabAlloc<I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> {
+    Aligned(SizedSlabAlloc<I, aligned::System<B::Aligned>, P>),
+    Large(SizedSlabAlloc<I, large::System<B::Large>, P>),
+}
+
+/// Configuration shared by `SlabAllocBuilder` and `UntypedSlabAllocBuilder`.
+struct Config {
+    partial_slab_buckets: usize,
+    coloring: bool,
+    cache_line_align: bool,
+    prefer_aligned: bool,
+    slab_size: Option<usize>,
+    objects_per_slab: usize,
+    debug: bool,
+    page_size: usize,
 }
 
-enum PrivateSlabAlloc<I: InitSystem, B: BackingAlloc> {
-    Aligned(SizedSlabAlloc<I, aligned::System<B::Aligned>>),
-    Large(SizedSlabAlloc<I, large::System<B::Large>>),
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            partial_slab_buckets: 1,
+            coloring: true,
+            cache_line_align: false,
+            prefer_aligned: false,
+            slab_size: None,
+            objects_per_slab: DEFAULT_OBJECTS_PER_SLAB,
+            debug: false,
+            page_size: default_page_size(),
+        }
+    }
+}
+
+#[cfg(feature = "os")]
+fn default_page_size() -> usize {
+    self::sysconf::page::pagesize()
 }
 
-enum PrivateUntypedSlabAlloc<I: InitSystem, B: BackingAlloc> {
-    Aligned(SizedSlabAlloc<I, aligned::System<B::Aligned>>),
-    Large(SizedSlabAlloc<I, large::System<B::Large>>),
+#[cfg(not(feature = "os"))]
+fn default_page_size() -> usize {
+    DEFAULT_PAGE_SIZE
+}
+
+/// A slab system of either type.
+enum Slabs<B: BackingAlloc> {
+    Aligned(aligned::System<B::Aligned>),
+    Large(large::System<B::Large>),
+}
+
+impl Config {
+    fn set_page_size(&mut self, page_size: usize, layout: &Layout) {
+        assert!(page_size.is_power_of_two());
+        assert!(layout.align() <= page_size);
+        if let Some(size) = self.slab_size {
+            assert_eq!(size % page_size, 0);
+        }
+        self.page_size = page_size;
+    }
+
+    /// Construct the slab system for objects of the given layout.
+    ///
+    /// Aligned slabs are used if the aligned slab size chosen for this configuration is supported
+    /// by `get_aligned`. Otherwise, large slabs are used. See `SlabAllocBuilder::build_backing`
+    /// for details.
+    fn build_slabs<I, B, A, L>(&self, layout: Layout, get_aligned: A, get_large: L) -> Slabs<B>
+        where I: InitSystem,
+              B: BackingAlloc,
+              A: Fn(Layout) -> Option<B::Aligned>,
+              L: Fn(Layout) -> B::Large
+    {
+        let mut layout = util::misc::satisfy_min_align(layout, I::min_align());
+        if self.cache_line_align {
+            layout = util::misc::satisfy_min_align(layout, CACHE_LINE_SIZE);
+        }
+
+        if let Some(aligned_backing_size) = aligned::backing_size_for::<I>(&layout, self) {
+            let aligned_slab_layout =
+                Layout::from_size_align(aligned_backing_size, aligned_backing_size).unwrap();
+            if let Some(alloc) = get_aligned(aligned_slab_layout) {
+                return Slabs::Aligned(aligned::System::new(layout, alloc, self)
+                                          .expect("slab size too small to hold an object"));
+            }
+        }
+
+        let backing_size = large::backing_size_for::<I>(&layout, self);
+        let slab_layout = Layout::from_size_align(backing_size, self.page_size).unwrap();
+        Slabs::Large(large::System::new(layout, get_large(slab_layout), self)
+                         .expect("slab size too small to hold an object"))
+    }
 }
 
 /// A builder for `SlabAlloc`s.
-pub struct SlabAllocBuilder<T, I: InitSystem> {
+pub struct SlabAllocBuilder<T, I: InitSystem, P: ReclaimPolicy = DefaultReclaimPolicy> {
     init: I,
     layout: Layout,
+    config: Config,
+    policy: P,
     _marker: PhantomData<T>,
 }
 
-impl<T, I: InitSystem> SlabAllocBuilder<T, I> {
+impl<T, I: InitSystem, P: ReclaimPolicy> SlabAllocBuilder<T, I, P> {
     /// Updates the alignment guaranteed by the allocator.
     ///
     /// `align` must not be greater than the size of `T` (that is, `core::mem::size_of::<T>()`),
-    /// must not be greater than the system's page size, and must be a power of two. The size of
-    /// `T` must be a multiple of `align`.
+    /// must not be greater than the page size (see `page_size`), and must be a power of two. The
+    /// size of `T` must be a multiple of `align`.
     ///
     /// If `align` is called multiple times, then the largest specified alignment will be used.
     /// Since all alignments must be powers of two, allocations which satisfy the largest specified
     /// alignment will also satisfy all smaller alignments.
-    pub fn align(mut self, align: usize) -> SlabAllocBuilder<T, I> {
+    pub fn align(mut self, align: usize) -> SlabAllocBuilder<T, I, P> {
         assert!(align.is_power_of_two());
         assert!(align <= mem::size_of::<T>());
         assert_eq!(mem::size_of::<T>() % align, 0);
-        assert!(align <= *PAGE_SIZE);
+        assert!(align <= self.config.page_size);
         self.layout = self.layout.align_to(align);
         self
     }
 
+    /// Sorts partially-full slabs into `buckets` buckets by occupancy.
+    ///
+    /// By default, the allocator allocates from whichever partially-full slab was most recently
+    /// freed to. Under workloads which free objects in a different order than they were allocated,
+    /// this tends to spread live objects thinly across many slabs, few of which ever have all of
+    /// their objects freed and become eligible to be returned to the system. With more than one
+    /// bucket, partially-full slabs are sorted by how many of their objects are available, and
+    /// allocations are always served from the slabs with the fewest available objects. This gives
+    /// the most sparsely-used slabs a chance to drain completely, at the cost of a small amount of
+    /// extra bookkeeping on each allocation and deallocation.
+    ///
+    /// `buckets` must be between 1 and 8 (inclusive). The default is 1, which disables sorting.
+    pub fn partial_slab_buckets(mut self, buckets: usize) -> SlabAllocBuilder<T, I, P> {
+        assert!(buckets > 0 && buckets <= util::list::MAX_BUCKETS);
+        self.config.partial_slab_buckets = buckets;
+        self
+    }
+
+    /// Enables or disables slab coloring.
+    ///
+    /// Coloring uses space which would otherwise be wasted at the end of each slab to offset the
+    /// beginning of the slab's array of objects by a different amount in each slab, so that
+    /// objects at the same index in different slabs don't compete for the same cache lines.
+    /// Coloring is enabled by default. If the `no-coloring` feature is enabled, coloring is always
+    /// disabled, and `coloring` has no effect.
+    pub fn coloring(mut self, coloring: bool) -> SlabAllocBuilder<T, I, P> {
+        self.config.coloring = coloring;
+        self
+    }
+
+    /// Aligns every object to the beginning of a cache line.
+    ///
+    /// Objects are padded to a multiple of the cache line size (assumed to be 64 bytes), and are
+    /// aligned to a cache line boundary so that no two objects share a cache line. This avoids
+    /// false sharing between objects used by different threads at the cost of wasting space for
+    /// objects whose size is not a multiple of the cache line size. The `Layout` reported by the
+    /// allocator is not affected.
+    pub fn cache_line_align(mut self) -> SlabAllocBuilder<T, I, P> {
+        self.config.cache_line_align = true;
+        self
+    }
+
+    /// Prefers aligned slabs over large slabs, even at the cost of space efficiency.
+    ///
+    /// Aligned slabs perform better than large slabs, but backing allocators are only required to
+    /// support page-sized aligned slabs (see `build_backing`). By default, the aligned slab size
+    /// is chosen to minimize wasted space, which may result in a size for which aligned slabs are
+    /// not supported, in which case large slabs are used instead. With `prefer_aligned_slabs`,
+    /// the smallest slab size which can hold at least one object is used for aligned slabs, even
+    /// if it wastes more space.
+    pub fn prefer_aligned_slabs(mut self) -> SlabAllocBuilder<T, I, P> {
+        self.config.prefer_aligned = true;
+        self
+    }
+
+    /// Uses slabs of exactly `size` bytes.
+    ///
+    /// `size` must be a non-zero multiple of the page size (see `page_size`). If `size` is a power
+    /// of two, aligned slabs will be used if the backing allocator supports them; otherwise, large
+    /// slabs will be used. Building the allocator will panic if a slab of this size is too small
+    /// to hold a single object.
+    pub fn slab_size(mut self, size: usize) -> SlabAllocBuilder<T, I, P> {
+        assert!(size > 0);
+        assert_eq!(size % self.config.page_size, 0);
+        self.config.slab_size = Some(size);
+        self
+    }
+
+    /// Sets the target number of objects per slab used when choosing slab sizes.
+    ///
+    /// When the slab size is chosen automatically, sizes holding up to about `objects` objects are
+    /// considered, and the one which wastes the least space per object is used. Larger values
+    /// allow larger slabs to be chosen in exchange for less wasted space. The default is 8.
+    pub fn objects_per_slab(mut self, objects: usize) -> SlabAllocBuilder<T, I, P> {
+        assert!(objects > 0);
+        self.config.objects_per_slab = objects;
+        self
+    }
+
+    /// Enables debug checks.
+    ///
+    /// With debug checks enabled, each object is surrounded by redzones filled with a known
+    /// pattern, and free objects which are not initialized (all free objects, if the allocator
+    /// does not perform initialization) are filled with a poison pattern. The redzones and poison
+    /// are verified whenever an object is allocated or freed and whenever a slab is returned to
+    /// the backing allocator, and freed pointers are checked to ensure that they point to an
+    /// object which is currently allocated. A buffer overflow or underflow, a write to a freed
+    /// object, a double free, or freeing a pointer which is not an object causes a panic.
+    ///
+    /// Debug checks make allocation and deallocation considerably slower and use extra space for
+    /// each object, and so are intended for testing rather than production use.
+    pub fn debug(mut self) -> SlabAllocBuilder<T, I, P> {
+        self.config.debug = true;
+        self
+    }
+
+    /// Sets the page size.
+    ///
+    /// Slabs are always a multiple of the page size and are always page-aligned, and backing
+    /// allocators are expected to provide memory with this granularity. By default, the system's
+    /// page size is used if the `os` feature is enabled, and 4096 bytes is used otherwise.
+    ///
+    /// `size` must be a power of two, must not be smaller than the alignment (see `align`), and
+    /// must evenly divide the slab size (see `slab_size`) if one has been set.
+    pub fn page_size(mut self, size: usize) -> SlabAllocBuilder<T, I, P> {
+        self.config.set_page_size(size, &self.layout);
+        self
+    }
+
+    /// Sets the policy used to decide when cached slabs are freed.
+    ///
+    /// By default, a `WorkingSetPolicy` with a working period of 15 seconds is used. See the
+    /// `reclaim` module for the available policies.
+    pub fn reclaim_policy<Q: ReclaimPolicy>(self, policy: Q) -> SlabAllocBuilder<T, I, Q> {
+        SlabAllocBuilder {
+            init: self.init,
+            layout: self.layout,
+            config: self.config,
+            policy: policy,
+            _marker: PhantomData,
+        }
+    }
+
     /// Builds a `SlabAlloc` whose memory is backed by the heap.
     #[cfg(feature = "std")]
-    pub fn build(self) -> SlabAlloc<T, I, HeapBackingAlloc> {
+    pub fn build(self) -> SlabAlloc<T, I, HeapBackingAlloc, P> {
         use backing::heap::{get_aligned, get_large};
         self.build_backing(get_aligned, get_large)
     }
@@ -163,14 +397,28 @@ impl<T, I: InitSystem> SlabAllocBuilder<T, I> {
     ///
     /// On Unix and Linux, `mmap` is used. On Windows, `VirtualAlloc` is used.
     #[cfg(feature = "os")]
-    pub fn build_mmap(self) -> SlabAlloc<T, I, MmapBackingAlloc> {
+    pub fn build_mmap(self) -> SlabAlloc<T, I, MmapBackingAlloc, P> {
         use backing::mmap::{get_aligned, get_large};
         self.build_backing(get_aligned, get_large)
     }
 
+    /// Builds a `SlabAlloc` whose memory is carved out of `region`.
+    ///
+    /// `region` does not need to be page-aligned, but space at its beginning may go unused in
+    /// order to align slabs. Once `region` is exhausted, allocating new slabs fails. Neither the
+    /// `std` nor the `os` feature is required. See `StaticRegionBackingAlloc` for details.
+    pub fn build_static(self,
+                        region: &'static mut [u8])
+                        -> SlabAlloc<T, I, StaticRegionBackingAlloc, P> {
+        use backing::static_region::{get_aligned, get_large};
+        let region = Cell::new(Some(region));
+        self.build_backing(|layout| get_aligned(&region, layout),
+                           |layout| get_large(&region, layout))
+    }
+
     /// Builds an `UntypedSlabAlloc` whose memory is backed by the heap.
     #[cfg(feature = "std")]
-    pub fn build_untyped(self) -> UntypedSlabAlloc<I, HeapBackingAlloc> {
+    pub fn build_untyped(self) -> UntypedSlabAlloc<I, HeapBackingAlloc, P> {
         use backing::heap::{get_aligned, get_large};
         self.build_untyped_backing(get_aligned, get_large)
     }
@@ -179,11 +427,56 @@ impl<T, I: InitSystem> SlabAllocBuilder<T, I> {
     ///
     /// On Unix and Linux, `mmap` is used. On Windows, `VirtualAlloc` is used.
     #[cfg(feature = "os")]
-    pub fn build_untyped_mmap(self) -> UntypedSlabAlloc<I, MmapBackingAlloc> {
+    pub fn build_untyped_mmap(self) -> UntypedSlabAlloc<I, MmapBackingAlloc, P> {
         use backing::mmap::{get_aligned, get_large};
         self.build_untyped_backing(get_aligned, get_large)
     }
 
+    /// Builds a `SlabAlloc` whose memory is provided by a single allocator of page-aligned memory.
+    ///
+    /// `get_large` is called with a `Layout` which is page-aligned and at least a page in size,
+    /// and must return an allocator for that layout, just like the `get_large` argument to
+    /// `build_backing`. Large slabs are allocated directly from such an allocator, while aligned
+    /// slabs are carved out of larger arenas allocated from one (see `ArenaBackingAlloc`). This
+    /// allows any source of page-aligned memory to back both types of slabs.
+    pub fn build_arena<A, F>(self, get_large: F) -> SlabAlloc<T, I, ArenaBackingAlloc<A>, P>
+        where A: UntypedObjectAlloc,
+              F: Fn(Layout) -> A
+    {
+        use backing::arena::get_aligned;
+        let page_size = self.config.page_size;
+        self.build_backing(|layout| Some(get_aligned(&get_large, layout, page_size)),
+                           |layout| get_large(layout))
+    }
+
+    /// Builds an `UntypedSlabAlloc` whose memory is provided by a single allocator of
+    /// page-aligned memory.
+    ///
+    /// See the documentation for `build_arena` for details.
+    pub fn build_untyped_arena<A, F>(self,
+                                     get_large: F)
+                                     -> UntypedSlabAlloc<I, ArenaBackingAlloc<A>, P>
+        where A: UntypedObjectAlloc,
+              F: Fn(Layout) -> A
+    {
+        use backing::arena::get_aligned;
+        let page_size = self.config.page_size;
+        self.build_untyped_backing(|layout| Some(get_aligned(&get_large, layout, page_size)),
+                                   |layout| get_large(layout))
+    }
+
+    /// Builds an `UntypedSlabAlloc` whose memory is carved out of `region`.
+    ///
+    /// See the documentation for `build_static` for details.
+    pub fn build_untyped_static(self,
+                                region: &'static mut [u8])
+                                -> UntypedSlabAlloc<I, StaticRegionBackingAlloc, P> {
+        use backing::static_region::{get_aligned, get_large};
+        let region = Cell::new(Some(region));
+        self.build_untyped_backing(|layout| get_aligned(&region, layout),
+                                   |layout| get_large(&region, layout))
+    }
+
     /// Builds a new `SlabAlloc` with a custom memory provider.
     ///
     /// `build_backing` builds a new `SlabAlloc` from the configuration `self`. `SlabAlloc`s get
@@ -205,24 +498,29 @@ impl<T, I: InitSystem> SlabAllocBuilder<T, I> {
     /// call `get_large` to get an allocator. It will only call `get_large` with a `Layout` that is
     /// required to be supported (at least a page in size and page-aligned), so `get_large` returns
     /// an allocator directly rather than an `Option`.
-    pub fn build_backing<B, A, L>(self, get_aligned: A, get_large: L) -> SlabAlloc<T, I, B>
+    pub fn build_backing<B, A, L>(self, get_aligned: A, get_large: L) -> SlabAlloc<T, I, B, P>
         where B: BackingAlloc,
               A: Fn(Layout) -> Option<B::Aligned>,
               L: Fn(Layout) -> B::Large
     {
-        let layout = util::misc::satisfy_min_align(self.layout.clone(), I::min_align());
-        let aligned_backing_size = aligned::backing_size_for::<I>(&layout);
-        let aligned_slab_layout =
-            Layout::from_size_align(aligned_backing_size, aligned_backing_size).unwrap();
+        let slabs = self.config
+            .build_slabs::<I, B, A, L>(self.layout.clone(), get_aligned, get_large);
         SlabAlloc {
-            alloc: if let Some(alloc) = get_aligned(aligned_slab_layout) {
-                let data = aligned::System::new(layout, alloc).unwrap();
-                PrivateSlabAlloc::Aligned(SizedSlabAlloc::new(self.init, self.layout, data))
-            } else {
-                let backing_size = large::backing_size_for::<I>(&layout);
-                let slab_layout = Layout::from_size_align(backing_size, *PAGE_SIZE).unwrap();
-                let data = large::System::new(layout, get_large(slab_layout)).unwrap();
-                PrivateSlabAlloc::Large(SizedSlabAlloc::new(self.init, self.layout, data))
+            alloc: match slabs {
+                Slabs::Aligned(data) => {
+                    PrivateSlabAlloc::Aligned(SizedSlabAlloc::new(self.init,
+                                                                  self.layout,
+                                                                  &self.config,
+                                                                  self.policy,
+                                                                  data))
+                }
+                Slabs::Large(data) => {
+                    PrivateSlabAlloc::Large(SizedSlabAlloc::new(self.init,
+                                                                self.layout,
+                                                                &self.config,
+                                                                self.policy,
+                                                                data))
+                }
             },
             _marker: PhantomData,
         }
@@ -235,24 +533,29 @@ impl<T, I: InitSystem> SlabAllocBuilder<T, I> {
     pub fn build_untyped_backing<B, A, L>(self,
                                           get_aligned: A,
                                           get_large: L)
-                                          -> UntypedSlabAlloc<I, B>
+                                          -> UntypedSlabAlloc<I, B, P>
         where B: BackingAlloc,
               A: Fn(Layout) -> Option<B::Aligned>,
               L: Fn(Layout) -> B::Large
     {
-        let layout = util::misc::satisfy_min_align(self.layout.clone(), I::min_align());
-        let aligned_backing_size = aligned::backing_size_for::<I>(&layout);
-        let aligned_slab_layout =
-            Layout::from_size_align(aligned_backing_size, aligned_backing_size).unwrap();
+        let slabs = self.config
+            .build_slabs::<I, B, A, L>(self.layout.clone(), get_aligned, get_large);
         UntypedSlabAlloc {
-            alloc: if let Some(alloc) = get_aligned(aligned_slab_layout) {
-                let data = aligned::System::new(layout, alloc).unwrap();
-                PrivateUntypedSlabAlloc::Aligned(SizedSlabAlloc::new(self.init, self.layout, data))
-            } else {
-                let backing_size = large::backing_size_for::<I>(&layout);
-                let slab_layout = Layout::from_size_align(backing_size, *PAGE_SIZE).unwrap();
-                let data = large::System::new(layout, get_large(slab_layout)).unwrap();
-                PrivateUntypedSlabAlloc::Large(SizedSlabAlloc::new(self.init, self.layout, data))
+            alloc: match slabs {
+                Slabs::Aligned(data) => {
+                    PrivateUntypedSlabAlloc::Aligned(SizedSlabAlloc::new(self.init,
+                                                                         self.layout,
+                                                                         &self.config,
+                                                                         self.policy,
+                                                                         data))
+                }
+                Slabs::Large(data) => {
+                    PrivateUntypedSlabAlloc::Large(SizedSlabAlloc::new(self.init,
+                                                                       self.layout,
+                                                                       &self.config,
+                                                                       self.policy,
+                                                                       data))
+                }
             },
         }
     }
@@ -268,6 +571,8 @@ impl<T: Default> SlabAllocBuilder<T, DefaultInitSystem<T>> {
         SlabAllocBuilder {
             init: DefaultInitSystem::new(DefaultInitializer::new()),
             layout: Layout::new::<T>(),
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
             _marker: PhantomData,
         }
     }
@@ -281,6 +586,8 @@ impl<T, F: Fn() -> T> SlabAllocBuilder<T, FnInitSystem<T, F>> {
         SlabAllocBuilder {
             init: FnInitSystem::new(FnInitializer::new(f)),
             layout: Layout::new::<T>(),
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
             _marker: PhantomData,
         }
     }
@@ -300,6 +607,8 @@ impl<T, F: Fn(*mut T)> SlabAllocBuilder<T, UnsafeFnInitSystem<T, F>> {
         SlabAllocBuilder {
             init: UnsafeFnInitSystem::new(UnsafeFnInitializer::new(f)),
             layout: Layout::new::<T>(),
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
             _marker: PhantomData,
         }
     }
@@ -316,39 +625,123 @@ impl<T> SlabAllocBuilder<T, NopInitSystem> {
         SlabAllocBuilder {
             init: NopInitSystem,
             layout: Layout::new::<T>(),
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
             _marker: PhantomData,
         }
     }
 }
 
 /// A builder for `UntypedSlabAlloc`s.
-pub struct UntypedSlabAllocBuilder<I: InitSystem> {
+pub struct UntypedSlabAllocBuilder<I: InitSystem, P: ReclaimPolicy = DefaultReclaimPolicy> {
     init: I,
     layout: Layout,
+    config: Config,
+    policy: P,
 }
 
-impl<I: InitSystem> UntypedSlabAllocBuilder<I> {
+impl<I: InitSystem, P: ReclaimPolicy> UntypedSlabAllocBuilder<I, P> {
     /// Updates the alignment guaranteed by the allocator.
     ///
     /// `align` must not be greater than the size of allocated objects, must not be greater than
-    /// the system's page size, and must be a power of two. The size of allocated objects must be a
-    /// multiple of `align`.
+    /// the page size (see `page_size`), and must be a power of two. The size of allocated objects
+    /// must be a multiple of `align`.
     ///
     /// If `align` is called multiple times, then the largest specified alignment will be used.
     /// Since all alignments must be powers of two, allocations which satisfy the largest specified
     /// alignment will also satisfy all smaller alignments.
-    pub fn align(mut self, align: usize) -> UntypedSlabAllocBuilder<I> {
+    pub fn align(mut self, align: usize) -> UntypedSlabAllocBuilder<I, P> {
         assert!(align.is_power_of_two());
         assert!(align <= self.layout.size());
         assert_eq!(self.layout.size() % align, 0);
-        assert!(align <= *PAGE_SIZE);
+        assert!(align <= self.config.page_size);
         self.layout = self.layout.align_to(align);
         self
     }
 
+    /// Sorts partially-full slabs into `buckets` buckets by occupancy.
+    ///
+    /// See the documentation for `SlabAllocBuilder::partial_slab_buckets` for details.
+    pub fn partial_slab_buckets(mut self, buckets: usize) -> UntypedSlabAllocBuilder<I, P> {
+        assert!(buckets > 0 && buckets <= util::list::MAX_BUCKETS);
+        self.config.partial_slab_buckets = buckets;
+        self
+    }
+
+    /// Enables or disables slab coloring.
+    ///
+    /// See the documentation for `SlabAllocBuilder::coloring` for details.
+    pub fn coloring(mut self, coloring: bool) -> UntypedSlabAllocBuilder<I, P> {
+        self.config.coloring = coloring;
+        self
+    }
+
+    /// Aligns every object to the beginning of a cache line.
+    ///
+    /// See the documentation for `SlabAllocBuilder::cache_line_align` for details.
+    pub fn cache_line_align(mut self) -> UntypedSlabAllocBuilder<I, P> {
+        self.config.cache_line_align = true;
+        self
+    }
+
+    /// Prefers aligned slabs over large slabs, even at the cost of space efficiency.
+    ///
+    /// See the documentation for `SlabAllocBuilder::prefer_aligned_slabs` for details.
+    pub fn prefer_aligned_slabs(mut self) -> UntypedSlabAllocBuilder<I, P> {
+        self.config.prefer_aligned = true;
+        self
+    }
+
+    /// Uses slabs of exactly `size` bytes.
+    ///
+    /// See the documentation for `SlabAllocBuilder::slab_size` for details.
+    pub fn slab_size(mut self, size: usize) -> UntypedSlabAllocBuilder<I, P> {
+        assert!(size > 0);
+        assert_eq!(size % self.config.page_size, 0);
+        self.config.slab_size = Some(size);
+        self
+    }
+
+    /// Sets the target number of objects per slab used when choosing slab sizes.
+    ///
+    /// See the documentation for `SlabAllocBuilder::objects_per_slab` for details.
+    pub fn objects_per_slab(mut self, objects: usize) -> UntypedSlabAllocBuilder<I, P> {
+        assert!(objects > 0);
+        self.config.objects_per_slab = objects;
+        self
+    }
+
+    /// Enables debug checks.
+    ///
+    /// See the documentation for `SlabAllocBuilder::debug` for details.
+    pub fn debug(mut self) -> UntypedSlabAllocBuilder<I, P> {
+        self.config.debug = true;
+        self
+    }
+
+    /// Sets the page size.
+    ///
+    /// See the documentation for `SlabAllocBuilder::page_size` for details.
+    pub fn page_size(mut self, size: usize) -> UntypedSlabAllocBuilder<I, P> {
+        self.config.set_page_size(size, &self.layout);
+        self
+    }
+
+    /// Sets the policy used to decide when cached slabs are freed.
+    ///
+    /// See the documentation for `SlabAllocBuilder::reclaim_policy` for details.
+    pub fn reclaim_policy<Q: ReclaimPolicy>(self, policy: Q) -> UntypedSlabAllocBuilder<I, Q> {
+        UntypedSlabAllocBuilder {
+            init: self.init,
+            layout: self.layout,
+            config: self.config,
+            policy: policy,
+        }
+    }
+
     /// Builds an `UntypedSlabAlloc` whose memory is backed by the heap.
     #[cfg(feature = "std")]
-    pub fn build(self) -> UntypedSlabAlloc<I, HeapBackingAlloc> {
+    pub fn build(self) -> UntypedSlabAlloc<I, HeapBackingAlloc, P> {
         use backing::heap::{get_aligned, get_large};
         self.build_backing(get_aligned, get_large)
     }
@@ -357,11 +750,37 @@ impl<I: InitSystem> UntypedSlabAllocBuilder<I> {
     ///
     /// On Unix and Linux, `mmap` is used. On Windows, `VirtualAlloc` is used.
     #[cfg(feature = "os")]
-    pub fn build_mmap(self) -> UntypedSlabAlloc<I, MmapBackingAlloc> {
+    pub fn build_mmap(self) -> UntypedSlabAlloc<I, MmapBackingAlloc, P> {
         use backing::mmap::{get_aligned, get_large};
         self.build_backing(get_aligned, get_large)
     }
 
+    /// Builds an `UntypedSlabAlloc` whose memory is provided by a single allocator of
+    /// page-aligned memory.
+    ///
+    /// See the documentation for `SlabAllocBuilder::build_arena` for details.
+    pub fn build_arena<A, F>(self, get_large: F) -> UntypedSlabAlloc<I, ArenaBackingAlloc<A>, P>
+        where A: UntypedObjectAlloc,
+              F: Fn(Layout) -> A
+    {
+        use backing::arena::get_aligned;
+        let page_size = self.config.page_size;
+        self.build_backing(|layout| Some(get_aligned(&get_large, layout, page_size)),
+                           |layout| get_large(layout))
+    }
+
+    /// Builds an `UntypedSlabAlloc` whose memory is carved out of `region`.
+    ///
+    /// See the documentation for `SlabAllocBuilder::build_static` for details.
+    pub fn build_static(self,
+                        region: &'static mut [u8])
+                        -> UntypedSlabAlloc<I, StaticRegionBackingAlloc, P> {
+        use backing::static_region::{get_aligned, get_large};
+        let region = Cell::new(Some(region));
+        self.build_backing(|layout| get_aligned(&region, layout),
+                           |layout| get_large(&region, layout))
+    }
+
     /// Builds a new `UntypedSlabAlloc` with a custom memory provider.
     ///
     /// `build_backing` builds a new `UntypedSlabAlloc` from the configuration `self`.
@@ -383,24 +802,29 @@ impl<I: InitSystem> UntypedSlabAllocBuilder<I> {
     /// call `get_large` to get an allocator. It will only call `get_large` with a `Layout` that is
     /// required to be supported (at least a page in size and page-aligned), so `get_large` returns
     /// an allocator directly rather than an `Option`.
-    pub fn build_backing<B, A, L>(self, get_aligned: A, get_large: L) -> UntypedSlabAlloc<I, B>
+    pub fn build_backing<B, A, L>(self, get_aligned: A, get_large: L) -> UntypedSlabAlloc<I, B, P>
         where B: BackingAlloc,
               A: Fn(Layout) -> Option<B::Aligned>,
               L: Fn(Layout) -> B::Large
     {
-        let layout = util::misc::satisfy_min_align(self.layout.clone(), I::min_align());
-        let aligned_backing_size = aligned::backing_size_for::<I>(&layout);
-        let aligned_slab_layout =
-            Layout::from_size_align(aligned_backing_size, aligned_backing_size).unwrap();
+        let slabs = self.config
+            .build_slabs::<I, B, A, L>(self.layout.clone(), get_aligned, get_large);
         UntypedSlabAlloc {
-            alloc: if let Some(alloc) = get_aligned(aligned_slab_layout) {
-                let data = aligned::System::new(layout, alloc).unwrap();
-                PrivateUntypedSlabAlloc::Aligned(SizedSlabAlloc::new(self.init, self.layout, data))
-            } else {
-                let backing_size = large::backing_size_for::<I>(&layout);
-                let slab_layout = Layout::from_size_align(backing_size, *PAGE_SIZE).unwrap();
-                let data = large::System::new(layout, get_large(slab_layout)).unwrap();
-                PrivateUntypedSlabAlloc::Large(SizedSlabAlloc::new(self.init, self.layout, data))
+            alloc: match slabs {
+                Slabs::Aligned(data) => {
+                    PrivateUntypedSlabAlloc::Aligned(SizedSlabAlloc::new(self.init,
+                                                                         self.layout,
+                                                                         &self.config,
+                                                                         self.policy,
+                                                                         data))
+                }
+                Slabs::Large(data) => {
+                    PrivateUntypedSlabAlloc::Large(SizedSlabAlloc::new(self.init,
+                                                                       self.layout,
+                                                                       &self.config,
+                                                                       self.policy,
+                                                                       data))
+                }
             },
         }
     }
@@ -411,6 +835,30 @@ impl<F: Fn(*mut u8)> UntypedSlabAllocBuilder<UnsafeFnInitSystem<u8, F>> {
         UntypedSlabAllocBuilder {
             init: UnsafeFnInitSystem::new(UnsafeFnInitializer::new(f)),
             layout: layout,
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
+        }
+    }
+}
+
+impl<F: Fn(*mut u8), D: Fn(*mut u8)> UntypedSlabAllocBuilder<UnsafeFnDropInitSystem<u8, F, D>> {
+    /// Constructs a new builder for an allocator which uses `f` to initialize allocated objects and
+    /// `d` to drop them.
+    ///
+    /// The constructed allocator will call `f` whenever a new object needs to be initialized, and
+    /// will call `d` on each initialized object that it has cached when the slab containing that
+    /// object is freed - either during garbage collection, during a call to `reclaim`, or when
+    /// the allocator itself is dropped. `d` is never called on objects which were not initialized
+    /// by `f`.
+    pub fn func_with_drop(layout: Layout,
+                          f: F,
+                          d: D)
+                          -> UntypedSlabAllocBuilder<UnsafeFnDropInitSystem<u8, F, D>> {
+        UntypedSlabAllocBuilder {
+            init: UnsafeFnDropInitSystem::new(UnsafeFnDropInitializer::new(f, d)),
+            layout: layout,
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
         }
     }
 }
@@ -420,12 +868,172 @@ impl UntypedSlabAllocBuilder<NopInitSystem> {
         UntypedSlabAllocBuilder {
             init: NopInitSystem,
             layout: layout,
+            config: Config::default(),
+            policy: DefaultReclaimPolicy::default(),
+        }
+    }
+}
+
+impl<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> SlabAlloc<T, I, B, P> {
+    /// Returns true if `ptr` points to an object in one of this allocator's slabs.
+    ///
+    /// `owns` returns true if `ptr` points to the beginning of an object in a slab currently held
+    /// by this allocator, regardless of whether that object is currently allocated. `ptr` may be
+    /// any pointer - it is never dereferenced unless it falls within one of this allocator's
+    /// slabs. Pointers into the middle of an object are not considered to be owned.
+    pub fn owns(&self, ptr: *mut T) -> bool {
+        self.object_index(ptr).is_some()
+    }
+
+    /// Returns the index of the object pointed to by `ptr` within its slab.
+    ///
+    /// If `owns(ptr)` is true, `object_index` returns the index of the object in the array of
+    /// objects in its slab. Otherwise, it returns `None`.
+    pub fn object_index(&self, ptr: *mut T) -> Option<usize> {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref alloc) => alloc.slab_system.object_index(ptr as *mut u8),
+            PrivateSlabAlloc::Large(ref alloc) => alloc.slab_system.object_index(ptr as *mut u8),
+        }
+    }
+
+    /// Calls `f` on each of the allocator's slabs.
+    ///
+    /// Every slab currently held by the allocator is visited, including slabs none of whose
+    /// objects are allocated.
+    pub fn for_each_slab<F: FnMut(&SlabInfo)>(&self, mut f: F) {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref alloc) => alloc.walk_slabs(&mut f),
+            PrivateSlabAlloc::Large(ref alloc) => alloc.walk_slabs(&mut f),
+        }
+    }
+
+    /// Calls `f` on each allocated object along with the slab containing it.
+    ///
+    /// Objects in the same slab are visited consecutively. Objects which have been freed are not
+    /// visited, even if they are initialized.
+    pub fn for_each_allocated<F: FnMut(*mut T, &SlabInfo)>(&self, mut f: F) {
+        let mut f = |obj, slab: &SlabInfo| f(obj as *mut T, slab);
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref alloc) => alloc.walk_allocated(&mut f),
+            PrivateSlabAlloc::Large(ref alloc) => alloc.walk_allocated(&mut f),
+        }
+    }
+
+    /// Returns a report of the objects which are currently allocated.
+    ///
+    /// See the `walk` module for details.
+    pub fn leak_report(&self) -> LeakReport {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref alloc) => LeakReport::new(alloc),
+            PrivateSlabAlloc::Large(ref alloc) => LeakReport::new(alloc),
+        }
+    }
+
+    /// Allocates a new object, recording the site at which it was allocated.
+    ///
+    /// `alloc_at` behaves like `alloc`, except that `site` is recorded until the object is freed.
+    /// It can be retrieved with `alloc_site`, and is included in leak reports. `site` is normally
+    /// obtained with the `alloc_site!` macro. Recording sites requires a hash table lookup on each
+    /// subsequent call to `dealloc`.
+    pub unsafe fn alloc_at(&mut self, site: &'static AllocSite) -> Result<*mut T, Error> {
+        match self.alloc {
+                PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.alloc_at(site),
+                PrivateSlabAlloc::Large(ref mut alloc) => alloc.alloc_at(site),
+            }
+            .map(|ptr| ptr as *mut T)
+    }
+
+    /// Returns the site at which `ptr` was allocated, if it was allocated with `alloc_at`.
+    pub fn alloc_site(&self, ptr: *mut T) -> Option<&'static AllocSite> {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref alloc) => alloc.alloc_site(ptr as *mut u8),
+            PrivateSlabAlloc::Large(ref alloc) => alloc.alloc_site(ptr as *mut u8),
+        }
+    }
+
+    /// Returns the map from objects to slabs if the allocator uses large slabs.
+    #[cfg(feature = "std")]
+    fn large_slab_map(&self) -> Option<alloc::arc::Arc<large::SlabMap>> {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(_) => None,
+            PrivateSlabAlloc::Large(ref alloc) => Some(alloc.slab_system.data.map.clone()),
         }
     }
 }
 
-unsafe impl<T, I: InitSystem, B: BackingAlloc> ObjectAlloc<T> for SlabAlloc<T, I, B> {
-    unsafe fn alloc(&mut self) -> Result<*mut T, Exhausted> {
+impl<I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> UntypedSlabAlloc<I, B, P> {
+    /// Returns true if `ptr` points to an object in one of this allocator's slabs.
+    ///
+    /// See the documentation for `SlabAlloc::owns` for details.
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        self.object_index(ptr).is_some()
+    }
+
+    /// Returns the index of the object pointed to by `ptr` within its slab.
+    ///
+    /// See the documentation for `SlabAlloc::object_index` for details.
+    pub fn object_index(&self, ptr: *mut u8) -> Option<usize> {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref alloc) => alloc.slab_system.object_index(ptr),
+            PrivateUntypedSlabAlloc::Large(ref alloc) => alloc.slab_system.object_index(ptr),
+        }
+    }
+
+    /// Calls `f` on each of the allocator's slabs.
+    ///
+    /// See the documentation for `SlabAlloc::for_each_slab` for details.
+    pub fn for_each_slab<F: FnMut(&SlabInfo)>(&self, mut f: F) {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref alloc) => alloc.walk_slabs(&mut f),
+            PrivateUntypedSlabAlloc::Large(ref alloc) => alloc.walk_slabs(&mut f),
+        }
+    }
+
+    /// Calls `f` on each allocated object along with the slab containing it.
+    ///
+    /// See the documentation for `SlabAlloc::for_each_allocated` for details.
+    pub fn for_each_allocated<F: FnMut(*mut u8, &SlabInfo)>(&self, mut f: F) {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref alloc) => alloc.walk_allocated(&mut f),
+            PrivateUntypedSlabAlloc::Large(ref alloc) => alloc.walk_allocated(&mut f),
+        }
+    }
+
+    /// Returns a report of the objects which are currently allocated.
+    ///
+    /// See the `walk` module for details.
+    pub fn leak_report(&self) -> LeakReport {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref alloc) => LeakReport::new(alloc),
+            PrivateUntypedSlabAlloc::Large(ref alloc) => LeakReport::new(alloc),
+        }
+    }
+
+    /// Allocates a new object, recording the site at which it was allocated.
+    ///
+    /// See the documentation for `SlabAlloc::alloc_at` for details.
+    pub unsafe fn alloc_at(&mut self, site: &'static AllocSite) -> Result<*mut u8, Error> {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref mut alloc) => alloc.alloc_at(site),
+            PrivateUntypedSlabAlloc::Large(ref mut alloc) => alloc.alloc_at(site),
+        }
+    }
+
+    /// Returns the site at which `ptr` was allocated, if it was allocated with `alloc_at`.
+    pub fn alloc_site(&self, ptr: *mut u8) -> Option<&'static AllocSite> {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref alloc) => alloc.alloc_site(ptr),
+            PrivateUntypedSlabAlloc::Large(ref alloc) => alloc.alloc_site(ptr),
+        }
+    }
+}
+
+unsafe impl<T, I, B, P> ObjectAlloc<T> for SlabAlloc<T, I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    unsafe fn alloc(&mut self) -> Result<*mut T, Error> {
         match self.alloc {
                 PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.alloc(),
                 PrivateSlabAlloc::Large(ref mut alloc) => alloc.alloc(),
@@ -439,9 +1047,29 @@ unsafe impl<T, I: InitSystem, B: BackingAlloc> ObjectAlloc<T> for SlabAlloc<T, I
             PrivateSlabAlloc::Large(ref mut alloc) => alloc.dealloc(x as *mut u8),
         }
     }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut T]) -> usize {
+        // NOTE: This is safe because *mut u8 and *mut T have the same size and representation.
+        let out = slice::from_raw_parts_mut(out.as_mut_ptr() as *mut *mut u8, out.len());
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.alloc_batch(out),
+            PrivateSlabAlloc::Large(ref mut alloc) => alloc.alloc_batch(out),
+        }
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.reclaim(amount),
+            PrivateSlabAlloc::Large(ref mut alloc) => alloc.reclaim(amount),
+        }
+    }
 }
 
-unsafe impl<T, I: InitSystem, B: BackingAlloc> UntypedObjectAlloc for SlabAlloc<T, I, B> {
+unsafe impl<T, I, B, P> UntypedObjectAlloc for SlabAlloc<T, I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
     fn layout(&self) -> Layout {
         match self.alloc {
             PrivateSlabAlloc::Aligned(ref alloc) => alloc.layout.clone(),
@@ -449,7 +1077,7 @@ unsafe impl<T, I: InitSystem, B: BackingAlloc> UntypedObjectAlloc for SlabAlloc<
         }
     }
 
-    unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
         match self.alloc {
             PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.alloc(),
             PrivateSlabAlloc::Large(ref mut alloc) => alloc.alloc(),
@@ -462,9 +1090,27 @@ unsafe impl<T, I: InitSystem, B: BackingAlloc> UntypedObjectAlloc for SlabAlloc<
             PrivateSlabAlloc::Large(ref mut alloc) => alloc.dealloc(x),
         }
     }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.alloc_batch(out),
+            PrivateSlabAlloc::Large(ref mut alloc) => alloc.alloc_batch(out),
+        }
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref mut alloc) => alloc.reclaim(amount),
+            PrivateSlabAlloc::Large(ref mut alloc) => alloc.reclaim(amount),
+        }
+    }
 }
 
-unsafe impl<I: InitSystem, B: BackingAlloc> UntypedObjectAlloc for UntypedSlabAlloc<I, B> {
+unsafe impl<I, B, P> UntypedObjectAlloc for UntypedSlabAlloc<I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
     fn layout(&self) -> Layout {
         match self.alloc {
             PrivateUntypedSlabAlloc::Aligned(ref alloc) => alloc.layout.clone(),
@@ -472,7 +1118,7 @@ unsafe impl<I: InitSystem, B: BackingAlloc> UntypedObjectAlloc for UntypedSlabAl
         }
     }
 
-    unsafe fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
+    unsafe fn alloc(&mut self) -> Result<*mut u8, Error> {
         match self.alloc {
             PrivateUntypedSlabAlloc::Aligned(ref mut alloc) => alloc.alloc(),
             PrivateUntypedSlabAlloc::Large(ref mut alloc) => alloc.alloc(),
@@ -485,14 +1131,70 @@ unsafe impl<I: InitSystem, B: BackingAlloc> UntypedObjectAlloc for UntypedSlabAl
             PrivateUntypedSlabAlloc::Large(ref mut alloc) => alloc.dealloc(x),
         }
     }
+
+    unsafe fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref mut alloc) => alloc.alloc_batch(out),
+            PrivateUntypedSlabAlloc::Large(ref mut alloc) => alloc.alloc_batch(out),
+        }
+    }
+
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref mut alloc) => alloc.reclaim(amount),
+            PrivateUntypedSlabAlloc::Large(ref mut alloc) => alloc.reclaim(amount),
+        }
+    }
+}
+
+/// Reports statistics for the allocator.
+///
+/// Cached objects include both objects which have been initialized and returned to the allocator
+/// and objects which have never been allocated. Backing memory is obtained from the allocator's
+/// `BackingAlloc`, and is assumed to be committed.
+impl<T, I, B, P> AllocatorStats for SlabAlloc<T, I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    fn stats(&self) -> Stats {
+        match self.alloc {
+            PrivateSlabAlloc::Aligned(ref alloc) => alloc.stats(),
+            PrivateSlabAlloc::Large(ref alloc) => alloc.stats(),
+        }
+    }
 }
 
-struct SizedSlabAlloc<I: InitSystem, S: SlabSystem<I>> {
-    freelist: LinkedList<S::Slab>, // partial slabs first, followed by full slabs
+/// Reports statistics for the allocator.
+///
+/// See the `AllocatorStats` implementation for `SlabAlloc` for details.
+impl<I, B, P> AllocatorStats for UntypedSlabAlloc<I, B, P>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    fn stats(&self) -> Stats {
+        match self.alloc {
+            PrivateUntypedSlabAlloc::Aligned(ref alloc) => alloc.stats(),
+            PrivateUntypedSlabAlloc::Large(ref alloc) => alloc.stats(),
+        }
+    }
+}
+
+struct SizedSlabAlloc<I: InitSystem, S: SlabSystem<I>, P: ReclaimPolicy> {
+    // Partially-full slabs, bucketed by the number of available objects so that the emptiest
+    // slabs (those with the fewest available objects) are allocated from first.
+    partial: BucketList<S::Slab>,
+    full: LinkedList<S::Slab>,
+    // Slabs with no available objects. They are never allocated from, but are kept track of so
+    // that the heap can be walked.
+    empty: LinkedList<S::Slab>,
     total_slabs: usize,
-    num_full: usize, // number of full slabs
     refcnt: usize,
-    full_slab_working_set: WorkingSet<usize>, /* minimum number of slabs full at every moment during this working period */
+    policy: P,
+    // The sites at which objects allocated with alloc_at were allocated. It is only created once
+    // alloc_at is first called.
+    sites: Option<Map<u8, AllocSite>>,
 
     slab_system: S,
     init_system: I,
@@ -500,91 +1202,202 @@ struct SizedSlabAlloc<I: InitSystem, S: SlabSystem<I>> {
     layout: Layout,
 }
 
-impl<I: InitSystem, S: SlabSystem<I>> SizedSlabAlloc<I, S> {
-    fn new(init: I, layout: Layout, slabs: S) -> SizedSlabAlloc<I, S> {
+impl<I: InitSystem, S: SlabSystem<I>, P: ReclaimPolicy> SizedSlabAlloc<I, S, P> {
+    fn new(init: I,
+           layout: Layout,
+           config: &Config,
+           policy: P,
+           slabs: S)
+           -> SizedSlabAlloc<I, S, P> {
         SizedSlabAlloc {
-            freelist: LinkedList::new(),
+            partial: BucketList::new(config.partial_slab_buckets),
+            full: LinkedList::new(),
+            empty: LinkedList::new(),
             total_slabs: 0,
-            num_full: 0,
             refcnt: 0,
-            full_slab_working_set: WorkingSet::new(0),
+            policy: policy,
+            sites: None,
             slab_system: slabs,
             init_system: init,
             layout: layout,
         }
     }
 
-    fn alloc(&mut self) -> Result<*mut u8, Exhausted> {
-        if self.freelist.size() == 0 {
-            let ok = self.alloc_slab();
-            if !ok {
-                return Err(Exhausted);
-            }
+    /// Compute the bucket of a partially-full slab with `available` available objects.
+    fn bucket_for(&self, available: usize) -> usize {
+        debug_assert!(available > 0 && available < self.slab_system.objects_per_slab());
+        if self.partial.num_buckets() == 1 {
+            // the default; avoid the division on the fast path
+            return 0;
         }
+        available * self.partial.num_buckets() / self.slab_system.objects_per_slab()
+    }
 
-        let slab = self.freelist.peek_front();
-        if self.slab_system.is_full(slab) {
-            self.num_full -= 1;
-            self.full_slab_working_set.update_min(self.num_full);
+    /// Get a slab with at least one available object.
+    ///
+    /// If there are any partially-full slabs, one from the lowest bucket is returned along with
+    /// that bucket. Otherwise, a full slab is removed from the full list (allocating a new one if
+    /// necessary), and `None` is returned in place of the bucket.
+    fn get_slab(&mut self) -> Result<(*mut S::Slab, Option<usize>), Error> {
+        if self.partial.size() > 0 {
+            let (slab, bucket) = self.partial.peek_lowest();
+            return Ok((slab, Some(bucket)));
+        }
+        if self.full.size() == 0 {
+            self.alloc_slab()?;
         }
+        let slab = self.full.remove_front();
+        self.policy.update(self.full.size());
+        Ok((slab, None))
+    }
 
-        let (obj, init_status) = self.slab_system.alloc(slab);
-        if self.slab_system.is_empty(slab) {
-            self.freelist.remove_front();
+    /// Return a slab obtained from `get_slab` after allocating from it.
+    ///
+    /// `bucket` is the bucket returned by `get_slab`.
+    fn put_slab(&mut self, slab: *mut S::Slab, bucket: Option<usize>) {
+        let available = self.slab_system.num_available(slab);
+        match (bucket, available) {
+            (Some(bucket), 0) => {
+                self.partial.remove(slab, bucket);
+                self.empty.insert_back(slab);
+            }
+            (Some(bucket), available) => {
+                let new = self.bucket_for(available);
+                self.partial.move_to(slab, bucket, new);
+            }
+            (None, 0) => self.empty.insert_back(slab),
+            (None, available) => {
+                let new = self.bucket_for(available);
+                self.partial.insert(slab, new);
+            }
         }
+    }
+
+    fn alloc(&mut self) -> Result<*mut u8, Error> {
+        // Report the failure in terms of the object that was requested rather than the slab.
+        let (slab, bucket) = self.get_slab()
+            .map_err(|err| err.with_layout(self.layout.clone()))?;
+        let (obj, init_status) = self.slab_system.alloc(slab);
+        self.put_slab(slab, bucket);
         self.refcnt += 1;
         debug_assert_eq!(obj as usize % self.layout.align(), 0);
         self.init_system.init(obj, init_status);
         Ok(obj)
     }
 
-    /// Allocate a new slab.
+    fn slab_info(&self, slab: *mut S::Slab) -> SlabInfo {
+        let objects = self.slab_system.objects_per_slab();
+        SlabInfo {
+            addr: slab as *mut u8,
+            size: self.slab_system.slab_size(),
+            objects: objects,
+            allocated: objects - self.slab_system.num_available(slab),
+        }
+    }
+
+    fn alloc_at(&mut self, site: &'static AllocSite) -> Result<*mut u8, Error> {
+        let obj = self.alloc()?;
+        if self.sites.is_none() {
+            self.sites = Some(Map::new(SITE_MAP_SIZE, self.layout.align()));
+        }
+        if let Some(ref mut sites) = self.sites {
+            sites.insert(obj, site as *const AllocSite as *mut AllocSite);
+        }
+        Ok(obj)
+    }
+
+    /// Allocate multiple objects.
     ///
-    /// Allocates a new slab and inserts it onto the back of the freelist. Returns `true` upon
-    /// success and `false` upon failure.
-    fn alloc_slab(&mut self) -> bool {
-        let new = self.slab_system.alloc_slab();
-        if new.is_null() {
-            return false;
+    /// `alloc_batch` fills `out` with newly-allocated objects, returning the number of objects
+    /// allocated. Rather than performing the freelist bookkeeping once per object, it drains each
+    /// slab's stack in turn, only touching the freelist when it moves on to the next slab. It
+    /// returns early only if a new slab cannot be allocated.
+    fn alloc_batch(&mut self, out: &mut [*mut u8]) -> usize {
+        let mut filled = 0;
+        while filled < out.len() {
+            let (slab, bucket) = match self.get_slab() {
+                Ok(res) => res,
+                Err(_) => break,
+            };
+
+            while filled < out.len() && !self.slab_system.is_empty(slab) {
+                let (obj, init_status) = self.slab_system.alloc(slab);
+                debug_assert_eq!(obj as usize % self.layout.align(), 0);
+                self.init_system.init(obj, init_status);
+                out[filled] = obj;
+                filled += 1;
+            }
+            self.put_slab(slab, bucket);
         }
+        self.refcnt += filled;
+        filled
+    }
+
+    fn stats(&self) -> Stats {
+        let bytes = self.total_slabs * self.slab_system.slab_size();
+        Stats {
+            live_objects: self.refcnt,
+            cached_objects: self.total_slabs * self.slab_system.objects_per_slab() - self.refcnt,
+            bytes_mapped: bytes,
+            bytes_committed: bytes,
+            slabs: self.total_slabs,
+        }
+    }
+
+    /// Allocate a new slab.
+    ///
+    /// Allocates a new slab and inserts it onto the back of the full list.
+    fn alloc_slab(&mut self) -> Result<(), Error> {
+        let new = self.slab_system.alloc_slab()?;
 
         // technically it doesn't matter whether it's back or front since this is only called when
         // the list is currently empty
-        self.freelist.insert_back(new);
+        self.full.insert_back(new);
         self.total_slabs += 1;
-        self.num_full += 1;
-        true
+        Ok(())
     }
 
     fn dealloc(&mut self, ptr: *mut u8) {
         debug_assert_eq!(ptr as usize % self.layout.align(), 0);
+        debug_assert!(self.slab_system.object_index(ptr).is_some(),
+                      "object {:?} freed to a slab allocator that did not allocate it",
+                      ptr);
+        if let Some(ref mut sites) = self.sites {
+            if sites.try_get(ptr).is_some() {
+                sites.delete(ptr);
+            }
+        }
         let (slab, was_empty) = self.slab_system.dealloc(ptr, I::status_initialized());
         let is_full = self.slab_system.is_full(slab);
+        let available = self.slab_system.num_available(slab);
+        if was_empty {
+            self.empty.remove(slab);
+        }
 
-        match (was_empty, is_full) {
-            // !was_empty implies it's already in the freelist; is_full implies it should be
-            // moved to the back of the freelist
-            (false, true) => {
-                self.freelist.move_to_back(slab);
-                self.num_full += 1;
-            }
-            // was_empty implies it's not already in the freelist; is_full implies it should be
-            // added to the back of the freelist (note: only possible if slabs have size 1 -
-            // they go from empty to full in a single free)
-            (true, true) => {
-                self.freelist.insert_back(slab);
-                self.num_full += 1;
+        // !was_empty implies it's already in the partial list, in the bucket corresponding to the
+        // number of objects that were available before this free
+        if !was_empty {
+            let old = self.bucket_for(available - 1);
+            if is_full {
+                self.partial.remove(slab, old);
+            } else {
+                let new = self.bucket_for(available);
+                self.partial.move_to(slab, old, new);
             }
-            // was_empty implies it's not already in the freelist; !is_full implies it should
-            // be added to the front since it's now partially-full
-            (true, false) => self.freelist.insert_front(slab),
-            // !was_empty implies it's already in the front section of the freelist; !is_full
-            // implies it should stay there
-            (false, false) => {}
+        } else if !is_full {
+            // was_empty implies it was in the empty list; !is_full implies it should be added to
+            // the partial list since it's now partially-full
+            let new = self.bucket_for(available);
+            self.partial.insert(slab, new);
         }
 
         if is_full {
-            // TODO: document the logic behind only doing this when a slab becomes full
+            // Newly-full slabs go on the back of the full list, which is where garbage collection
+            // frees slabs from. Note that was_empty and is_full are both true only if slabs have
+            // size 1 - they go from empty to full in a single free.
+            self.full.insert_back(slab);
+            // A slab becoming full is the only event which can cause the policy to want to free
+            // more slabs than it did before.
             self.garbage_collect_slabs();
         }
 
@@ -592,34 +1405,101 @@ impl<I: InitSystem, S: SlabSystem<I>> SizedSlabAlloc<I, S> {
     }
 
     fn garbage_collect_slabs(&mut self) {
-        if let Some(min_full) = self.full_slab_working_set
-               .refresh(WORKING_PERIOD_SECONDS) {
-            for _ in 0..min_full {
-                let slab = self.freelist.remove_back();
-                self.slab_system.dealloc_slab(slab);
-                self.total_slabs -= 1;
-                self.num_full -= 1;
+        let to_free = self.policy
+            .slabs_to_free(self.full.size(), self.slab_system.slab_size());
+        debug_assert!(to_free <= self.full.size());
+        for _ in 0..to_free {
+            let slab = self.full.remove_back();
+            self.slab_system.dealloc_slab(slab, &self.init_system);
+            self.total_slabs -= 1;
+        }
+    }
+
+    /// Free full slabs, returning the number of bytes freed.
+    ///
+    /// Full slabs are freed from the back of the full list until `amount` is satisfied or there
+    /// are no full slabs left. Freeing a slab drops any initialized objects it contains.
+    fn reclaim(&mut self, amount: Reclaim) -> usize {
+        let slab_size = self.slab_system.slab_size();
+        let mut freed = 0;
+        while self.full.size() > 0 {
+            let done = match amount {
+                Reclaim::All => false,
+                Reclaim::AtLeast(bytes) => freed >= bytes,
+                Reclaim::KeepAtMost(bytes) => self.full.size() * slab_size <= bytes,
+            };
+            if done {
+                break;
             }
-            self.full_slab_working_set.set(self.num_full);
+
+            let slab = self.full.remove_back();
+            self.slab_system.dealloc_slab(slab, &self.init_system);
+            self.total_slabs -= 1;
+            freed += slab_size;
         }
+        self.policy.update(self.full.size());
+        freed
+    }
+}
+
+impl<I: InitSystem, S: SlabSystem<I>, P: ReclaimPolicy> HeapWalk for SizedSlabAlloc<I, S, P> {
+    fn walk_slabs(&self, f: &mut FnMut(&SlabInfo)) {
+        let mut visit = |slab: *mut S::Slab| f(&self.slab_info(slab));
+        self.empty.for_each(&mut visit);
+        self.partial.for_each(&mut visit);
+        self.full.for_each(&mut visit);
     }
+
+    fn walk_allocated(&self, f: &mut FnMut(*mut u8, &SlabInfo)) {
+        // full slabs have no allocated objects
+        let mut visit = |slab: *mut S::Slab| {
+            let info = self.slab_info(slab);
+            self.slab_system
+                .for_each_allocated(slab, &mut |obj| f(obj, &info));
+        };
+        self.empty.for_each(&mut visit);
+        self.partial.for_each(&mut visit);
+    }
+
+    fn alloc_site(&self, obj: *mut u8) -> Option<&'static AllocSite> {
+        match self.sites {
+            Some(ref sites) => sites.try_get(obj).map(|site| unsafe { &*site }),
+            None => None,
+        }
+    }
+
+    fn live_objects(&self) -> usize {
+        self.refcnt
+    }
+}
+
+#[cfg(feature = "std")]
+fn panicking() -> bool {
+    std::thread::panicking()
+}
+
+// Without std, there's no way to tell whether the current thread is panicking.
+#[cfg(not(feature = "std"))]
+fn panicking() -> bool {
+    false
 }
 
-impl<I: InitSystem, S: SlabSystem<I>> Drop for SizedSlabAlloc<I, S> {
+impl<I: InitSystem, S: SlabSystem<I>, P: ReclaimPolicy> Drop for SizedSlabAlloc<I, S, P> {
     fn drop(&mut self) {
         if self.refcnt != 0 {
-            if std::thread::panicking() {
+            if panicking() {
                 // TODO: We shouldn't panic here because then we'd panic while panicking, and just
                 // abort without printing a useful message. We should figure out an alternative
                 // that allows us to properly print a diagnostic.
             } else {
-                panic!("non-zero refcount when dropping slab allocator");
+                panic!("non-zero refcount when dropping slab allocator: {}",
+                       LeakReport::new(&*self));
             }
         }
 
-        while self.freelist.size() > 0 {
-            let slab = self.freelist.remove_front();
-            self.slab_system.dealloc_slab(slab);
+        while self.full.size() > 0 {
+            let slab = self.full.remove_front();
+            self.slab_system.dealloc_slab(slab, &self.init_system);
         }
     }
 }
@@ -630,15 +1510,29 @@ trait SlabSystem<I: InitSystem> {
     /// Allocate a new `Slab`.
     ///
     /// The returned `Slab` has its next and previous pointers initialized to null.
-    fn alloc_slab(&mut self) -> *mut Self::Slab;
-    fn dealloc_slab(&mut self, slab: *mut Self::Slab);
+    fn alloc_slab(&mut self) -> Result<*mut Self::Slab, Error>;
+    /// Deallocate a `Slab`, using `init_system` to drop any initialized objects it contains.
+    fn dealloc_slab(&mut self, slab: *mut Self::Slab, init_system: &I);
 
     /// `is_full` returns true if all objects are available for allocation.
     fn is_full(&self, slab: *mut Self::Slab) -> bool;
+    /// `objects_per_slab` returns the number of objects in each `Slab`.
+    fn objects_per_slab(&self) -> usize;
+    /// `slab_size` returns the size of the backing memory used by each `Slab`.
+    fn slab_size(&self) -> usize;
     /// `is_empty` returns true if no objects are available for allocation.
     fn is_empty(&self, slab: *mut Self::Slab) -> bool;
+    /// `num_available` returns the number of objects available for allocation.
+    fn num_available(&self, slab: *mut Self::Slab) -> usize;
     /// `alloc` allocates a new object from the given `Slab`.
     fn alloc(&self, slab: *mut Self::Slab) -> (*mut u8, I::Status);
+    /// `object_index` returns the index within its `Slab` of the object pointed to by `ptr`, or
+    /// `None` if `ptr` does not point to the beginning of an object in one of this system's
+    /// `Slab`s. `ptr` may be any pointer.
+    fn object_index(&self, ptr: *mut u8) -> Option<usize>;
+    /// `for_each_allocated` calls `f` on each object in the given `Slab` which is currently
+    /// allocated.
+    fn for_each_allocated(&self, slab: *mut Self::Slab, f: &mut FnMut(*mut u8));
     /// `dealloc` deallocates the given object. It is `dealloc`'s responsibility to find the
     /// object's parent `Slab` and return it. It also returns whether the `Slab` was empty prior to
     /// deallocation.
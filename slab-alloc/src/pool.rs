@@ -0,0 +1,50 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+use core::cell::RefCell;
+
+use {ReclaimPolicy, SlabAlloc};
+use reclaim::DefaultReclaimPolicy;
+use backing::BackingAlloc;
+use init::InitSystem;
+use object_alloc::Error;
+use object_alloc::boxed::ObjectBox;
+
+/// A pool of objects backed by a `SlabAlloc`.
+///
+/// An `ObjectPool` hands out `ObjectBox`es, which return their objects to the pool when they are
+/// dropped. Unlike using a `SlabAlloc` directly, an `ObjectPool` can be shared by reference, and
+/// any number of objects allocated from it can be alive at once.
+pub struct ObjectPool<T, I, B, P = DefaultReclaimPolicy>
+    where I: InitSystem,
+          B: BackingAlloc,
+          P: ReclaimPolicy
+{
+    alloc: RefCell<SlabAlloc<T, I, B, P>>,
+}
+
+impl<T, I: InitSystem, B: BackingAlloc, P: ReclaimPolicy> ObjectPool<T, I, B, P> {
+    /// Constructs a new `ObjectPool` which allocates objects from `alloc`.
+    pub fn new(alloc: SlabAlloc<T, I, B, P>) -> ObjectPool<T, I, B, P> {
+        ObjectPool { alloc: RefCell::new(alloc) }
+    }
+
+    /// Allocates a new object.
+    ///
+    /// The state of the returned object is as described in the documentation for `SlabAlloc`'s
+    /// `alloc` method.
+    pub fn alloc(&self) -> Result<ObjectBox<T, SlabAlloc<T, I, B, P>>, Error> {
+        ObjectBox::new(&self.alloc)
+    }
+
+    /// Consumes the `ObjectPool`, returning the underlying `SlabAlloc`.
+    ///
+    /// Since every `ObjectBox` borrows the pool, all objects have been returned to the allocator
+    /// by the time `into_inner` can be called.
+    pub fn into_inner(self) -> SlabAlloc<T, I, B, P> {
+        self.alloc.into_inner()
+    }
+}
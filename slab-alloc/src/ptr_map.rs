@@ -29,7 +29,7 @@
 //   number of objects are freed very quickly and then allocated very quickly, the working set
 //   algorithm will refuse to free these slabs. The only way for thrashing to be a problem is for
 //   the sequence of frees and the sequence of allocs to be separated by a full working period
-//   (15 seconds as of the writing of this comment).
+//   (15 seconds by default as of the writing of this comment).
 //
 // Given this workload profile, we make the following design decisions:
 // - We assume that, since insertions and deletions are infrequent, the cost of allocating and
@@ -55,8 +55,44 @@
 //   a similar shrinking algorithm - to halve the number of buckets when the number of elements
 //   stored is equal to half the number of buckets.
 
+// Concurrent design
+//
+// ConcurrentPtrHashMap is a variant for use by allocators shared between threads. It makes the
+// same assumptions about its workload, and additionally assumes that lookups may happen on any
+// number of threads at once, including while another thread is inserting or deleting. Lookups
+// take no locks; insertions and deletions are serialized by a spin lock (since they are
+// infrequent, contention is assumed to be rare). Given this, we make the following additional
+// design decisions:
+// - Each slot holds an atomic key and an atomic value. A writer always clears a slot's key before
+//   changing its value, and only then stores the new key. A reader loads the key, then the value,
+//   and then the key again, and only accepts the value if the key didn't change. Thus, a reader
+//   can never return a value belonging to a different key.
+// - Memory reachable by readers is never freed while the map is alive. Overflow buckets stay in
+//   their chain once allocated, even when they become empty. Tables that have been resized away
+//   are kept until the map is dropped; since tables only ever double in size, this at most
+//   doubles the space used by the map. The concurrent map never shrinks.
+// - Resizing is incremental so that no single insertion pays for rehashing the whole table.
+//   When the table needs to grow, a new table is installed alongside the old one, and every
+//   subsequent insertion or deletion migrates MIGRATION_STEP buckets from the old table to the
+//   new one. Since the new table has as many more slots as the old table has buckets, migration
+//   always finishes before the new table needs to grow. Lookups check the old table first and
+//   then the new one. Migration copies an entry into the new table before removing it from the
+//   old one, so a lookup that misses an entry in the old table will find it in the new one.
+// - Some writes can still make a concurrent lookup miss a present key: installing a new table
+//   (a lookup might see no old table, and then the new table before anything has been migrated
+//   to it) and coalescing (an entry moved to an earlier slot might be missed in both places).
+//   These writes are bracketed by a sequence lock: the writer increments a counter before and
+//   after, and a lookup that doesn't find its key retries if the counter was odd or changed in
+//   the meantime. Lookups that find their key never retry.
+
 extern crate alloc;
+#[cfg(not(feature = "std"))]
+use self::alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use self::alloc::vec::Vec;
+use core::cell::UnsafeCell;
 use core::ptr;
+use core::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
 
 const BUCKET_SIZE: usize = 4;
 
@@ -135,6 +171,16 @@ impl<K, V> PtrHashMap<K, V> {
         self.get_bucket(ptr).get(ptr)
     }
 
+    /// Gets the value associated with the given key, or `None` if the key does not exist.
+    #[inline]
+    pub fn try_get(&self, ptr: *mut K) -> Option<*mut V> {
+        if ptr.is_null() {
+            // null marks an empty slot, so it would spuriously match
+            return None;
+        }
+        self.get_bucket(ptr).try_get(ptr)
+    }
+
     /// Inserts a new key/value pair.
     ///
     /// # Panics
@@ -342,6 +388,15 @@ impl<K, V> Bucket<K, V> {
         self.next.as_ref().unwrap().get(ptr)
     }
 
+    fn try_get(&self, ptr: *mut K) -> Option<*mut V> {
+        for i in &self.data {
+            if ptr == i.0 {
+                return Some(i.1);
+            }
+        }
+        self.next.as_ref().and_then(|next| next.try_get(ptr))
+    }
+
     fn delete(&mut self, ptr: *mut K) {
         for i in 0..BUCKET_SIZE {
             unsafe {
@@ -438,6 +493,428 @@ impl<K, V> Bucket<K, V> {
     }
 }
 
+/// The number of buckets of the old table migrated by each insertion or deletion during a resize.
+const MIGRATION_STEP: usize = 2;
+
+/// A `PtrHashMap` which supports concurrent lookups.
+///
+/// Unlike `PtrHashMap`, all of the methods of `ConcurrentPtrHashMap` take `&self`. Any number of
+/// threads may perform lookups at once without blocking each other, even while another thread is
+/// inserting or deleting. Insertions and deletions are serialized internally. When the table
+/// grows, existing entries are migrated to the new table a few buckets at a time by subsequent
+/// insertions and deletions rather than all at once.
+///
+/// The same restrictions as for `PtrHashMap` apply: existing keys must not be inserted, and
+/// non-existent keys must not be deleted. A lookup that races with the insertion or deletion of
+/// the same key may or may not find it.
+pub struct ConcurrentPtrHashMap<K, V> {
+    current: AtomicPtr<Table<K, V>>,
+    // the table which is being migrated into current, or null if no resize is in progress
+    old: AtomicPtr<Table<K, V>>,
+    // odd while a write that could cause a concurrent lookup to miss is in progress
+    seq: AtomicUsize,
+    writing: AtomicBool,
+    // only accessed while writing is held
+    state: UnsafeCell<WriterState<K, V>>,
+    align_shift: u32,
+}
+
+unsafe impl<K, V> Send for ConcurrentPtrHashMap<K, V> {}
+unsafe impl<K, V> Sync for ConcurrentPtrHashMap<K, V> {}
+
+struct WriterState<K, V> {
+    size: usize, // number of elements stored in the map
+    // number of buckets of the old table which have been migrated
+    migrated: usize,
+    // tables which have been fully migrated, but which lookups may still be traversing
+    retired: Vec<*mut Table<K, V>>,
+}
+
+impl<K, V> ConcurrentPtrHashMap<K, V> {
+    /// Constructs a new `ConcurrentPtrHashMap`.
+    ///
+    /// The arguments have the same meaning as for `PtrHashMap::new`.
+    pub fn new(size: usize, align: usize) -> Self {
+        use core::mem;
+        assert!(align >= mem::align_of::<K>());
+
+        // size must be power of two
+        let size = (size as u64).next_power_of_two() as usize;
+        ConcurrentPtrHashMap {
+            current: AtomicPtr::new(Table::new(size)),
+            old: AtomicPtr::new(ptr::null_mut()),
+            seq: AtomicUsize::new(0),
+            writing: AtomicBool::new(false),
+            state: UnsafeCell::new(WriterState {
+                                       size: 0,
+                                       migrated: 0,
+                                       retired: Vec::new(),
+                                   }),
+            align_shift: align.trailing_zeros(),
+        }
+    }
+
+    // Get the value associated with the given key. The key must exist, or else get will panic.
+    #[inline]
+    pub fn get(&self, ptr: *mut K) -> *mut V {
+        self.try_get(ptr).unwrap()
+    }
+
+    /// Gets the value associated with the given key, or `None` if the key does not exist.
+    #[inline]
+    pub fn try_get(&self, ptr: *mut K) -> Option<*mut V> {
+        if ptr.is_null() {
+            // null marks an empty slot, so it would spuriously match
+            return None;
+        }
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            // The old table must be checked first - see the design comment at the top of this
+            // file.
+            let old = self.old.load(Ordering::Acquire);
+            if !old.is_null() {
+                if let Some(v) = unsafe { (*old).bucket(ptr, self.align_shift).try_get(ptr) } {
+                    return Some(v);
+                }
+            }
+            let cur = self.current.load(Ordering::Acquire);
+            if let Some(v) = unsafe { (*cur).bucket(ptr, self.align_shift).try_get(ptr) } {
+                return Some(v);
+            }
+            fence(Ordering::Acquire);
+            if seq & 1 == 0 && self.seq.load(Ordering::Relaxed) == seq {
+                return None;
+            }
+        }
+    }
+
+    /// Inserts a new key/value pair.
+    ///
+    /// # Panics
+    /// The key must not already exist in the table, or else `insert` will panic.
+    pub fn insert(&self, ptr: *mut K, v: *mut V) {
+        debug_assert!(!ptr.is_null());
+        self.write(|state| {
+            #[cfg(not(feature = "hashmap-no-resize"))]
+            {
+                let len = unsafe { (*self.current.load(Ordering::Relaxed)).len() };
+                if state.size == len {
+                    self.grow();
+                }
+            }
+
+            let cur = self.current.load(Ordering::Relaxed);
+            unsafe { (*cur).bucket(ptr, self.align_shift).insert(ptr, v) };
+            state.size += 1;
+            self.migrate(state, MIGRATION_STEP);
+        });
+    }
+
+    /// Deletes a key/value pair from the table.
+    ///
+    /// # Panics
+    /// The key must already exist in the table, or else `delete` will panic.
+    pub fn delete(&self, ptr: *mut K) {
+        debug_assert!(!ptr.is_null());
+        self.write(|state| {
+            let old = self.old.load(Ordering::Relaxed);
+            // Keys inserted since the resize began are in the current table even if their bucket
+            // in the old table hasn't been migrated yet.
+            let in_old = !old.is_null() &&
+                         unsafe { (*old).bucket(ptr, self.align_shift).find(ptr).is_some() };
+            let table = if in_old {
+                old
+            } else {
+                self.current.load(Ordering::Relaxed)
+            };
+            unsafe { (*table).bucket(ptr, self.align_shift).delete(ptr, &self.seq) };
+            state.size -= 1;
+            self.migrate(state, MIGRATION_STEP);
+        });
+    }
+
+    /// Dumps all of the buckets in the current table.
+    ///
+    /// Like `PtrHashMap::dump_by_bucket`, but entries which have not yet been migrated from the
+    /// old table during a resize are not included. `dump_by_bucket` must not be called
+    /// concurrently with insertions or deletions.
+    #[cfg_attr(not(test), allow(unused))]
+    pub fn dump_by_bucket(&self) -> Vec<Vec<(*mut K, *mut V)>> {
+        let cur = unsafe { &*self.current.load(Ordering::Acquire) };
+        cur.buckets.iter().map(|b| b.dump()).collect()
+    }
+
+    fn write<F: FnOnce(&mut WriterState<K, V>)>(&self, f: F) {
+        while self.writing.compare_and_swap(false, true, Ordering::Acquire) {}
+        f(unsafe { &mut *self.state.get() });
+        self.writing.store(false, Ordering::Release);
+    }
+
+    /// Installs a new table with twice as many buckets as the current one.
+    fn grow(&self) {
+        // Each insertion migrates MIGRATION_STEP buckets, so the previous resize has always
+        // finished by the time the current table fills up.
+        debug_assert!(self.old.load(Ordering::Relaxed).is_null());
+        let cur = self.current.load(Ordering::Relaxed);
+        let new = Table::new(unsafe { (*cur).len() } * 2);
+        begin_write(&self.seq);
+        self.old.store(cur, Ordering::Release);
+        self.current.store(new, Ordering::Release);
+        end_write(&self.seq);
+    }
+
+    /// Migrates up to `buckets` buckets from the old table to the current one.
+    fn migrate(&self, state: &mut WriterState<K, V>, buckets: usize) {
+        let old = self.old.load(Ordering::Relaxed);
+        if old.is_null() {
+            return;
+        }
+        let (old_len, cur) = unsafe { ((*old).len(), &*self.current.load(Ordering::Relaxed)) };
+        let end = old_len.min(state.migrated.saturating_add(buckets));
+        for i in state.migrated..end {
+            let mut bkt = unsafe {
+                (*old).buckets.get_unchecked(i) as *const ConcurrentBucket<K, V>
+            };
+            while !bkt.is_null() {
+                for slot in unsafe { &(*bkt).data } {
+                    let k = slot.key.load(Ordering::Relaxed);
+                    if !k.is_null() {
+                        // copy the entry before removing it so that lookups always find it in
+                        // one table or the other
+                        cur.bucket(k, self.align_shift)
+                            .insert(k, slot.value.load(Ordering::Relaxed));
+                        slot.key.store(ptr::null_mut(), Ordering::Release);
+                    }
+                }
+                bkt = unsafe { (*bkt).next.load(Ordering::Relaxed) };
+            }
+        }
+        state.migrated = end;
+
+        if end == old_len {
+            self.old.store(ptr::null_mut(), Ordering::Release);
+            state.retired.push(old);
+            state.migrated = 0;
+        }
+    }
+}
+
+impl<K, V> Drop for ConcurrentPtrHashMap<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            Box::from_raw(self.current.load(Ordering::Relaxed));
+            let old = self.old.load(Ordering::Relaxed);
+            if !old.is_null() {
+                Box::from_raw(old);
+            }
+            for &table in &(*self.state.get()).retired {
+                Box::from_raw(table);
+            }
+        }
+    }
+}
+
+fn begin_write(seq: &AtomicUsize) {
+    seq.fetch_add(1, Ordering::Relaxed);
+    fence(Ordering::Release);
+}
+
+fn end_write(seq: &AtomicUsize) {
+    seq.fetch_add(1, Ordering::Release);
+}
+
+struct Table<K, V> {
+    // never resized, so buckets never move while lookups are traversing them
+    buckets: Vec<ConcurrentBucket<K, V>>,
+}
+
+impl<K, V> Table<K, V> {
+    // len must be a power of two
+    fn new(len: usize) -> *mut Table<K, V> {
+        debug_assert!(len.is_power_of_two());
+        let mut buckets = Vec::with_capacity(len);
+        for _ in 0..len {
+            buckets.push(ConcurrentBucket::default());
+        }
+        Box::into_raw(Box::new(Table { buckets: buckets }))
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    #[inline]
+    fn index(&self, ptr: *mut K, align_shift: u32) -> usize {
+        // NOTE: Since len is a power of two, x & (len - 1) is equivalent to x % len
+        ((ptr as usize) >> align_shift) & (self.len() - 1)
+    }
+
+    #[inline]
+    fn bucket(&self, ptr: *mut K, align_shift: u32) -> &ConcurrentBucket<K, V> {
+        unsafe { self.buckets.get_unchecked(self.index(ptr, align_shift)) }
+    }
+}
+
+struct Slot<K, V> {
+    key: AtomicPtr<K>,
+    value: AtomicPtr<V>,
+}
+
+impl<K, V> Default for Slot<K, V> {
+    fn default() -> Slot<K, V> {
+        Slot {
+            key: AtomicPtr::new(ptr::null_mut()),
+            value: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<K, V> Slot<K, V> {
+    // Only called by writers, and only on slots whose key is null.
+    fn set(&self, ptr: *mut K, v: *mut V) {
+        self.value.store(v, Ordering::Release);
+        self.key.store(ptr, Ordering::Release);
+    }
+}
+
+struct ConcurrentBucket<K, V> {
+    data: [Slot<K, V>; BUCKET_SIZE],
+    next: AtomicPtr<ConcurrentBucket<K, V>>,
+}
+
+impl<K, V> Default for ConcurrentBucket<K, V> {
+    fn default() -> ConcurrentBucket<K, V> {
+        ConcurrentBucket {
+            data: Default::default(),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<K, V> Drop for ConcurrentBucket<K, V> {
+    fn drop(&mut self) {
+        let next = self.next.load(Ordering::Relaxed);
+        if !next.is_null() {
+            unsafe { Box::from_raw(next) };
+        }
+    }
+}
+
+impl<K, V> ConcurrentBucket<K, V> {
+    fn try_get(&self, ptr: *mut K) -> Option<*mut V> {
+        let mut bkt = self as *const ConcurrentBucket<K, V>;
+        while !bkt.is_null() {
+            for slot in unsafe { &(*bkt).data } {
+                if slot.key.load(Ordering::Acquire) == ptr {
+                    let v = slot.value.load(Ordering::Acquire);
+                    // if the key changed, v might belong to a different key
+                    if slot.key.load(Ordering::Acquire) == ptr {
+                        return Some(v);
+                    }
+                }
+            }
+            bkt = unsafe { (*bkt).next.load(Ordering::Acquire) };
+        }
+        None
+    }
+
+    #[cfg_attr(not(test), allow(unused))]
+    fn dump(&self) -> Vec<(*mut K, *mut V)> {
+        let mut res = Vec::new();
+        let mut bkt = self as *const ConcurrentBucket<K, V>;
+        while !bkt.is_null() {
+            for slot in unsafe { &(*bkt).data } {
+                let k = slot.key.load(Ordering::Acquire);
+                if !k.is_null() {
+                    res.push((k, slot.value.load(Ordering::Acquire)));
+                }
+            }
+            bkt = unsafe { (*bkt).next.load(Ordering::Acquire) };
+        }
+        res
+    }
+
+    // The methods below are only called by writers.
+
+    fn insert(&self, ptr: *mut K, v: *mut V) {
+        let mut bkt = self as *const ConcurrentBucket<K, V>;
+        loop {
+            for slot in unsafe { &(*bkt).data } {
+                let cur = slot.key.load(Ordering::Relaxed);
+                debug_assert_ne!(ptr, cur);
+                if cur.is_null() {
+                    slot.set(ptr, v);
+                    return;
+                }
+            }
+            let next = unsafe { (*bkt).next.load(Ordering::Relaxed) };
+            if next.is_null() {
+                break;
+            }
+            bkt = next;
+        }
+
+        let new = ConcurrentBucket::default();
+        new.data[0].set(ptr, v);
+        unsafe {
+            (*bkt)
+                .next
+                .store(Box::into_raw(Box::new(new)), Ordering::Release)
+        };
+    }
+
+    fn delete(&self, ptr: *mut K, seq: &AtomicUsize) {
+        let slot = self.find(ptr).unwrap();
+        #[cfg(not(feature = "hashmap-no-coalesce"))]
+        {
+            // Keep the chain coalesced by moving its last entry into the deleted slot. See the
+            // description of the coalesce algorithm in Bucket.
+            let last = self.last();
+            if last as *const Slot<K, V> != slot as *const Slot<K, V> {
+                begin_write(seq);
+                slot.key.store(ptr::null_mut(), Ordering::Release);
+                slot.set(last.key.load(Ordering::Relaxed),
+                         last.value.load(Ordering::Relaxed));
+                last.key.store(ptr::null_mut(), Ordering::Release);
+                end_write(seq);
+                return;
+            }
+        }
+        let _ = seq;
+        slot.key.store(ptr::null_mut(), Ordering::Release);
+    }
+
+    fn find(&self, ptr: *mut K) -> Option<&Slot<K, V>> {
+        let mut bkt = self as *const ConcurrentBucket<K, V>;
+        while !bkt.is_null() {
+            for slot in unsafe { &(*bkt).data } {
+                if slot.key.load(Ordering::Relaxed) == ptr {
+                    return Some(slot);
+                }
+            }
+            bkt = unsafe { (*bkt).next.load(Ordering::Relaxed) };
+        }
+        None
+    }
+
+    // Returns the last full slot in the chain. The chain must not be empty.
+    #[cfg_attr(feature = "hashmap-no-coalesce", allow(unused))]
+    fn last(&self) -> &Slot<K, V> {
+        let mut last = None;
+        let mut bkt = self as *const ConcurrentBucket<K, V>;
+        while !bkt.is_null() {
+            for slot in unsafe { &(*bkt).data } {
+                if !slot.key.load(Ordering::Relaxed).is_null() {
+                    last = Some(slot);
+                }
+            }
+            bkt = unsafe { (*bkt).next.load(Ordering::Relaxed) };
+        }
+        last.unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -468,4 +945,249 @@ mod tests {
             }
         }
     }
+
+    // Keys are never dereferenced, so there's no need for them to point to anything.
+    fn key(i: usize) -> *mut usize {
+        ((i + 1) * 8) as *mut usize
+    }
+
+    #[test]
+    fn concurrent_map_functionality() {
+        const SIZE: usize = 1 << 16;
+        let hs = ConcurrentPtrHashMap::new(16, 8);
+        for i in 0..SIZE {
+            hs.insert(key(i), key(i + 1));
+            // check some keys that might be in the middle of being migrated
+            for j in 0..(i.min(8)) {
+                assert_eq!(hs.get(key(i - j)), key(i - j + 1));
+            }
+        }
+        for i in 0..SIZE {
+            assert_eq!(hs.get(key(i)), key(i + 1));
+        }
+        for i in (0..SIZE).filter(|i| i % 2 == 0) {
+            hs.delete(key(i));
+        }
+        for i in 0..SIZE {
+            if i % 2 == 0 {
+                assert_eq!(hs.try_get(key(i)), None);
+            } else {
+                assert_eq!(hs.get(key(i)), key(i + 1));
+            }
+        }
+        assert_eq!(hs.try_get(ptr::null_mut()), None);
+    }
+
+    #[test]
+    fn concurrent_map_lookups_during_inserts() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+
+        // keys 0..BASE stay in the map the whole time while the other keys are inserted and
+        // deleted, forcing the table to be resized repeatedly
+        const BASE: usize = 1024;
+        const SIZE: usize = 1 << 16;
+        let hs = Arc::new(ConcurrentPtrHashMap::new(16, 8));
+        for i in 0..BASE {
+            hs.insert(key(i), key(i + 1));
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (hs, done) = (hs.clone(), done.clone());
+                thread::spawn(move || while !done.load(Ordering::SeqCst) {
+                                  for i in 0..BASE {
+                                      assert_eq!(hs.try_get(key(i)), Some(key(i + 1)));
+                                  }
+                              })
+            })
+            .collect();
+
+        for _ in 0..4 {
+            for i in BASE..SIZE {
+                hs.insert(key(i), key(i + 1));
+            }
+            for i in BASE..SIZE {
+                hs.delete(key(i));
+            }
+        }
+        done.store(true, Ordering::SeqCst);
+        for r in readers {
+            r.join().unwrap();
+        }
+    }
+
+    // Benchmarks comparing PtrHashMap and ConcurrentPtrHashMap to the maps in util::ptrmap, which
+    // are what the allocator actually uses. Run them with the use-stdlib-hashmap feature to compare
+    // against the standard library's HashMap.
+
+    #[cfg(feature = "build-ignored-tests")]
+    extern crate test;
+    #[cfg(feature = "build-ignored-tests")]
+    use self::test::{Bencher, black_box};
+    #[cfg(feature = "build-ignored-tests")]
+    use util::ptrmap::{ConcurrentMap, Map};
+
+    #[cfg(feature = "build-ignored-tests")]
+    const BENCH_SIZE: usize = 1024;
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_lookup_ptr_hash_map(b: &mut Bencher) {
+        let mut hs = PtrHashMap::new(BENCH_SIZE, 8);
+        for i in 0..BENCH_SIZE {
+            hs.insert(key(i), key(i));
+        }
+        b.iter(|| for i in 0..BENCH_SIZE {
+                   black_box(hs.get(key(i)));
+               });
+    }
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_lookup_concurrent_ptr_hash_map(b: &mut Bencher) {
+        let hs = ConcurrentPtrHashMap::new(BENCH_SIZE, 8);
+        for i in 0..BENCH_SIZE {
+            hs.insert(key(i), key(i));
+        }
+        b.iter(|| for i in 0..BENCH_SIZE {
+                   black_box(hs.get(key(i)));
+               });
+    }
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_lookup_ptrmap_map(b: &mut Bencher) {
+        let mut hs = Map::new(BENCH_SIZE, 8);
+        for i in 0..BENCH_SIZE {
+            hs.insert(key(i), key(i));
+        }
+        b.iter(|| for i in 0..BENCH_SIZE {
+                   black_box(hs.get(key(i)));
+               });
+    }
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_insert_delete_ptr_hash_map(b: &mut Bencher) {
+        let mut hs = PtrHashMap::new(16, 8);
+        b.iter(|| {
+                   for i in 0..BENCH_SIZE {
+                       hs.insert(key(i), key(i));
+                   }
+                   for i in 0..BENCH_SIZE {
+                       hs.delete(key(i));
+                   }
+               });
+    }
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_insert_delete_concurrent_ptr_hash_map(b: &mut Bencher) {
+        // the concurrent map never shrinks, so only the first iteration resizes
+        let hs = ConcurrentPtrHashMap::new(16, 8);
+        b.iter(|| {
+                   for i in 0..BENCH_SIZE {
+                       hs.insert(key(i), key(i));
+                   }
+                   for i in 0..BENCH_SIZE {
+                       hs.delete(key(i));
+                   }
+               });
+    }
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_insert_delete_ptrmap_map(b: &mut Bencher) {
+        let mut hs = Map::new(16, 8);
+        b.iter(|| {
+                   for i in 0..BENCH_SIZE {
+                       hs.insert(key(i), key(i));
+                   }
+                   for i in 0..BENCH_SIZE {
+                       hs.delete(key(i));
+                   }
+               });
+    }
+
+    // Measure lookups while another thread continuously inserts and deletes keys. With the
+    // use-stdlib-hashmap feature, ConcurrentMap is a HashMap protected by a Mutex.
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_lookup_during_inserts_concurrent_ptr_hash_map(b: &mut Bencher) {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+
+        let hs = Arc::new(ConcurrentPtrHashMap::new(BENCH_SIZE, 8));
+        for i in 0..BENCH_SIZE {
+            hs.insert(key(i), key(i));
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (hs, done) = (hs.clone(), done.clone());
+            thread::spawn(move || while !done.load(Ordering::SeqCst) {
+                              for i in BENCH_SIZE..(2 * BENCH_SIZE) {
+                                  hs.insert(key(i), key(i));
+                              }
+                              for i in BENCH_SIZE..(2 * BENCH_SIZE) {
+                                  hs.delete(key(i));
+                              }
+                          })
+        };
+        b.iter(|| for i in 0..BENCH_SIZE {
+                   black_box(hs.get(key(i)));
+               });
+        done.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+    }
+
+    #[bench]
+    #[cfg(feature = "build-ignored-tests")]
+    #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+    #[ignore]
+    fn bench_lookup_during_inserts_ptrmap_concurrent_map(b: &mut Bencher) {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+
+        let hs = Arc::new(ConcurrentMap::new(BENCH_SIZE, 8));
+        for i in 0..BENCH_SIZE {
+            hs.insert(key(i), key(i));
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (hs, done) = (hs.clone(), done.clone());
+            thread::spawn(move || while !done.load(Ordering::SeqCst) {
+                              for i in BENCH_SIZE..(2 * BENCH_SIZE) {
+                                  hs.insert(key(i), key(i));
+                              }
+                              for i in BENCH_SIZE..(2 * BENCH_SIZE) {
+                                  hs.delete(key(i));
+                              }
+                          })
+        };
+        b.iter(|| for i in 0..BENCH_SIZE {
+                   black_box(hs.get(key(i)));
+               });
+        done.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+    }
 }
@@ -0,0 +1,193 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! Policies governing when a slab allocator returns memory to its backing allocator.
+//!
+//! A slab allocator caches slabs all of whose objects are available for allocation ("full" slabs)
+//! rather than freeing them right away, since they are likely to be needed again soon. A
+//! `ReclaimPolicy` decides how many of these full slabs are kept around. The policy is consulted
+//! every time a slab becomes full, and is informed every time a full slab is put back into use.
+//!
+//! Regardless of policy, cached slabs can always be freed explicitly by calling `reclaim` on the
+//! allocator.
+
+use util::workingset::WorkingSet;
+
+/// A policy deciding when full slabs are freed.
+///
+/// In order to support policies that track how the number of full slabs varies over time, the
+/// allocator notifies the policy whenever the number of full slabs decreases (`update`), and asks
+/// the policy how many slabs to free whenever a slab becomes full (`slabs_to_free`).
+pub trait ReclaimPolicy {
+    /// Records a decrease in the number of full slabs.
+    ///
+    /// `update` is called with the new number of full slabs whenever a full slab is allocated
+    /// from or is freed by a call to `reclaim`. It is not called when slabs are freed as a result
+    /// of `slabs_to_free`. The default implementation does nothing.
+    fn update(&mut self, num_full: usize) {
+        let _ = num_full;
+    }
+
+    /// Decides how many full slabs to free.
+    ///
+    /// `slabs_to_free` is called whenever a slab becomes full. `num_full` is the number of full
+    /// slabs (including the one which just became full), and `slab_size` is the number of bytes
+    /// of memory used by each slab. It returns the number of full slabs to free, which must not be
+    /// greater than `num_full`.
+    fn slabs_to_free(&mut self, num_full: usize, slab_size: usize) -> usize;
+}
+
+/// A source of time for reclamation policies.
+pub trait Clock {
+    /// Returns the current time in milliseconds.
+    ///
+    /// Times are measured relative to an arbitrary fixed point, and must never decrease.
+    fn now(&self) -> u64;
+}
+
+/// A `Clock` which reads the system's monotonic clock.
+#[cfg(feature = "std")]
+pub struct SystemClock(::std::time::Instant);
+
+#[cfg(feature = "std")]
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock(::std::time::Instant::now())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        let elapsed = self.0.elapsed();
+        elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64
+    }
+}
+
+/// The `ReclaimPolicy` used by slab allocators unless another is configured.
+#[cfg(feature = "std")]
+pub type DefaultReclaimPolicy = WorkingSetPolicy<SystemClock>;
+
+/// The `ReclaimPolicy` used by slab allocators unless another is configured.
+///
+/// Without the `std` feature, there is no clock on which to base a `WorkingSetPolicy`, so slabs
+/// are never freed by default. A `WorkingSetPolicy` can still be used by providing a `Clock` to
+/// `WorkingSetPolicy::with_clock`.
+#[cfg(not(feature = "std"))]
+pub type DefaultReclaimPolicy = NeverFree;
+
+/// The default working period of a `WorkingSetPolicy`, in milliseconds.
+pub const DEFAULT_WORKING_PERIOD: u64 = 15_000;
+
+/// A policy which frees slabs that have gone unused for an entire working period.
+///
+/// `WorkingSetPolicy` tracks the minimum number of full slabs at every moment during a working
+/// period. At the end of the period, that many slabs were never needed during the period, so it
+/// frees them. Periods end the first time a slab becomes full after the period's length has
+/// elapsed. This is the default policy.
+pub struct WorkingSetPolicy<C: Clock> {
+    period: u64,
+    clock: C,
+    // minimum number of slabs full at every moment during this working period
+    working_set: WorkingSet<usize>,
+}
+
+impl<C: Clock> WorkingSetPolicy<C> {
+    /// Constructs a new `WorkingSetPolicy` whose working period is `period` milliseconds
+    /// according to `clock`.
+    pub fn with_clock(period: u64, clock: C) -> WorkingSetPolicy<C> {
+        let now = clock.now();
+        WorkingSetPolicy {
+            period: period,
+            clock: clock,
+            working_set: WorkingSet::new(0, now),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl WorkingSetPolicy<SystemClock> {
+    /// Constructs a new `WorkingSetPolicy` whose working period is `period` milliseconds.
+    pub fn new(period: u64) -> WorkingSetPolicy<SystemClock> {
+        WorkingSetPolicy::with_clock(period, SystemClock::new())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for WorkingSetPolicy<SystemClock> {
+    fn default() -> WorkingSetPolicy<SystemClock> {
+        WorkingSetPolicy::new(DEFAULT_WORKING_PERIOD)
+    }
+}
+
+impl<C: Clock> ReclaimPolicy for WorkingSetPolicy<C> {
+    fn update(&mut self, num_full: usize) {
+        self.working_set.update_min(num_full);
+    }
+
+    fn slabs_to_free(&mut self, num_full: usize, _slab_size: usize) -> usize {
+        match self.working_set.refresh_now(self.period, self.clock.now()) {
+            Some(min_full) => {
+                self.working_set.set(num_full - min_full);
+                min_full
+            }
+            None => 0,
+        }
+    }
+}
+
+/// A policy which never frees slabs.
+///
+/// Memory is only returned to the backing allocator by an explicit call to `reclaim` or when
+/// the allocator is dropped.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct NeverFree;
+
+impl ReclaimPolicy for NeverFree {
+    fn slabs_to_free(&mut self, _num_full: usize, _slab_size: usize) -> usize {
+        0
+    }
+}
+
+/// A policy which frees slabs as soon as they become full.
+///
+/// `FreeImmediately` minimizes memory usage, but a workload which repeatedly allocates and frees
+/// a single object will allocate and free a slab each time.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FreeImmediately;
+
+impl ReclaimPolicy for FreeImmediately {
+    fn slabs_to_free(&mut self, num_full: usize, _slab_size: usize) -> usize {
+        num_full
+    }
+}
+
+/// A policy which caches at most a fixed number of full slabs.
+#[derive(Copy, Clone, Debug)]
+pub struct KeepAtMost(pub usize);
+
+impl ReclaimPolicy for KeepAtMost {
+    fn slabs_to_free(&mut self, num_full: usize, _slab_size: usize) -> usize {
+        num_full.saturating_sub(self.0)
+    }
+}
+
+/// A policy which caches at most a fixed number of bytes worth of full slabs.
+#[derive(Copy, Clone, Debug)]
+pub struct ByteBudget(pub usize);
+
+impl ReclaimPolicy for ByteBudget {
+    fn slabs_to_free(&mut self, num_full: usize, slab_size: usize) -> usize {
+        num_full.saturating_sub(self.0 / slab_size)
+    }
+}
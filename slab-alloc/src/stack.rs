@@ -58,12 +58,14 @@ extern crate object_alloc;
 
 use SlabSystem;
 use init::InitSystem;
-use core::{mem, ptr};
+use core::{cmp, mem, ptr, slice};
 use util::stack::Stack;
 use util::color::{ColorSettings, Color};
 use util::list::*;
 use self::alloc::allocator;
-use self::object_alloc::UntypedObjectAlloc;
+#[cfg(not(feature = "std"))]
+use self::alloc::vec::Vec;
+use self::object_alloc::{Error, UntypedObjectAlloc};
 
 /// Configuration to customize a stack-based slab implementation.
 ///
@@ -90,8 +92,24 @@ pub trait ConfigData
     ///
     /// Given an object, `ptr_to_slab` locates the slab containing that object.
     fn ptr_to_slab(&self, slab_size: usize, ptr: *mut u8) -> *mut SlabHeader;
+
+    /// Look up the slab containing an arbitrary pointer.
+    ///
+    /// Unlike `ptr_to_slab`, `ptr` may be any pointer, including one which was not allocated from
+    /// this slab system. If `ptr` points to an object in one of this system's slabs, the slab is
+    /// returned. Otherwise, `None` is returned. `try_ptr_to_slab` must not dereference `ptr` or
+    /// any slab that is not currently allocated.
+    fn try_ptr_to_slab(&self, slab_size: usize, ptr: *mut u8) -> Option<*mut SlabHeader>;
 }
 
+/// The minimum size of the redzones placed on either side of each object when debug checks are
+/// enabled.
+pub const REDZONE_SIZE: usize = 16;
+/// The byte pattern with which redzones are filled.
+pub const REDZONE_BYTE: u8 = 0xbb;
+/// The byte pattern with which free, uninitialized objects are filled.
+pub const POISON_BYTE: u8 = 0x6b;
+
 pub struct System<A: UntypedObjectAlloc, C: ConfigData> {
     pub data: C,
     layout: Layout,
@@ -106,20 +124,55 @@ impl<A: UntypedObjectAlloc, C: ConfigData> System<A, C> {
             alloc: alloc,
         }
     }
+
+    /// Fill the redzones around `obj` and, if `poison` is true, the object itself.
+    unsafe fn debug_fill(&self, obj: *mut u8, poison: bool) {
+        let (size, redzone) = (self.layout.layout.size(), self.layout.redzone);
+        ptr::write_bytes(obj.offset(-(redzone as isize)), REDZONE_BYTE, redzone);
+        ptr::write_bytes(obj.offset(size as isize), REDZONE_BYTE, redzone);
+        if poison {
+            ptr::write_bytes(obj, POISON_BYTE, size);
+        }
+    }
+
+    /// Verify that the redzones around `obj` are intact and, if `poisoned` is true, that `obj`
+    /// still contains the poison pattern.
+    unsafe fn debug_check(&self, obj: *mut u8, poisoned: bool) {
+        let (size, redzone) = (self.layout.layout.size(), self.layout.redzone);
+        if !all_bytes(obj.offset(-(redzone as isize)), redzone, REDZONE_BYTE) {
+            panic!("slab-alloc: redzone before object {:?} overwritten", obj);
+        }
+        if !all_bytes(obj.offset(size as isize), redzone, REDZONE_BYTE) {
+            panic!("slab-alloc: redzone after object {:?} overwritten", obj);
+        }
+        if poisoned && !all_bytes(obj, size, POISON_BYTE) {
+            panic!("slab-alloc: object {:?} modified while free", obj);
+        }
+    }
+}
+
+unsafe fn all_bytes(ptr: *const u8, len: usize, byte: u8) -> bool {
+    slice::from_raw_parts(ptr, len).iter().all(|&b| b == byte)
+}
+
+/// Whether `status` is the uninitialized status.
+///
+/// Objects with this status have no state which needs to be preserved while they are free, so
+/// they can be poisoned. For `InitSystem`s that do not perform initialization, every status is the
+/// uninitialized status.
+fn is_uninitialized<I: InitSystem>(status: I::Status) -> bool {
+    I::pack(ptr::null_mut(), status) == I::pack(ptr::null_mut(), I::status_uninitialized())
 }
 
 impl<I: InitSystem, A: UntypedObjectAlloc, C: ConfigData> SlabSystem<I> for System<A, C> {
     type Slab = SlabHeader;
 
-    fn alloc_slab(&mut self) -> *mut SlabHeader {
+    fn alloc_slab(&mut self) -> Result<*mut SlabHeader, Error> {
         unsafe {
             let color = self.layout
                 .color_settings
                 .next_color(self.layout.layout.align());
-            let slab = match self.alloc.alloc() {
-                Ok(slab) => slab as *mut SlabHeader,
-                Err(..) => return ptr::null_mut(),
-            };
+            let slab = self.alloc.alloc()? as *mut SlabHeader;
 
             ptr::write(slab,
                        SlabHeader {
@@ -131,6 +184,9 @@ impl<I: InitSystem, A: UntypedObjectAlloc, C: ConfigData> SlabSystem<I> for Syst
             let stack_data_ptr = self.layout.stack_begin(slab);
             for i in 0..self.layout.num_obj {
                 let ptr = self.layout.nth_obj(slab, color, i);
+                if self.layout.redzone > 0 {
+                    self.debug_fill(ptr, true);
+                }
                 (*slab)
                     .stack
                     .push(stack_data_ptr, I::pack(ptr, I::status_uninitialized()));
@@ -138,11 +194,11 @@ impl<I: InitSystem, A: UntypedObjectAlloc, C: ConfigData> SlabSystem<I> for Syst
 
             self.data
                 .post_alloc(&self.layout, self.alloc.layout().size(), slab);
-            slab
+            Ok(slab)
         }
     }
 
-    fn dealloc_slab(&mut self, slab: *mut SlabHeader) {
+    fn dealloc_slab(&mut self, slab: *mut SlabHeader, init_system: &I) {
         unsafe {
             debug_assert_eq!((*slab).stack.size(), self.layout.num_obj);
             self.data
@@ -150,7 +206,11 @@ impl<I: InitSystem, A: UntypedObjectAlloc, C: ConfigData> SlabSystem<I> for Syst
             let stack_data_ptr = self.layout.stack_begin(slab);
             for _ in 0..self.layout.num_obj {
                 let packed = (*slab).stack.pop(stack_data_ptr);
-                I::drop(I::unpack_ptr(packed), I::unpack_status(packed));
+                let (ptr, status) = (I::unpack_ptr(packed), I::unpack_status(packed));
+                if self.layout.redzone > 0 {
+                    self.debug_check(ptr, is_uninitialized::<I>(status));
+                }
+                init_system.drop(ptr, status);
             }
 
             self.alloc.dealloc(slab as *mut u8);
@@ -161,24 +221,95 @@ impl<I: InitSystem, A: UntypedObjectAlloc, C: ConfigData> SlabSystem<I> for Syst
         unsafe { (*slab).stack.size() == self.layout.num_obj }
     }
 
+    fn objects_per_slab(&self) -> usize {
+        self.layout.num_obj
+    }
+
+    fn slab_size(&self) -> usize {
+        self.alloc.layout().size()
+    }
+
     fn is_empty(&self, slab: *mut SlabHeader) -> bool {
         unsafe { (*slab).stack.size() == 0 }
     }
 
+    fn num_available(&self, slab: *mut SlabHeader) -> usize {
+        unsafe { (*slab).stack.size() }
+    }
+
     fn alloc(&self, slab: *mut SlabHeader) -> (*mut u8, I::Status) {
         unsafe {
             let stack_data_ptr = self.layout.stack_begin(slab);
             let packed = (*slab).stack.pop(stack_data_ptr);
-            (I::unpack_ptr(packed), I::unpack_status(packed))
+            let (ptr, status) = (I::unpack_ptr(packed), I::unpack_status(packed));
+            if self.layout.redzone > 0 {
+                self.debug_check(ptr, is_uninitialized::<I>(status));
+            }
+            (ptr, status)
+        }
+    }
+
+    fn object_index(&self, ptr: *mut u8) -> Option<usize> {
+        let slab = match self.data.try_ptr_to_slab(self.alloc.layout().size(), ptr) {
+            Some(slab) => slab,
+            None => return None,
+        };
+        let first_obj = self.layout.nth_obj(slab, unsafe { (*slab).get_color() }, 0) as usize;
+        let obj_stride = self.layout.obj_stride();
+        let ptr = ptr as usize;
+        if ptr < first_obj || (ptr - first_obj) % obj_stride != 0 {
+            return None;
+        }
+        let idx = (ptr - first_obj) / obj_stride;
+        if idx < self.layout.num_obj {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    fn for_each_allocated(&self, slab: *mut SlabHeader, f: &mut FnMut(*mut u8)) {
+        unsafe {
+            let color = (*slab).get_color();
+            let first_obj = self.layout.nth_obj(slab, color, 0) as usize;
+            let obj_stride = self.layout.obj_stride();
+
+            // every object which isn't on the stack is allocated
+            let mut available = Vec::with_capacity(self.layout.num_obj);
+            available.resize(self.layout.num_obj, false);
+            let stack_data_ptr = self.layout.stack_begin(slab);
+            for i in 0..(*slab).stack.size() {
+                let ptr = I::unpack_ptr((*slab).stack.get(stack_data_ptr, i)) as usize;
+                available[(ptr - first_obj) / obj_stride] = true;
+            }
+
+            for (i, &available) in available.iter().enumerate() {
+                if !available {
+                    f(self.layout.nth_obj(slab, color, i));
+                }
+            }
         }
     }
 
     fn dealloc(&self, obj: *mut u8, init_status: I::Status) -> (*mut SlabHeader, bool) {
         unsafe {
+            if self.layout.redzone > 0 &&
+               <Self as SlabSystem<I>>::object_index(self, obj).is_none() {
+                panic!("slab-alloc: freed pointer {:?} is not an object", obj);
+            }
             let slab = self.data.ptr_to_slab(self.alloc.layout().size(), obj);
             let was_empty = (*slab).stack.size() == 0;
 
             let stack_data_ptr = self.layout.stack_begin(slab);
+            if self.layout.redzone > 0 {
+                for i in 0..(*slab).stack.size() {
+                    if I::unpack_ptr((*slab).stack.get(stack_data_ptr, i)) == obj {
+                        panic!("slab-alloc: double free of object {:?}", obj);
+                    }
+                }
+                self.debug_check(obj, false);
+                self.debug_fill(obj, is_uninitialized::<I>(init_status));
+            }
             (*slab)
                 .stack
                 .push(stack_data_ptr, I::pack(obj, init_status));
@@ -222,15 +353,28 @@ pub struct Layout {
     pub stack_begin_offset: usize,
     pub array_begin_offset: usize,
     pub color_settings: ColorSettings,
+    /// The size of the redzones on either side of each object, or 0 if debug checks are disabled.
+    pub redzone: usize,
 }
 
 impl Layout {
     /// Determines whether an allocator can be constructed for T using the given slab size. If so,
     /// it returns a constructed Layout for T using that slab size and the amount of unused space
-    /// left at the end of the slab (when no coloring is used).
-    pub fn for_slab_size(layout: allocator::Layout, slab_size: usize) -> Option<(Layout, usize)> {
-        let obj_size = layout.size();
+    /// left at the end of the slab (when no coloring is used). If `debug` is true, space is
+    /// reserved for redzones around each object.
+    pub fn for_slab_size(layout: allocator::Layout,
+                         slab_size: usize,
+                         debug: bool)
+                         -> Option<(Layout, usize)> {
         let obj_align = layout.align();
+        // Redzones must be a multiple of the alignment so that objects remain aligned. Since both
+        // are powers of two, the larger of the two is a multiple of the smaller.
+        let redzone = if debug {
+            cmp::max(REDZONE_SIZE, obj_align)
+        } else {
+            0
+        };
+        let obj_stride = layout.size() + 2 * redzone;
         let hdr_size = mem::size_of::<SlabHeader>();
 
         // padding between the SlabHeader and the base of the pointer stack
@@ -253,7 +397,7 @@ impl Layout {
                 .unwrap()
                 .padding_needed_for(obj_align);
 
-            if total_hdr_size + post_stack_padding + (candidate * obj_size) <= slab_size {
+            if total_hdr_size + post_stack_padding + (candidate * obj_stride) <= slab_size {
                 num_obj = candidate;
                 array_begin_offset = total_hdr_size + post_stack_padding;
             } else {
@@ -265,22 +409,33 @@ impl Layout {
         }
         assert!(array_begin_offset > 0);
 
-        let unused_space = slab_size - array_begin_offset - (num_obj * obj_size);
+        let unused_space = slab_size - array_begin_offset - (num_obj * obj_stride);
         let l = Layout {
             num_obj: num_obj,
             layout: layout,
             stack_begin_offset: stack_begin_offset,
             array_begin_offset: array_begin_offset,
             color_settings: ColorSettings::new(obj_align, unused_space),
+            redzone: redzone,
         };
 
         // assert that the objects fit within the slab
         assert!(slab_size >=
                 l.array_begin_offset + l.color_settings.max_color().as_usize() +
-                (l.num_obj * obj_size));
+                (l.num_obj * obj_stride));
         Some((l, unused_space))
     }
 
+    /// Disables coloring, so that every slab's array of objects begins at the same offset.
+    pub fn disable_coloring(&mut self) {
+        self.color_settings = ColorSettings::new(self.layout.align(), 0);
+    }
+
+    /// The distance between the beginnings of adjacent objects, including their redzones.
+    fn obj_stride(&self) -> usize {
+        self.layout.size() + 2 * self.redzone
+    }
+
     fn array_begin(&self, slab: *mut SlabHeader, color: Color) -> *mut u8 {
         debug_assert!(color.as_usize() <= self.color_settings.max_color().as_usize());
         ((slab as usize) + self.array_begin_offset + color.as_usize()) as *mut u8
@@ -292,6 +447,6 @@ impl Layout {
 
     pub fn nth_obj(&self, slab: *mut SlabHeader, color: Color, n: usize) -> *mut u8 {
         debug_assert!((n as usize) < self.num_obj);
-        (self.array_begin(slab, color) as usize + n * self.layout.size()) as *mut u8
+        (self.array_begin(slab, color) as usize + self.redzone + n * self.obj_stride()) as *mut u8
     }
 }
@@ -11,18 +11,20 @@ extern crate rand;
 extern crate sysconf;
 extern crate test;
 
-use SlabAllocBuilder;
+use {DefaultInitSystem, PrivateSlabAlloc, SlabAllocBuilder};
 use self::alloc::heap::{Alloc, Heap, Layout};
-use self::object_alloc::{Exhausted, ObjectAlloc};
+use self::object_alloc::{Error, ObjectAlloc};
+use self::object_alloc::adapter::AllocObjectAlloc;
 use self::test::{Bencher, black_box};
 use self::object_alloc_test::leaky_alloc::LeakyAlloc;
-use backing::alloc::AllocObjectAlloc;
 use backing::BackingAlloc;
+use init::NopInitSystem;
+use reclaim::ReclaimPolicy;
 use SlabAlloc;
 
 fn infer_allocator_type<T>(alloc: &mut ObjectAlloc<T>) {
     if false {
-        let _: Result<*mut T, Exhausted> = unsafe { alloc.alloc() };
+        let _: Result<*mut T, Error> = unsafe { alloc.alloc() };
     }
 }
 
@@ -136,6 +138,965 @@ call_for_all_types_prefix!(make_test_memory_corruption, test_memory_corruption);
 call_for_all_types_prefix!(make_test_quickcheck_memory_corruption,
                            quickcheck_memory_corruption);
 
+#[test]
+fn test_alloc_batch() {
+    use std::collections::HashSet;
+
+    let mut alloc: SlabAlloc<[u64; 16], _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    // large enough to span multiple slabs
+    let mut ptrs = vec![::std::ptr::null_mut::<[u64; 16]>(); 1024];
+    assert_eq!(unsafe { alloc.alloc_batch(&mut ptrs[..]) }, ptrs.len());
+
+    let mut set = HashSet::new();
+    for &ptr in &ptrs {
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % ::std::mem::align_of::<[u64; 16]>(), 0);
+        assert_eq!(unsafe { *ptr }, [0; 16]);
+        assert!(set.insert(ptr as usize), "duplicate pointer: {:?}", ptr);
+    }
+
+    unsafe {
+        alloc.dealloc_batch(&ptrs[..512]);
+        assert_eq!(alloc.alloc_batch(&mut ptrs[..512]), 512);
+        alloc.dealloc_batch(&ptrs[..]);
+    }
+}
+
+#[test]
+fn test_alloc_error() {
+    use self::alloc::allocator::AllocErr;
+    use self::object_alloc::ErrorKind;
+
+    struct FailingAlloc;
+
+    unsafe impl Alloc for FailingAlloc {
+        unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+            Err(AllocErr::Exhausted { request: layout })
+        }
+
+        unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+            unreachable!()
+        }
+    }
+
+    struct FailingBackingAlloc;
+
+    impl BackingAlloc for FailingBackingAlloc {
+        type Aligned = AllocObjectAlloc<FailingAlloc>;
+        type Large = AllocObjectAlloc<FailingAlloc>;
+    }
+
+    fn failing_get_aligned(layout: Layout) -> Option<AllocObjectAlloc<FailingAlloc>> {
+        Some(AllocObjectAlloc::new(FailingAlloc, layout))
+    }
+
+    fn failing_get_large(layout: Layout) -> AllocObjectAlloc<FailingAlloc> {
+        AllocObjectAlloc::new(FailingAlloc, layout)
+    }
+
+    let mut alloc: SlabAlloc<[u64; 16], _, FailingBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(failing_get_aligned, failing_get_large);
+    let err = unsafe { alloc.alloc().unwrap_err() };
+    assert_eq!(err.kind(), ErrorKind::Exhausted);
+    assert_eq!(err.layout(), &Layout::new::<[u64; 16]>());
+    match AllocErr::from(err) {
+        AllocErr::Exhausted { request } => assert_eq!(request, Layout::new::<[u64; 16]>()),
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_build_static() {
+    use self::object_alloc::{ErrorKind, Reclaim};
+
+    const REGION_SIZE: usize = 1 << 20;
+    let region = unsafe { &mut *Box::into_raw(vec![0u8; REGION_SIZE].into_boxed_slice()) };
+    let begin = region.as_ptr() as usize;
+    let mut alloc: SlabAlloc<[u64; 16], _, _> = SlabAllocBuilder::default()
+        .page_size(4096)
+        .build_static(region);
+
+    let mut ptrs = Vec::new();
+    loop {
+        match unsafe { alloc.alloc() } {
+            Ok(ptr) => ptrs.push(ptr),
+            Err(err) => {
+                assert_eq!(err.kind(), ErrorKind::Exhausted);
+                break;
+            }
+        }
+    }
+    assert!(ptrs.len() * ::std::mem::size_of::<[u64; 16]>() > REGION_SIZE / 2);
+    for &ptr in &ptrs {
+        assert!(ptr as usize >= begin && (ptr as usize) < begin + REGION_SIZE);
+    }
+
+    // slabs returned to the region are reused
+    let num = ptrs.len();
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    alloc.reclaim(Reclaim::All);
+    let ptrs: Vec<_> = (0..num).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+}
+
+#[test]
+fn test_build_arena() {
+    use self::sysconf::page::pagesize;
+    use self::object_alloc::UntypedObjectAlloc;
+    use backing::arena::get_aligned;
+
+    let slab_size = 4 * pagesize();
+    let get_large = |layout: Layout| AllocObjectAlloc::new(Heap, layout);
+
+    // every slab carved out of the arenas is aligned to its size
+    let slab_layout = Layout::from_size_align(slab_size, slab_size).unwrap();
+    let mut arena = get_aligned(&get_large, slab_layout, pagesize());
+    let slabs: Vec<_> = (0..64)
+        .map(|_| unsafe { UntypedObjectAlloc::alloc(&mut arena).unwrap() })
+        .collect();
+    for &slab in &slabs {
+        assert_eq!(slab as usize % slab_size, 0);
+    }
+    for slab in slabs {
+        unsafe { UntypedObjectAlloc::dealloc(&mut arena, slab) };
+    }
+
+    let mut alloc: SlabAlloc<[u64; 16], _, _> = SlabAllocBuilder::default()
+        .slab_size(slab_size)
+        .build_arena(get_large);
+    match alloc.alloc {
+        PrivateSlabAlloc::Aligned(_) => {}
+        PrivateSlabAlloc::Large(_) => panic!("aligned slabs not used"),
+    }
+    let ptrs: Vec<_> = (0..4096)
+        .map(|_| unsafe { ObjectAlloc::alloc(&mut alloc).unwrap() })
+        .collect();
+    for ptr in ptrs {
+        unsafe { ObjectAlloc::dealloc(&mut alloc, ptr) };
+    }
+}
+
+#[test]
+fn test_stats() {
+    use self::object_alloc::AllocatorStats;
+
+    let mut alloc: SlabAlloc<[u64; 16], _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    assert_eq!(alloc.stats(), Default::default());
+
+    let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    let stats = alloc.stats();
+    assert_eq!(stats.live_objects, 1024);
+    assert!(stats.slabs > 1);
+    assert_eq!(stats.bytes_mapped % stats.slabs, 0);
+    assert_eq!(stats.bytes_committed, stats.bytes_mapped);
+    let per_slab = (stats.live_objects + stats.cached_objects) / stats.slabs;
+    assert_eq!(per_slab * stats.slabs, stats.live_objects + stats.cached_objects);
+    assert!(per_slab * 128 <= stats.bytes_mapped / stats.slabs);
+
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    let after = alloc.stats();
+    assert_eq!(after.live_objects, 0);
+    assert_eq!(after.cached_objects, per_slab * after.slabs);
+}
+
+#[test]
+fn test_reclaim() {
+    use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+    use self::object_alloc::{AllocatorStats, Reclaim};
+
+    static DROPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    #[derive(Default)]
+    struct Counted([u64; 16]);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let mut alloc: SlabAlloc<Counted, _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    assert_eq!(alloc.reclaim(Reclaim::All), 0);
+
+    let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    // Nothing can be reclaimed while every slab has live objects.
+    assert_eq!(alloc.reclaim(Reclaim::All), 0);
+    let live = alloc.stats();
+    let slab_size = live.bytes_mapped / live.slabs;
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+
+    let before = alloc.stats();
+    assert!(before.slabs > 2);
+    assert_eq!(alloc.reclaim(Reclaim::AtLeast(1)), slab_size);
+    assert_eq!(alloc.stats().slabs, before.slabs - 1);
+    assert_eq!(alloc.reclaim(Reclaim::KeepAtMost(slab_size)),
+               (before.slabs - 2) * slab_size);
+    assert_eq!(alloc.stats().slabs, 1);
+    assert_eq!(alloc.reclaim(Reclaim::All), slab_size);
+
+    let after = alloc.stats();
+    assert_eq!(after, Default::default());
+    // Every object that was initialized must have been dropped.
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1024);
+}
+
+#[test]
+fn test_slab_size() {
+    use self::object_alloc::AllocatorStats;
+    use self::sysconf::page::pagesize;
+
+    fn slab_size<T: Default>(builder: SlabAllocBuilder<T, DefaultInitSystem<T>>) -> (usize, bool) {
+        let mut alloc: SlabAlloc<T, _, LeakyBackingAlloc> =
+            builder.build_backing(leaky_get_aligned, leaky_get_large);
+        let is_aligned = match alloc.alloc {
+            PrivateSlabAlloc::Aligned(_) => true,
+            PrivateSlabAlloc::Large(_) => false,
+        };
+        let ptr = unsafe { alloc.alloc().unwrap() };
+        let stats = alloc.stats();
+        unsafe { alloc.dealloc(ptr) };
+        (stats.bytes_mapped / stats.slabs, is_aligned)
+    }
+
+    // LeakyBackingAlloc only supports page-sized aligned slabs, and the most space-efficient
+    // aligned slab size for these objects is larger than a page.
+    assert_eq!(slab_size(SlabAllocBuilder::<[u64; 16], _>::default()),
+               (pagesize(), false));
+    assert_eq!(slab_size(SlabAllocBuilder::<[u64; 16], _>::default().prefer_aligned_slabs()),
+               (pagesize(), true));
+    assert_eq!(slab_size(SlabAllocBuilder::<[u64; 16], _>::default().slab_size(pagesize())),
+               (pagesize(), true));
+    assert_eq!(slab_size(SlabAllocBuilder::<[u64; 16], _>::default().slab_size(3 * pagesize())),
+               (3 * pagesize(), false));
+}
+
+#[test]
+fn test_cache_line_align() {
+    let mut alloc: SlabAlloc<[u8; 24], _, LeakyBackingAlloc> = SlabAllocBuilder::default()
+        .cache_line_align()
+        .build_backing(leaky_get_aligned, leaky_get_large);
+    let mut ptrs: Vec<_> = (0..256).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    ptrs.sort();
+    for pair in ptrs.windows(2) {
+        assert_eq!(pair[0] as usize % 64, 0);
+        assert!(pair[1] as usize - pair[0] as usize >= 64);
+    }
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+}
+
+#[test]
+fn test_coloring() {
+    use std::collections::HashSet;
+    use self::sysconf::page::pagesize;
+
+    // Returns the number of distinct offsets within their slabs of the first object in each slab.
+    fn colors(coloring: bool) -> usize {
+        let mut alloc: SlabAlloc<[u64; 32], _, LeakyBackingAlloc> = SlabAllocBuilder::default()
+            .prefer_aligned_slabs()
+            .coloring(coloring)
+            .build_backing(leaky_get_aligned, leaky_get_large);
+        let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+        let offsets: HashSet<_> = ptrs.iter()
+            .filter(|&&ptr| alloc.object_index(ptr) == Some(0))
+            .map(|&ptr| ptr as usize % pagesize())
+            .collect();
+        for ptr in ptrs {
+            unsafe { alloc.dealloc(ptr) };
+        }
+        offsets.len()
+    }
+
+    assert_eq!(colors(false), 1);
+    if cfg!(not(feature = "no-coloring")) {
+        assert!(colors(true) > 1);
+    }
+}
+
+type DebugAlloc = SlabAlloc<[u64; 4], NopInitSystem, LeakyBackingAlloc>;
+
+fn debug_alloc() -> DebugAlloc {
+    unsafe {
+        SlabAllocBuilder::no_initialize()
+            .debug()
+            .build_backing(leaky_get_aligned, leaky_get_large)
+    }
+}
+
+#[test]
+fn test_debug() {
+    use self::object_alloc::Reclaim;
+    use stack::POISON_BYTE;
+
+    let mut alloc = debug_alloc();
+    for _ in 0..4 {
+        let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+        for &ptr in &ptrs {
+            // objects which the allocator doesn't initialize are poisoned while free
+            assert_eq!(unsafe { *ptr }, [0x0101_0101_0101_0101 * POISON_BYTE as u64; 4]);
+            unsafe { *ptr = [1, 2, 3, 4] };
+        }
+        for ptr in ptrs {
+            unsafe { alloc.dealloc(ptr) };
+        }
+        // tear down the slabs, which checks every object
+        alloc.reclaim(Reclaim::All);
+    }
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn test_debug_double_free() {
+    let mut alloc = debug_alloc();
+    unsafe {
+        let ptr = alloc.alloc().unwrap();
+        let _other = alloc.alloc().unwrap();
+        alloc.dealloc(ptr);
+        alloc.dealloc(ptr);
+    }
+}
+
+#[test]
+#[should_panic(expected = "redzone after object")]
+fn test_debug_overflow() {
+    let mut alloc = debug_alloc();
+    unsafe {
+        let ptr = alloc.alloc().unwrap();
+        *(ptr.offset(1) as *mut u8) = 0;
+        alloc.dealloc(ptr);
+    }
+}
+
+#[test]
+#[should_panic(expected = "modified while free")]
+fn test_debug_use_after_free() {
+    let mut alloc = debug_alloc();
+    unsafe {
+        let ptr = alloc.alloc().unwrap();
+        alloc.dealloc(ptr);
+        (*ptr)[0] = 0;
+        // the most recently freed object is allocated first
+        alloc.alloc().unwrap();
+    }
+}
+
+type PolicyAlloc<P> = SlabAlloc<[u64; 16], NopInitSystem, LeakyBackingAlloc, P>;
+
+/// Allocate enough objects to fill several slabs and then free them all, returning the number of
+/// slabs that were used and the size of each slab.
+fn alloc_and_free_slabs<P: ReclaimPolicy>(alloc: &mut PolicyAlloc<P>) -> (usize, usize) {
+    use self::object_alloc::AllocatorStats;
+
+    let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    let stats = alloc.stats();
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    (stats.slabs, stats.bytes_mapped / stats.slabs)
+}
+
+#[test]
+fn test_reclaim_policies() {
+    use self::object_alloc::AllocatorStats;
+    use reclaim::{ByteBudget, FreeImmediately, KeepAtMost, NeverFree};
+
+    fn build<P: ReclaimPolicy>(policy: P) -> PolicyAlloc<P> {
+        unsafe {
+            SlabAllocBuilder::no_initialize()
+                .reclaim_policy(policy)
+                .build_backing(leaky_get_aligned, leaky_get_large)
+        }
+    }
+
+    let mut alloc = build(NeverFree);
+    let (slabs, slab_size) = alloc_and_free_slabs(&mut alloc);
+    assert!(slabs > 3);
+    assert_eq!(alloc.stats().slabs, slabs);
+
+    let mut alloc = build(FreeImmediately);
+    alloc_and_free_slabs(&mut alloc);
+    assert_eq!(alloc.stats().slabs, 0);
+
+    let mut alloc = build(KeepAtMost(2));
+    alloc_and_free_slabs(&mut alloc);
+    assert_eq!(alloc.stats().slabs, 2);
+
+    let mut alloc = build(ByteBudget(3 * slab_size + 1));
+    alloc_and_free_slabs(&mut alloc);
+    assert_eq!(alloc.stats().slabs, 3);
+}
+
+#[test]
+fn test_working_set_policy() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use self::object_alloc::AllocatorStats;
+    use reclaim::{Clock, WorkingSetPolicy};
+
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    let time = Rc::new(Cell::new(0));
+    let mut alloc = unsafe {
+        SlabAllocBuilder::no_initialize()
+            .reclaim_policy(WorkingSetPolicy::with_clock(1000, ManualClock(time.clone())))
+            .build_backing(leaky_get_aligned, leaky_get_large)
+    };
+    let alloc_and_free = |alloc: &mut PolicyAlloc<_>| unsafe {
+        let ptr = alloc.alloc().unwrap();
+        alloc.dealloc(ptr);
+    };
+
+    let (slabs, _) = alloc_and_free_slabs(&mut alloc);
+    assert_eq!(alloc.stats().slabs, slabs);
+
+    // Every slab was in use at some point during the first period, so none are freed when it
+    // ends.
+    time.set(1000);
+    alloc_and_free(&mut alloc);
+    assert_eq!(alloc.stats().slabs, slabs);
+
+    // Only one slab is used during the second period, so all of the others are freed.
+    time.set(1999);
+    alloc_and_free(&mut alloc);
+    assert_eq!(alloc.stats().slabs, slabs);
+    time.set(2000);
+    alloc_and_free(&mut alloc);
+    assert_eq!(alloc.stats().slabs, 1);
+}
+
+/// Run a workload which leaves live objects scattered across slabs, returning the number of slabs
+/// still in use once it's done.
+///
+/// `NUM_OBJS` objects are allocated, and then random objects are freed and new ones allocated,
+/// with the number of live objects drifting towards `NUM_OBJS / 10`. Objects are freed in random
+/// order, so unless the allocator consolidates live objects onto as few slabs as possible, many
+/// slabs end up only sparsely occupied.
+fn fragmentation_slabs<B: BackingAlloc>(alloc: &mut SlabAlloc<[u64; 4], NopInitSystem, B>,
+                                        steps: usize)
+                                        -> usize {
+    use self::object_alloc::{AllocatorStats, Reclaim};
+    use self::rand::{Rng, SeedableRng, XorShiftRng};
+
+    const NUM_OBJS: usize = 10_000;
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let mut live: Vec<_> = (0..NUM_OBJS)
+        .map(|_| unsafe { alloc.alloc().unwrap() })
+        .collect();
+    for _ in 0..steps {
+        if rng.gen_range(0, NUM_OBJS / 5) < live.len() {
+            let idx = rng.gen_range(0, live.len());
+            unsafe { alloc.dealloc(live.swap_remove(idx)) };
+        } else {
+            live.push(unsafe { alloc.alloc().unwrap() });
+        }
+    }
+
+    alloc.reclaim(Reclaim::All);
+    let slabs = alloc.stats().slabs;
+    for ptr in live {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    slabs
+}
+
+#[test]
+fn test_partial_slab_buckets() {
+    let slabs = |buckets| {
+        let mut alloc = unsafe {
+            SlabAllocBuilder::no_initialize()
+                .partial_slab_buckets(buckets)
+                .build_backing(leaky_get_aligned, leaky_get_large)
+        };
+        fragmentation_slabs(&mut alloc, 20_000)
+    };
+
+    let unsorted = slabs(1);
+    let sorted = slabs(8);
+    assert!(sorted * 2 < unsorted,
+            "sorted: {} slabs, unsorted: {} slabs",
+            sorted,
+            unsorted);
+}
+
+#[test]
+fn test_untyped_drop() {
+    use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+    use self::object_alloc::{Reclaim, UntypedObjectAlloc};
+    use UntypedSlabAllocBuilder;
+
+    static INITIALIZED: AtomicUsize = ATOMIC_USIZE_INIT;
+    static DROPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+    const MAGIC: u64 = 0xDEADBEEF;
+
+    fn init(ptr: *mut u8) {
+        unsafe { *(ptr as *mut u64) = MAGIC };
+        INITIALIZED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn drop_obj(ptr: *mut u8) {
+        assert_eq!(unsafe { *(ptr as *mut u64) }, MAGIC);
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let mut alloc = UntypedSlabAllocBuilder::func_with_drop(layout, init, drop_obj)
+        .build_backing(leaky_get_aligned, leaky_get_large);
+
+    let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    assert_eq!(INITIALIZED.load(Ordering::SeqCst), 1024);
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    // Cached objects stay initialized until their slabs are freed.
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+    alloc.reclaim(Reclaim::All);
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1024);
+
+    let ptrs: Vec<_> = (0..1024).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    assert_eq!(INITIALIZED.load(Ordering::SeqCst), 2048);
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    drop(alloc);
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 2048);
+}
+
+fn test_owns<T: Default>() {
+    use std::collections::HashSet;
+
+    let new = || -> SlabAlloc<T, _, LeakyBackingAlloc> {
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large)
+    };
+    let mut alloc = new();
+    let mut other = new();
+
+    let mut local = T::default();
+    assert!(!alloc.owns(::std::ptr::null_mut()));
+    assert!(!alloc.owns(&mut local as *mut T));
+
+    let ptrs: Vec<_> = (0..256).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    let other_ptr = unsafe { other.alloc().unwrap() };
+    let mut slots = HashSet::new();
+    for &ptr in &ptrs {
+        assert!(alloc.owns(ptr));
+        assert!(!other.owns(ptr));
+        assert!(!alloc.owns((ptr as usize + 1) as *mut T));
+        let idx = alloc.object_index(ptr).unwrap();
+        // Objects in different slabs may share an index, but never in the same slab.
+        assert!(slots.insert((ptr as usize - idx * ::std::mem::size_of::<T>(), idx)));
+    }
+    assert!(!alloc.owns(other_ptr));
+    assert!(other.owns(other_ptr));
+
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    unsafe { other.dealloc(other_ptr) };
+}
+
+#[test]
+fn test_owns_aligned() {
+    test_owns::<[u64; 2]>();
+}
+
+#[test]
+fn test_owns_large() {
+    test_owns::<[u8; 3000]>();
+}
+
+fn test_walk<T: Default>() {
+    use std::collections::HashSet;
+    use self::object_alloc::AllocatorStats;
+
+    let mut alloc: SlabAlloc<T, _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    let ptrs: Vec<_> = (0..256).map(|_| unsafe { alloc.alloc().unwrap() }).collect();
+    // free some of the objects so that there are both partial and empty slabs
+    let mut live = HashSet::new();
+    for (i, ptr) in ptrs.into_iter().enumerate() {
+        if i % 3 == 0 && i < 128 {
+            unsafe { alloc.dealloc(ptr) };
+        } else {
+            live.insert(ptr);
+        }
+    }
+
+    let (mut slabs, mut allocated) = (0, 0);
+    alloc.for_each_slab(|slab| {
+        assert!(slab.allocated <= slab.objects);
+        slabs += 1;
+        allocated += slab.allocated;
+    });
+    assert_eq!(slabs, alloc.stats().slabs);
+    assert_eq!(allocated, live.len());
+
+    let mut seen = HashSet::new();
+    alloc.for_each_allocated(|ptr, slab| {
+        assert!(ptr as usize >= slab.addr as usize);
+        assert!((ptr as usize) < slab.addr as usize + slab.size);
+        assert!(seen.insert(ptr));
+    });
+    assert_eq!(seen, live);
+    assert_eq!(alloc.leak_report().live_objects(), live.len());
+
+    for ptr in live {
+        unsafe { alloc.dealloc(ptr) };
+    }
+    assert!(alloc.leak_report().is_empty());
+    alloc.for_each_allocated(|_, _| panic!("object allocated after all objects were freed"));
+}
+
+#[test]
+fn test_walk_aligned() {
+    test_walk::<[u64; 2]>();
+}
+
+#[test]
+fn test_walk_large() {
+    test_walk::<[u8; 3000]>();
+}
+
+#[test]
+fn test_leak_report() {
+    let mut alloc: SlabAlloc<[u64; 2], _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    let site = alloc_site!();
+    let (ptr, other) = unsafe { (alloc.alloc_at(site).unwrap(), alloc.alloc().unwrap()) };
+    assert_eq!(alloc.alloc_site(ptr), Some(site));
+    assert_eq!(alloc.alloc_site(other), None);
+
+    let report = format!("{}", alloc.leak_report());
+    assert!(report.starts_with("2 live objects"));
+    assert!(report.contains(&format!("{:?} allocated at {}", ptr, site)));
+    assert!(report.contains(&format!("{:?}", other)));
+
+    unsafe {
+        alloc.dealloc(ptr);
+        alloc.dealloc(other);
+    }
+    assert_eq!(alloc.alloc_site(ptr), None);
+    assert_eq!(format!("{}", alloc.leak_report()), "0 live objects");
+}
+
+#[test]
+#[should_panic(expected = "1 live objects")]
+fn test_drop_leak() {
+    let mut alloc: SlabAlloc<[u64; 2], _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    unsafe { alloc.alloc().unwrap() };
+}
+
+fn test_concurrent_memory_corruption<T: Copy + Send + 'static>() {
+    use std::sync::Mutex;
+    use self::object_alloc_test::corruption::{CorruptionTesterDefault, TestBuilder};
+    use ConcurrentSlabAlloc;
+
+    let alloc: ConcurrentSlabAlloc<CorruptionTesterDefault<T>, _, LeakyBackingAlloc> =
+        ConcurrentSlabAlloc::new(SlabAllocBuilder::default()
+                                     .build_backing(leaky_get_aligned, leaky_get_large));
+    // Each thread gets its own handle to the same allocator.
+    let alloc = Mutex::new(alloc);
+    TestBuilder::new(move || alloc.lock().unwrap().clone())
+        .threads(8)
+        .test_iters(50_000)
+        .test();
+}
+
+#[test]
+fn test_concurrent_memory_corruption_16_byte() {
+    test_concurrent_memory_corruption::<[u8; 16]>();
+}
+
+#[test]
+fn test_concurrent_memory_corruption_100_byte() {
+    test_concurrent_memory_corruption::<[u8; 100]>();
+}
+
+#[test]
+fn test_concurrent_memory_corruption_4096_byte() {
+    test_concurrent_memory_corruption::<[u8; 4096]>();
+}
+
+#[test]
+fn test_concurrent_cross_thread() {
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use self::object_alloc::Reclaim;
+    use ConcurrentSlabAlloc;
+
+    const N_THREADS: usize = 8;
+    const N_ITEMS: usize = 4096;
+
+    let mut alloc: ConcurrentSlabAlloc<usize, _, LeakyBackingAlloc> =
+        ConcurrentSlabAlloc::new(SlabAllocBuilder::default()
+                                     .build_backing(leaky_get_aligned, leaky_get_large));
+    // Allocate from many threads and free everything from the main thread so that magazines
+    // filled by one handle are handed to the others through the depot.
+    let (send, recv) = channel();
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|i| {
+                 let mut alloc = alloc.clone();
+                 let send = send.clone();
+                 thread::spawn(move || for j in 0..N_ITEMS {
+                                   unsafe {
+                                       let obj = alloc.alloc().unwrap();
+                                       *obj = i * N_ITEMS + j;
+                                       send.send(obj as usize).unwrap();
+                                   }
+                               })
+             })
+        .collect();
+    drop(send);
+
+    let mut seen = vec![false; N_THREADS * N_ITEMS];
+    for obj in recv {
+        let obj = obj as *mut usize;
+        unsafe {
+            assert!(!seen[*obj], "object {} seen twice", *obj);
+            seen[*obj] = true;
+            alloc.dealloc(obj);
+        }
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(seen.iter().all(|&b| b));
+
+    // Every object is now cached by this handle or by the depot, so reclaiming frees every slab.
+    assert!(alloc.reclaim(Reclaim::All) > 0);
+    assert_eq!(alloc.reclaim(Reclaim::All), 0);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "not allocated by this allocator")]
+fn test_concurrent_large_dealloc_wrong_alloc() {
+    use ConcurrentSlabAlloc;
+
+    // objects this large are allocated from large slabs
+    let mut a: ConcurrentSlabAlloc<[u8; 4096], _, LeakyBackingAlloc> =
+        ConcurrentSlabAlloc::new(SlabAllocBuilder::default()
+                                     .build_backing(leaky_get_aligned, leaky_get_large));
+    let mut b: ConcurrentSlabAlloc<[u8; 4096], _, LeakyBackingAlloc> =
+        ConcurrentSlabAlloc::new(SlabAllocBuilder::default()
+                                     .build_backing(leaky_get_aligned, leaky_get_large));
+    unsafe {
+        let obj = a.alloc().unwrap();
+        b.dealloc(obj);
+    }
+}
+
+#[test]
+fn test_object_pool() {
+    use ObjectPool;
+
+    let pool: ObjectPool<[u64; 16], _, LeakyBackingAlloc> =
+        ObjectPool::new(SlabAllocBuilder::default()
+                            .build_backing(leaky_get_aligned, leaky_get_large));
+    {
+        let mut objs = Vec::new();
+        for i in 0..1024 {
+            let mut obj = pool.alloc().unwrap();
+            assert_eq!(*obj, [0; 16]);
+            obj[0] = i;
+            objs.push(obj);
+        }
+        for (i, obj) in objs.iter().enumerate() {
+            assert_eq!(obj[0], i as u64);
+        }
+    }
+    // all of the boxes have been dropped, so this won't trip the refcnt check
+    drop(pool.into_inner());
+}
+
+slab_cache! {
+    static TEST_CACHE: [u64; 16] = SlabAllocBuilder::default().build();
+    pub static TEST_CACHE_PUB: [u64; 4] = SlabAllocBuilder::default().build();
+}
+
+#[test]
+fn test_slab_cache() {
+    use std::thread;
+    use CacheBox;
+
+    {
+        let mut objs = Vec::new();
+        for i in 0..1024 {
+            let mut obj = TEST_CACHE.alloc_box().unwrap();
+            assert_eq!(*obj, [0; 16]);
+            obj[0] = i;
+            objs.push(obj);
+        }
+        for (i, obj) in objs.iter().enumerate() {
+            assert_eq!(obj[0], i as u64);
+        }
+        // objects are cached in whatever state they're freed in, so restore the default state
+        for obj in &mut objs {
+            obj[0] = 0;
+        }
+    }
+
+    let ptr = CacheBox::into_raw(TEST_CACHE_PUB.alloc_box().unwrap());
+    unsafe {
+        TEST_CACHE_PUB.free(ptr);
+        let ptr = TEST_CACHE_PUB.alloc().unwrap();
+        drop(CacheBox::from_raw(ptr, &TEST_CACHE_PUB));
+    }
+
+    // Each thread has its own instance of the cache, which is dropped when the thread exits.
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+                 thread::spawn(|| {
+                                   let objs: Vec<_> = (0..1024)
+                                       .map(|_| TEST_CACHE.alloc_box().unwrap())
+                                       .collect();
+                                   assert!(objs.iter().all(|obj| **obj == [0; 16]));
+                               })
+             })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+struct TestNode {
+    child: Option<::CacheBox<TestNode>>,
+    // make nodes large enough that each one gets its own slab
+    _pad: [u8; 3072],
+}
+
+impl Default for TestNode {
+    fn default() -> TestNode {
+        TestNode {
+            child: None,
+            _pad: [0; 3072],
+        }
+    }
+}
+
+slab_cache! {
+    static TEST_NODES: TestNode = SlabAllocBuilder::default().objects_per_slab(1).build();
+}
+
+#[test]
+fn test_slab_cache_reentrant_free() {
+    use self::object_alloc::Reclaim;
+
+    {
+        let mut parent = TEST_NODES.alloc_box().unwrap();
+        parent.child = Some(TEST_NODES.alloc_box().unwrap());
+    }
+    // Reclaiming the parent's slab drops the parent, which frees its child to the cache while the
+    // cache is in use. The child is freed once reclaim is done with the cache, and its slab can be
+    // reclaimed afterwards.
+    assert!(TEST_NODES.reclaim(Reclaim::All) > 0);
+    assert!(TEST_NODES.reclaim(Reclaim::All) > 0);
+}
+
+#[test]
+fn test_object_rc() {
+    use std::cell::RefCell;
+    use self::object_alloc::boxed::{ObjectRc, RcObject};
+
+    let alloc: RefCell<SlabAlloc<RcObject<u64>, _, LeakyBackingAlloc>> =
+        RefCell::new(SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large));
+    let mut a = ObjectRc::new(&alloc).unwrap();
+    *ObjectRc::get_mut(&mut a).unwrap() = 5;
+    let b = a.clone();
+    assert_eq!(ObjectRc::count(&a), 2);
+    assert!(ObjectRc::ptr_eq(&a, &b));
+    assert!(ObjectRc::get_mut(&mut a).is_none());
+    drop(a);
+    assert_eq!(ObjectRc::count(&b), 1);
+    assert_eq!(*b, 5);
+    drop(b);
+    drop(alloc.into_inner());
+}
+
+#[test]
+fn test_object_arena() {
+    use self::object_alloc::arena::{ArenaObject, ObjectArena};
+
+    let alloc: SlabAlloc<ArenaObject<u64>, _, LeakyBackingAlloc> =
+        SlabAllocBuilder::default().build_backing(leaky_get_aligned, leaky_get_large);
+    let mut arena = ObjectArena::new(alloc);
+    for _ in 0..4 {
+        {
+            let objs: Vec<&mut u64> = (0..1024).map(|_| arena.alloc().unwrap()).collect();
+            for (i, obj) in objs.into_iter().enumerate() {
+                *obj = i as u64;
+            }
+        }
+        arena.clear();
+    }
+    arena.alloc().unwrap();
+    // into_inner frees all remaining objects, so this won't trip the refcnt check
+    drop(arena.into_inner());
+}
+
+#[test]
+fn test_size_class_alloc() {
+    use std::collections::HashSet;
+    use std::ptr::write_bytes;
+    use init::NopInitSystem;
+    use self::alloc::allocator::AllocErr;
+    use self::object_alloc::adapter::SizeClassAlloc;
+    use {UntypedSlabAlloc, UntypedSlabAllocBuilder};
+
+    fn new(size: usize) -> UntypedSlabAlloc<NopInitSystem, LeakyBackingAlloc> {
+        UntypedSlabAllocBuilder::new(Layout::from_size_align(size, 8).unwrap())
+            .build_backing(leaky_get_aligned, leaky_get_large)
+    }
+
+    let mut alloc = SizeClassAlloc::new([new(16), new(32), new(64), new(128), new(256)]);
+    let mut ptrs = Vec::new();
+    let mut set = HashSet::new();
+    for size in 1..257 {
+        for &align in &[1, 2, 4, 8] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let (min, max) = alloc.usable_size(&layout);
+            assert_eq!(min, size);
+            assert_eq!(max, ::std::cmp::max(size.next_power_of_two(), 16));
+            let ptr = unsafe { alloc.alloc(layout.clone()).unwrap() };
+            assert_eq!(ptr as usize % align, 0);
+            assert!(set.insert(ptr as usize), "duplicate pointer: {:?}", ptr);
+            unsafe { write_bytes(ptr, 0xFF, size) };
+            ptrs.push((ptr, layout));
+        }
+    }
+
+    match unsafe { alloc.alloc(Layout::from_size_align(257, 8).unwrap()) } {
+        Err(AllocErr::Unsupported { .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match unsafe { alloc.alloc(Layout::from_size_align(16, 16).unwrap()) } {
+        Err(AllocErr::Unsupported { .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    for (ptr, layout) in ptrs {
+        unsafe { alloc.dealloc(ptr, layout) };
+    }
+}
+
 #[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
 fn bench_alloc_no_free<T: Default>(b: &mut Bencher) {
     let mut alloc = SlabAllocBuilder::default().build();
@@ -206,6 +1167,33 @@ fn bench_alloc_free_pairs_heap<T: Default>(b: &mut Bencher) {
            });
 }
 
+#[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+fn bench_fragmentation(b: &mut Bencher, buckets: usize) {
+    // Measures the cost of the bookkeeping required to sort partially-full slabs.
+    let mut alloc = unsafe {
+        SlabAllocBuilder::no_initialize()
+            .partial_slab_buckets(buckets)
+            .build_backing(leaky_get_aligned, leaky_get_large)
+    };
+    b.iter(|| black_box(fragmentation_slabs(&mut alloc, 10_000)));
+}
+
+#[bench]
+#[cfg(feature = "build-ignored-tests")]
+#[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+#[ignore]
+fn bench_fragmentation_1_bucket(b: &mut Bencher) {
+    bench_fragmentation(b, 1);
+}
+
+#[bench]
+#[cfg(feature = "build-ignored-tests")]
+#[cfg_attr(not(feature = "build-ignored-tests"), allow(unused))]
+#[ignore]
+fn bench_fragmentation_8_buckets(b: &mut Bencher) {
+    bench_fragmentation(b, 8);
+}
+
 macro_rules! make_bench_alloc_no_free {
     ($name:ident, $typ:ty) => (
         #[bench]
@@ -109,6 +109,16 @@ pub mod stack {
             self.size
         }
 
+        /// Returns the element at index `idx`, counting from the bottom of the stack.
+        ///
+        /// `idx` must be less than the size of the stack.
+        pub fn get(&self, data: *mut T, idx: usize) -> T
+            where T: Copy
+        {
+            debug_assert!(idx < self.size);
+            Self::get_at_idx(data, idx as isize)
+        }
+
         #[cfg_attr(feature = "cargo-clippy", allow(inline_always))]
         #[inline(always)]
         fn get_at_idx(data: *mut T, idx: isize) -> T {
@@ -219,14 +229,14 @@ pub mod color {
 
 pub mod ptrmap {
     #[cfg(not(feature = "use-stdlib-hashmap"))]
-    pub use self::optimized::Map;
+    pub use self::optimized::{ConcurrentMap, Map};
 
     #[cfg(feature = "use-stdlib-hashmap")]
-    pub use self::stdlib::Map;
+    pub use self::stdlib::{ConcurrentMap, Map};
 
     #[cfg(not(feature = "use-stdlib-hashmap"))]
     mod optimized {
-        use ptr_map::PtrHashMap;
+        use ptr_map::{ConcurrentPtrHashMap, PtrHashMap};
 
         pub struct Map<K, V> {
             pub map: PtrHashMap<K, V>,
@@ -241,6 +251,10 @@ pub mod ptrmap {
                 self.map.get(k)
             }
 
+            pub fn try_get(&self, k: *mut K) -> Option<*mut V> {
+                self.map.try_get(k)
+            }
+
             pub fn insert(&mut self, k: *mut K, v: *mut V) {
                 self.map.insert(k, v);
             }
@@ -249,11 +263,38 @@ pub mod ptrmap {
                 self.map.delete(k);
             }
         }
+
+        pub struct ConcurrentMap<K, V> {
+            pub map: ConcurrentPtrHashMap<K, V>,
+        }
+
+        impl<K, V> ConcurrentMap<K, V> {
+            pub fn new(size_hint: usize, align: usize) -> ConcurrentMap<K, V> {
+                ConcurrentMap { map: ConcurrentPtrHashMap::new(size_hint, align) }
+            }
+
+            pub fn get(&self, k: *mut K) -> *mut V {
+                self.map.get(k)
+            }
+
+            pub fn try_get(&self, k: *mut K) -> Option<*mut V> {
+                self.map.try_get(k)
+            }
+
+            pub fn insert(&self, k: *mut K, v: *mut V) {
+                self.map.insert(k, v);
+            }
+
+            pub fn delete(&self, k: *mut K) {
+                self.map.delete(k);
+            }
+        }
     }
 
     #[cfg(feature = "use-stdlib-hashmap")]
     mod stdlib {
         use std::collections::HashMap;
+        use std::sync::Mutex;
 
         pub struct Map<K, V> {
             map: HashMap<*mut K, *mut V>,
@@ -268,6 +309,10 @@ pub mod ptrmap {
                 *self.map.get(&k).unwrap()
             }
 
+            pub fn try_get(&self, k: *mut K) -> Option<*mut V> {
+                self.map.get(&k).cloned()
+            }
+
             pub fn insert(&mut self, k: *mut K, v: *mut V) {
                 self.map.insert(k, v);
             }
@@ -276,6 +321,37 @@ pub mod ptrmap {
                 self.map.remove(&k);
             }
         }
+
+        pub struct ConcurrentMap<K, V> {
+            map: Mutex<HashMap<*mut K, *mut V>>,
+        }
+
+        // The keys and values are never dereferenced, so it doesn't matter which thread they came
+        // from.
+        unsafe impl<K, V> Send for ConcurrentMap<K, V> {}
+        unsafe impl<K, V> Sync for ConcurrentMap<K, V> {}
+
+        impl<K, V> ConcurrentMap<K, V> {
+            pub fn new(size_hint: usize, _: usize) -> ConcurrentMap<K, V> {
+                ConcurrentMap { map: Mutex::new(HashMap::with_capacity(size_hint)) }
+            }
+
+            pub fn get(&self, k: *mut K) -> *mut V {
+                *self.map.lock().unwrap().get(&k).unwrap()
+            }
+
+            pub fn try_get(&self, k: *mut K) -> Option<*mut V> {
+                self.map.lock().unwrap().get(&k).cloned()
+            }
+
+            pub fn insert(&self, k: *mut K, v: *mut V) {
+                self.map.lock().unwrap().insert(k, v);
+            }
+
+            pub fn delete(&self, k: *mut K) {
+                self.map.lock().unwrap().remove(&k);
+            }
+        }
     }
 }
 
@@ -408,6 +484,30 @@ pub mod list {
             }
         }
 
+        /// Removes `t` from the list. `t` must currently be in the list.
+        pub fn remove(&mut self, t: *mut T) {
+            debug_assert!(self.size > 0);
+            unsafe {
+                let prev = (*t).prev();
+                let next = (*t).next();
+                if prev.is_null() {
+                    debug_assert_eq!(self.head, t);
+                    self.head = next;
+                } else {
+                    (*prev).set_next(next);
+                }
+                if next.is_null() {
+                    debug_assert_eq!(self.tail, t);
+                    self.tail = prev;
+                } else {
+                    (*next).set_prev(prev);
+                }
+                (*t).set_prev(ptr::null_mut());
+                (*t).set_next(ptr::null_mut());
+            }
+            self.size -= 1;
+        }
+
         pub fn peek_front(&self) -> *mut T {
             debug_assert!(self.size > 0);
             self.head
@@ -416,22 +516,115 @@ pub mod list {
         pub fn size(&self) -> usize {
             self.size
         }
+
+        /// Calls `f` on each element of the list, from front to back.
+        pub fn for_each<F: FnMut(*mut T)>(&self, mut f: F) {
+            let mut t = self.head;
+            for _ in 0..self.size {
+                let next = unsafe { (*t).next() };
+                f(t);
+                t = next;
+            }
+        }
+    }
+
+    /// The maximum number of buckets in a `BucketList`.
+    pub const MAX_BUCKETS: usize = 8;
+
+    /// A list of elements sorted into a small number of buckets.
+    ///
+    /// A `BucketList` is an array of `LinkedList`s along with a bitmask recording which of them
+    /// are non-empty, making it cheap to find an element in the lowest-numbered non-empty bucket.
+    /// The meaning of buckets is up to the caller, who is responsible for remembering which
+    /// bucket each element is in.
+    pub struct BucketList<T: Linkable> {
+        buckets: [LinkedList<T>; MAX_BUCKETS],
+        num_buckets: usize,
+        nonempty: u8, // bit i is set if buckets[i] is non-empty
+        size: usize,
+    }
+
+    impl<T: Linkable> BucketList<T> {
+        pub fn new(num_buckets: usize) -> BucketList<T> {
+            assert!(num_buckets > 0 && num_buckets <= MAX_BUCKETS);
+            BucketList {
+                buckets: [LinkedList::new(),
+                          LinkedList::new(),
+                          LinkedList::new(),
+                          LinkedList::new(),
+                          LinkedList::new(),
+                          LinkedList::new(),
+                          LinkedList::new(),
+                          LinkedList::new()],
+                num_buckets: num_buckets,
+                nonempty: 0,
+                size: 0,
+            }
+        }
+
+        pub fn num_buckets(&self) -> usize {
+            self.num_buckets
+        }
+
+        pub fn insert(&mut self, t: *mut T, bucket: usize) {
+            debug_assert!(bucket < self.num_buckets);
+            self.buckets[bucket].insert_front(t);
+            self.nonempty |= 1 << bucket;
+            self.size += 1;
+        }
+
+        pub fn remove(&mut self, t: *mut T, bucket: usize) {
+            debug_assert!(bucket < self.num_buckets);
+            self.buckets[bucket].remove(t);
+            if self.buckets[bucket].size() == 0 {
+                self.nonempty &= !(1 << bucket);
+            }
+            self.size -= 1;
+        }
+
+        /// Moves `t` from bucket `from` to bucket `to`.
+        pub fn move_to(&mut self, t: *mut T, from: usize, to: usize) {
+            if from != to {
+                self.remove(t, from);
+                self.insert(t, to);
+            }
+        }
+
+        /// Returns an element of the lowest-numbered non-empty bucket along with that bucket.
+        pub fn peek_lowest(&self) -> (*mut T, usize) {
+            debug_assert!(self.size > 0);
+            let bucket = self.nonempty.trailing_zeros() as usize;
+            (self.buckets[bucket].peek_front(), bucket)
+        }
+
+        pub fn size(&self) -> usize {
+            self.size
+        }
+
+        /// Calls `f` on each element, from the lowest-numbered bucket to the highest.
+        pub fn for_each<F: FnMut(*mut T)>(&self, mut f: F) {
+            for bucket in &self.buckets[..self.num_buckets] {
+                bucket.for_each(&mut f);
+            }
+        }
     }
 }
 
 pub mod workingset {
-    use std::time::Instant;
-
+    /// A value tracked over a working period.
+    ///
+    /// Times are given in milliseconds relative to an arbitrary fixed point (see
+    /// `reclaim::Clock`).
     pub struct WorkingSet<T: Copy> {
         data: T,
-        period_begin: Instant,
+        period_begin: u64,
     }
 
     impl<T: Copy> WorkingSet<T> {
-        pub fn new(init: T) -> WorkingSet<T> {
+        pub fn new(init: T, now: u64) -> WorkingSet<T> {
             WorkingSet {
                 data: init,
-                period_begin: Instant::now(),
+                period_begin: now,
             }
         }
 
@@ -439,22 +632,14 @@ pub mod workingset {
             self.data = new;
         }
 
-        /// Refreshes the working set.
-        ///
-        /// If at least `secs` seconds have elapsed since the beginning of the period, the period
-        /// is reset and the current value is returned. Otherwise, `refresh` returns `None` and is
-        /// a no-op. Note that unless `set` is called after `refresh`, the stored `T` value will be
-        /// the same in the new period.
-        pub fn refresh(&mut self, secs: u64) -> Option<T> {
-            self.refresh_now(secs, Instant::now())
-        }
-
         /// Refreshes the working set given the current time.
         ///
-        /// `refresh_now` is like `refresh`, but it takes the current time as an argument instead
-        /// of looking it up.
-        pub fn refresh_now(&mut self, secs: u64, now: Instant) -> Option<T> {
-            if now.duration_since(self.period_begin).as_secs() >= secs {
+        /// If at least `period` milliseconds have elapsed since the beginning of the period, the
+        /// period is reset and the current value is returned. Otherwise, `refresh_now` returns
+        /// `None` and is a no-op. Note that unless `set` is called after `refresh_now`, the stored
+        /// `T` value will be the same in the new period.
+        pub fn refresh_now(&mut self, period: u64, now: u64) -> Option<T> {
+            if now.saturating_sub(self.period_begin) >= period {
                 self.period_begin = now;
                 Some(self.data)
             } else {
@@ -0,0 +1,140 @@
+// Copyright 2017 the authors. See the 'Copyright and license' section of the
+// README.md file at the top-level directory of this repository.
+//
+// Licensed under the Apache License, Version 2.0 (the LICENSE file). This file
+// may not be copied, modified, or distributed except according to those terms.
+
+//! Inspection of the contents of a slab allocator.
+//!
+//! This module provides the types used by the heap-walking methods of `SlabAlloc` and
+//! `UntypedSlabAlloc` (`for_each_slab` and `for_each_allocated`), and by leak reports, which
+//! describe every object which is still allocated. A leak report is included in the panic message
+//! when an allocator is dropped while objects are still allocated, and can be obtained at any time
+//! with `leak_report`.
+//!
+//! Objects allocated with `alloc_at` rather than `alloc` record the site at which they were
+//! allocated (see the `alloc_site!` macro), which is included in leak reports.
+
+use core::{fmt, ptr};
+
+/// A description of a slab.
+#[derive(Copy, Clone, Debug)]
+pub struct SlabInfo {
+    /// The address of the beginning of the slab.
+    pub addr: *mut u8,
+    /// The size of the slab in bytes.
+    pub size: usize,
+    /// The number of objects in the slab.
+    pub objects: usize,
+    /// The number of the slab's objects which are currently allocated.
+    pub allocated: usize,
+}
+
+/// A location in the source code at which an object was allocated.
+///
+/// `AllocSite`s are normally constructed with the `alloc_site!` macro.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AllocSite {
+    pub file: &'static str,
+    pub line: u32,
+}
+
+impl fmt::Display for AllocSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Evaluates to a `&'static AllocSite` describing the location at which it is invoked.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let ptr = unsafe { alloc.alloc_at(alloc_site!()).unwrap() };
+/// ```
+#[macro_export]
+macro_rules! alloc_site {
+    () => {{
+        static SITE: $crate::AllocSite = $crate::AllocSite {
+            file: file!(),
+            line: line!(),
+        };
+        &SITE
+    }};
+}
+
+/// A walker over the slabs and objects of a single allocator.
+pub trait HeapWalk {
+    /// Calls `f` on each of the allocator's slabs.
+    fn walk_slabs(&self, f: &mut FnMut(&SlabInfo));
+    /// Calls `f` on each allocated object along with the slab containing it.
+    ///
+    /// Objects in the same slab are visited consecutively.
+    fn walk_allocated(&self, f: &mut FnMut(*mut u8, &SlabInfo));
+    /// Returns the site at which `obj` was allocated, if it was allocated with `alloc_at`.
+    fn alloc_site(&self, obj: *mut u8) -> Option<&'static AllocSite>;
+    /// Returns the number of allocated objects.
+    fn live_objects(&self) -> usize;
+}
+
+/// A report of the objects allocated from an allocator.
+///
+/// A `LeakReport` is obtained from the `leak_report` method of `SlabAlloc` or `UntypedSlabAlloc`.
+/// Its `Display` implementation lists every allocated object, grouped by slab, along with each
+/// slab's occupancy and the site at which each object was allocated (if known). The allocator is
+/// walked each time the report is formatted.
+pub struct LeakReport<'a> {
+    alloc: &'a HeapWalk,
+}
+
+impl<'a> LeakReport<'a> {
+    pub fn new(alloc: &'a HeapWalk) -> LeakReport<'a> {
+        LeakReport { alloc: alloc }
+    }
+
+    /// Returns the number of allocated objects.
+    pub fn live_objects(&self) -> usize {
+        self.alloc.live_objects()
+    }
+
+    /// Returns true if no objects are allocated.
+    pub fn is_empty(&self) -> bool {
+        self.live_objects() == 0
+    }
+}
+
+impl<'a> fmt::Display for LeakReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} live objects", self.live_objects())?;
+        let mut res = Ok(());
+        let mut cur_slab = ptr::null_mut();
+        self.alloc
+            .walk_allocated(&mut |obj, slab| {
+                if res.is_err() {
+                    return;
+                }
+                if slab.addr != cur_slab {
+                    cur_slab = slab.addr;
+                    res = write!(f,
+                                 "\nslab {:?}: {} of {} objects allocated",
+                                 slab.addr,
+                                 slab.allocated,
+                                 slab.objects);
+                    if res.is_err() {
+                        return;
+                    }
+                }
+                res = match self.alloc.alloc_site(obj) {
+                    Some(site) => write!(f, "\n    {:?} allocated at {}", obj, site),
+                    None => write!(f, "\n    {:?}", obj),
+                };
+            });
+        res
+    }
+}
+
+impl<'a> fmt::Debug for LeakReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}